@@ -0,0 +1,53 @@
+//! Connection State
+//!
+//! What is kept of a client connection between requests, while it waits on the event loop
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::connection::stream::Stream;
+
+/// Number of connections currently open
+static OPEN_CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Client connection and the bytes received from it that were not parsed yet
+pub struct Connection {
+    pub stream: Stream,
+    /// Bytes received and not yet parsed, the beginning of the next request
+    pub pending: Vec<u8>,
+    /// Number of requests already served
+    pub served: usize,
+}
+
+impl Connection {
+    pub fn new(stream: Stream) -> Connection {
+        OPEN_CONNECTIONS.fetch_add(1, Ordering::SeqCst);
+        Connection {
+            stream,
+            pending: Vec::new(),
+            served: 0,
+        }
+    }
+
+    /// Number of connections currently open
+    pub fn count() -> usize {
+        OPEN_CONNECTIONS.load(Ordering::SeqCst)
+    }
+
+    /// Checks if the pending bytes contain the whole head of a request
+    pub fn has_complete_head(&self) -> bool {
+        let pending = &self.pending[..];
+        let start = pending.iter().position(|byte| *byte != b'\r' && *byte != b'\n');
+        match start {
+            Some(start) => {
+                let head = &pending[start..];
+                head.windows(4).any(|window| window == b"\r\n\r\n") || head.windows(2).any(|window| window == b"\n\n")
+            }
+            None => false,
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        OPEN_CONNECTIONS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
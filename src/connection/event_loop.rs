@@ -0,0 +1,369 @@
+//! Event Loop
+//!
+//! Waits on a single thread for new connections and for the requests of the open ones, a
+//! connection only takes a worker of the pool once it has a request ready to be served, so idle
+//! keep-alive connections and clients sending their headers slowly do not block the server.
+//! TLS handshakes also happen here
+use std::collections::{BTreeSet, HashMap};
+use std::io::{ErrorKind, Result};
+use std::os::unix::io::AsRawFd;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use mio::unix::SourceFd;
+use mio::{Events, Interest, Poll, Token, Waker};
+use rustls::ServerConfig;
+use threadpool::ThreadPool;
+
+use crate::connection::connection::Connection;
+use crate::connection::listener::Listener;
+use crate::connection::shutdown::*;
+use crate::request::reader::MAX_HEAD_BYTES;
+use crate::utils::log::*;
+use crate::APP_CONFIG;
+
+/// Token used to wake up the loop when a worker returns a connection
+const WAKER: Token = Token(usize::MAX);
+/// Size of each read from a waiting connection
+const READ_SIZE: usize = 4096;
+/// Maximum number of events handled on each poll
+const EVENTS_CAPACITY: usize = 1024;
+
+/// Serves the requests of a connection, returns it when it has to wait for the next request
+pub type Handler = fn(Connection) -> Option<Connection>;
+
+/// Connection waiting for its next request
+struct Waiting {
+    connection: Connection,
+    /// When the connection will be closed if the request has not arrived
+    deadline: Instant,
+}
+
+/// Accepts connections and keeps them until they have a request to serve
+pub struct EventLoop {
+    poll: Poll,
+    /// Listeners with the TLS config of their connections, their token is their index
+    listeners: Vec<(Listener, Option<Arc<ServerConfig>>)>,
+    /// Number of listeners registered, the listeners are closed when the server stops
+    listener_count: usize,
+    waiting: HashMap<Token, Waiting>,
+    /// Deadlines of the waiting connections, the first one to expire first
+    deadlines: BTreeSet<(Instant, Token)>,
+    next_token: usize,
+    pool: ThreadPool,
+    handler: Handler,
+    /// Connections given back by the workers
+    returned: Receiver<Connection>,
+    sender: Sender<Connection>,
+    waker: Arc<Waker>,
+    /// When the server stops waiting for the active connections, set once it is stopping
+    stop_deadline: Option<Instant>,
+}
+
+impl EventLoop {
+    pub fn new(
+        listeners: Vec<(Listener, Option<Arc<ServerConfig>>)>,
+        pool: ThreadPool,
+        handler: Handler,
+    ) -> Result<EventLoop> {
+        let poll = Poll::new()?;
+        for (index, (listener, _tls_config)) in listeners.iter().enumerate() {
+            listener.set_nonblocking(true)?;
+            poll.registry()
+                .register(&mut SourceFd(&listener.as_raw_fd()), Token(index), Interest::READABLE)?;
+        }
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+        handle_signals(waker.clone())?;
+        let (sender, returned) = channel();
+        Ok(EventLoop {
+            poll,
+            next_token: listeners.len(),
+            listener_count: listeners.len(),
+            listeners,
+            waiting: HashMap::new(),
+            deadlines: BTreeSet::new(),
+            pool,
+            handler,
+            returned,
+            sender,
+            waker,
+            stop_deadline: None,
+        })
+    }
+
+    /// Runs the loop until the server is stopped and the active connections end
+    pub fn run(&mut self) -> Result<()> {
+        let mut events = Events::with_capacity(EVENTS_CAPACITY);
+        loop {
+            if take_restart_request() {
+                self.restart();
+            }
+            if is_stopping() && self.stop_deadline.is_none() {
+                self.stop();
+            }
+            if let Some(deadline) = self.stop_deadline {
+                if Connection::count() == 0 {
+                    return Ok(());
+                }
+                if Instant::now() >= deadline {
+                    log_warning(&format!("Closing {} active connections", Connection::count()));
+                    return Ok(());
+                }
+            }
+
+            let timeout = self
+                .deadlines
+                .iter()
+                .next()
+                .map(|(deadline, _token)| *deadline)
+                .into_iter()
+                .chain(self.stop_deadline)
+                .min()
+                .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            if let Err(error) = self.poll.poll(&mut events, timeout) {
+                if error.kind() == ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(error);
+            }
+
+            for event in events.iter() {
+                let token = event.token();
+                if token == WAKER {
+                    continue;
+                }
+                if token.0 < self.listener_count {
+                    if !self.listeners.is_empty() {
+                        self.accept(token.0);
+                    }
+                } else {
+                    self.receive(token);
+                }
+            }
+
+            let keep_alive = APP_CONFIG.get().timeout.get_keep_alive_duration();
+            while let Ok(connection) = self.returned.try_recv() {
+                if self.stop_deadline.is_none() {
+                    self.wait(connection, keep_alive);
+                }
+            }
+            self.close_expired();
+        }
+    }
+
+    /// Accepts every connection waiting on a listener
+    fn accept(&mut self, index: usize) {
+        loop {
+            let (listener, tls_config) = &self.listeners[index];
+            match listener.accept(tls_config.as_ref()) {
+                Ok(stream) => {
+                    if Connection::count() >= APP_CONFIG.get().server.max_connections {
+                        log_warning(&"Connection limit reached, closing new connection");
+                        continue;
+                    }
+                    let connection = Connection::new(stream);
+                    self.wait(connection, APP_CONFIG.get().timeout.get_request_duration());
+                }
+                Err(ref error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(ref error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(error) => {
+                    // Like running out of file descriptors, the next event will try again
+                    log_error(&error);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Registers a connection until its next request arrives or the timeout expires
+    fn wait(&mut self, connection: Connection, timeout: Duration) {
+        if connection.has_complete_head() || connection.stream.has_buffered_data() {
+            self.dispatch(connection);
+            return;
+        }
+        if let Err(error) = connection.stream.set_nonblocking(true) {
+            log_error(&error);
+            return;
+        }
+
+        let token = Token(self.next_token);
+        self.next_token += 1;
+        if self.next_token == WAKER.0 {
+            self.next_token = self.listener_count;
+        }
+        let registered = self.poll.registry().register(
+            &mut SourceFd(&connection.stream.as_raw_fd()),
+            token,
+            get_interest(&connection),
+        );
+        match registered {
+            Ok(_) => {
+                let deadline = Instant::now() + timeout;
+                self.deadlines.insert((deadline, token));
+                self.waiting.insert(token, Waiting { connection, deadline });
+            }
+            Err(error) => log_error(&error),
+        }
+    }
+
+    /// Reads what a waiting connection sent, sending it to the pool once the head of the
+    /// request is complete
+    fn receive(&mut self, token: Token) {
+        let waiting = match self.waiting.get_mut(&token) {
+            Some(value) => value,
+            None => return,
+        };
+
+        let started = !waiting.connection.pending.is_empty();
+        let mut closed = false;
+        let mut chunk = [0u8; READ_SIZE];
+        loop {
+            match waiting.connection.stream.read_available(&mut chunk) {
+                Ok(0) => {
+                    closed = true;
+                    break;
+                }
+                Ok(read) => {
+                    waiting.connection.pending.extend_from_slice(&chunk[..read]);
+                    if waiting.connection.pending.len() >= MAX_HEAD_BYTES {
+                        // Let the parser answer the oversized head
+                        break;
+                    }
+                }
+                Err(ref error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(ref error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(_error) => {
+                    closed = true;
+                    break;
+                }
+            }
+        }
+        let connection = &waiting.connection;
+        let ready = connection.has_complete_head() || connection.pending.len() >= MAX_HEAD_BYTES;
+
+        if closed || ready {
+            if let Some(waiting) = self.remove(token) {
+                if !closed {
+                    self.dispatch(waiting.connection);
+                }
+            }
+            return;
+        }
+
+        if !started && !connection.pending.is_empty() {
+            // The client started a request, it has the request timeout to end it
+            let deadline = Instant::now() + APP_CONFIG.get().timeout.get_request_duration();
+            self.deadlines.remove(&(waiting.deadline, token));
+            self.deadlines.insert((deadline, token));
+            waiting.deadline = deadline;
+        }
+        // Handshake records that did not fit on the socket are sent once it is writable
+        let registered = self.poll.registry().reregister(
+            &mut SourceFd(&waiting.connection.stream.as_raw_fd()),
+            token,
+            get_interest(&waiting.connection),
+        );
+        if let Err(error) = registered {
+            log_error(&error);
+        }
+    }
+
+    /// Sends a connection with a request to the pool, it comes back through the channel if it
+    /// stays open
+    fn dispatch(&self, connection: Connection) {
+        if let Err(error) = connection.stream.set_nonblocking(false) {
+            log_error(&error);
+            return;
+        }
+        // Blocking reads and writes of the worker wait for the client at most the request timeout
+        let timeout = Some(APP_CONFIG.get().timeout.get_request_duration());
+        connection.stream.set_read_timeout(timeout).ok();
+        connection.stream.set_write_timeout(timeout).ok();
+        let handler = self.handler;
+        let sender = self.sender.clone();
+        let waker = self.waker.clone();
+        self.pool.execute(move || {
+            if let Some(connection) = handler(connection) {
+                if sender.send(connection).is_ok() {
+                    waker.wake().ok();
+                }
+            } else if is_stopping() {
+                // The loop may be waiting for this connection to end
+                waker.wake().ok();
+            }
+        });
+    }
+
+    /// Starts a new process with the same listeners, this one stops once it is ready
+    fn restart(&self) {
+        let listeners: Vec<&Listener> = self.listeners.iter().map(|(listener, _tls_config)| listener).collect();
+        match restart(&listeners) {
+            Ok(_) => log_warning(&"Restarting, a new process was started with the listeners"),
+            Err(error) => log_error(&error),
+        }
+    }
+
+    /// Closes the listeners and the idle connections, the ones being served are closed after
+    /// their current request
+    fn stop(&mut self) {
+        log_warning(&"Stopping, waiting for the active connections to end");
+        self.stop_deadline = Some(Instant::now() + APP_CONFIG.get().timeout.get_shutdown_duration());
+        for (listener, _tls_config) in self.listeners.drain(..) {
+            self.poll.registry().deregister(&mut SourceFd(&listener.as_raw_fd())).ok();
+        }
+
+        // New connections and clients that already started sending a request still get a
+        // response
+        let idle: Vec<Token> = self
+            .waiting
+            .iter()
+            .filter(|(_token, waiting)| waiting.connection.served > 0 && waiting.connection.pending.is_empty())
+            .map(|(token, _waiting)| *token)
+            .collect();
+        for token in idle {
+            if let Some(waiting) = self.remove(token) {
+                waiting.connection.stream.shutdown().ok();
+            }
+        }
+    }
+
+    /// Stops waiting on a connection
+    fn remove(&mut self, token: Token) -> Option<Waiting> {
+        let waiting = self.waiting.remove(&token)?;
+        self.deadlines.remove(&(waiting.deadline, token));
+        self.poll
+            .registry()
+            .deregister(&mut SourceFd(&waiting.connection.stream.as_raw_fd()))
+            .ok();
+        Some(waiting)
+    }
+
+    /// Closes the connections whose request did not arrive on time
+    fn close_expired(&mut self) {
+        let now = Instant::now();
+        while let Some((deadline, token)) = self.deadlines.iter().next().copied() {
+            if deadline > now {
+                break;
+            }
+            match self.remove(token) {
+                Some(waiting) => {
+                    waiting.connection.stream.shutdown().ok();
+                }
+                None => {
+                    self.deadlines.remove(&(deadline, token));
+                }
+            }
+        }
+    }
+}
+
+/// Events a waiting connection is registered for, TLS sessions also wait until they can send
+/// the records left
+fn get_interest(connection: &Connection) -> Interest {
+    if connection.stream.wants_write() {
+        Interest::READABLE | Interest::WRITABLE
+    } else {
+        Interest::READABLE
+    }
+}
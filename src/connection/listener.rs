@@ -0,0 +1,250 @@
+//! Listeners
+//!
+//! Sockets the server accepts connections on, parsed from the `listen` settings
+use std::io::{Error, ErrorKind, Result};
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::sync::Arc;
+
+use rustls::ServerConfig;
+use socket2::{Domain, Socket, Type};
+
+use crate::connection::stream::Stream;
+
+/// Maximum number of connections waiting to be accepted
+const BACKLOG: i32 = 1024;
+/// Environment variable with the listeners a restarted process inherits, like `tcp:3,unix:4`
+#[cfg(unix)]
+pub const INHERITED_LISTENERS: &str = "AKIRA_LISTENERS";
+
+/// Socket accepting connections
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Binds a listen address: `0.0.0.0:80`, `[::]:80`, `localhost:80` or `unix:/run/akira.sock`
+    ///
+    /// IPv6 sockets only accept IPv6 connections so they can be used next to an IPv4 one on
+    /// the same port, stale Unix socket files are replaced
+    pub fn bind(address: &str, ttl: u32) -> Result<Listener> {
+        if let Some(path) = address.strip_prefix("unix:") {
+            return bind_unix(path);
+        }
+
+        let socket_address = match address.to_socket_addrs()?.next() {
+            Some(value) => value,
+            None => return Err(Error::new(ErrorKind::InvalidInput, format!("Invalid listen address {}", address))),
+        };
+        let socket = Socket::new(Domain::for_address(socket_address), Type::STREAM, None)?;
+        if let SocketAddr::V6(_) = socket_address {
+            socket.set_only_v6(true)?;
+        }
+        socket.set_reuse_address(true)?;
+        socket.bind(&socket_address.into())?;
+        socket.listen(BACKLOG)?;
+        let listener: TcpListener = socket.into();
+        listener.set_ttl(ttl)?;
+        Ok(Listener::Tcp(listener))
+    }
+
+    /// Waits for the next connection, TCP connections are wrapped on a TLS session when a config
+    /// is given
+    pub fn accept(&self, tls_config: Option<&Arc<ServerConfig>>) -> Result<Stream> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (socket, _address) = listener.accept()?;
+                match tls_config {
+                    Some(config) => Stream::new_tls(socket, config.clone()),
+                    None => Ok(Stream::Plain(socket)),
+                }
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                let (socket, _address) = listener.accept()?;
+                Ok(Stream::Unix(socket))
+            }
+        }
+    }
+
+    /// Makes `accept` return `ErrorKind::WouldBlock` instead of waiting
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<()> {
+        match self {
+            Listener::Tcp(listener) => listener.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Listener::Unix(listener) => listener.set_nonblocking(nonblocking),
+        }
+    }
+
+    /// Checks if the listener is bound to a listen address
+    pub fn matches(&self, address: &str) -> bool {
+        match self {
+            Listener::Tcp(listener) => {
+                if address.starts_with("unix:") {
+                    return false;
+                }
+                match (listener.local_addr(), address.to_socket_addrs()) {
+                    (Ok(local), Ok(mut addresses)) => addresses.next() == Some(local),
+                    _ => false,
+                }
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener) => match (listener.local_addr(), address.strip_prefix("unix:")) {
+                (Ok(local), Some(path)) => local.as_pathname() == Some(std::path::Path::new(path)),
+                _ => false,
+            },
+        }
+    }
+
+    /// Duplicates the socket without close-on-exec so a new process can inherit it, returns the
+    /// entry for `INHERITED_LISTENERS`
+    #[cfg(unix)]
+    pub fn share(&self) -> Result<String> {
+        let fd = unsafe { libc::dup(self.as_raw_fd()) };
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+        match self {
+            Listener::Tcp(_) => Ok(format!("tcp:{}", fd)),
+            Listener::Unix(_) => Ok(format!("unix:{}", fd)),
+        }
+    }
+
+    /// Defines if connections can be wrapped on TLS sessions
+    pub fn supports_tls(&self) -> bool {
+        match self {
+            Listener::Tcp(_) => true,
+            #[cfg(unix)]
+            Listener::Unix(_) => false,
+        }
+    }
+}
+
+#[cfg(unix)]
+impl AsRawFd for Listener {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Listener::Tcp(listener) => listener.as_raw_fd(),
+            Listener::Unix(listener) => listener.as_raw_fd(),
+        }
+    }
+}
+
+/// Takes the listeners passed by the process that started this one
+#[cfg(unix)]
+pub fn inherited_listeners() -> Vec<Listener> {
+    let value = match std::env::var(INHERITED_LISTENERS) {
+        Ok(value) => value,
+        Err(_err) => return Vec::new(),
+    };
+    // Processes started by this one get their own list
+    std::env::remove_var(INHERITED_LISTENERS);
+    parse_inherited_listeners(&value)
+}
+
+/// Takes ownership of the listeners of an `INHERITED_LISTENERS` value, invalid entries are
+/// skipped
+#[cfg(unix)]
+pub fn parse_inherited_listeners(value: &str) -> Vec<Listener> {
+    let mut listeners: Vec<Listener> = Vec::new();
+    for (kind, fd) in value.split(',').filter_map(parse_inherited_entry) {
+        // The descriptors were duplicated for this process and nothing else owns them, they are
+        // not passed again to the processes this one starts
+        unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+        match kind {
+            "tcp" => listeners.push(Listener::Tcp(unsafe { TcpListener::from_raw_fd(fd) })),
+            _ => listeners.push(Listener::Unix(unsafe { UnixListener::from_raw_fd(fd) })),
+        }
+    }
+    listeners
+}
+
+/// Reads an entry of `INHERITED_LISTENERS` like `tcp:3` as its kind and descriptor
+#[cfg(unix)]
+pub fn parse_inherited_entry(entry: &str) -> Option<(&str, RawFd)> {
+    let (kind, fd) = entry.trim().split_once(':')?;
+    match (kind, fd.parse::<RawFd>()) {
+        ("tcp" | "unix", Ok(fd)) if fd >= 0 => Some((kind, fd)),
+        _ => None,
+    }
+}
+
+#[cfg(not(unix))]
+pub fn inherited_listeners() -> Vec<Listener> {
+    Vec::new()
+}
+
+#[cfg(unix)]
+fn bind_unix(path: &str) -> Result<Listener> {
+    use std::os::unix::fs::FileTypeExt;
+
+    // A socket left by a previous run would make the bind fail
+    if let Ok(metadata) = std::fs::metadata(path) {
+        if metadata.file_type().is_socket() && std::os::unix::net::UnixStream::connect(path).is_err() {
+            std::fs::remove_file(path)?;
+        }
+    }
+    Ok(Listener::Unix(UnixListener::bind(path)?))
+}
+
+#[cfg(not(unix))]
+fn bind_unix(_path: &str) -> Result<Listener> {
+    Err(Error::new(ErrorKind::InvalidInput, "Unix sockets are not supported on this platform"))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::net::TcpStream;
+
+    #[test]
+    fn binds_and_matches_listen_addresses() {
+        let listener = Listener::bind("127.0.0.1:0", 64).unwrap();
+        let address = match &listener {
+            Listener::Tcp(listener) => listener.local_addr().unwrap().to_string(),
+            Listener::Unix(_) => panic!("Not a TCP listener"),
+        };
+        assert!(listener.matches(&address));
+        assert!(!listener.matches("127.0.0.1:1"));
+        assert!(!listener.matches(&format!("unix:{}", address)));
+        assert!(listener.supports_tls());
+        TcpStream::connect(&address).unwrap();
+
+        assert_eq!(Listener::bind("nothing", 64).err().map(|err| err.kind()), Some(ErrorKind::InvalidInput));
+        assert!(Listener::bind("127.0.0.1:99999", 64).is_err());
+    }
+
+    #[test]
+    fn replaces_stale_unix_sockets() {
+        let path = std::env::temp_dir().join(format!("akira-listener-{}.sock", std::process::id()));
+        let address = format!("unix:{}", path.display());
+        drop(UnixListener::bind(&path).unwrap());
+
+        let listener = Listener::bind(&address, 64).unwrap();
+        assert!(listener.matches(&address));
+        assert!(!listener.matches("127.0.0.1:80"));
+        assert!(!listener.supports_tls());
+        // A socket that is still accepting connections is not replaced
+        assert!(Listener::bind(&address, 64).is_err());
+
+        drop(listener);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn parses_inherited_entries() {
+        assert_eq!(parse_inherited_entry("tcp:3"), Some(("tcp", 3)));
+        assert_eq!(parse_inherited_entry(" unix:12 "), Some(("unix", 12)));
+        assert_eq!(parse_inherited_entry("udp:3"), None);
+        assert_eq!(parse_inherited_entry("tcp:"), None);
+        assert_eq!(parse_inherited_entry("tcp:-1"), None);
+        assert_eq!(parse_inherited_entry("tcp"), None);
+        assert_eq!(parse_inherited_entry(""), None);
+        assert!(parse_inherited_listeners("udp:3,tcp:x,,unix").is_empty());
+    }
+}
@@ -0,0 +1,9 @@
+//! # Connection Module
+#[allow(clippy::module_inception)]
+pub mod connection;
+#[cfg(unix)]
+pub mod event_loop;
+pub mod listener;
+pub mod shutdown;
+pub mod stream;
+pub mod tls;
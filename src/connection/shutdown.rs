@@ -0,0 +1,163 @@
+//! Shutdown
+//!
+//! SIGTERM and SIGINT make the server stop accepting connections and wait for the active ones to
+//! end, SIGUSR2 starts a new process that inherits the listeners before stopping this one so
+//! restarts do not refuse any connection and SIGHUP reloads the settings
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(unix)]
+use std::sync::Arc;
+
+#[cfg(unix)]
+use mio::Waker;
+
+#[cfg(unix)]
+use crate::connection::listener::{parse_inherited_entry, Listener, INHERITED_LISTENERS};
+#[cfg(unix)]
+use crate::utils::log::*;
+#[cfg(unix)]
+use crate::APP_CONFIG;
+
+/// Environment variable with the id of the process to stop once the new one is ready
+#[cfg(unix)]
+const RESTARTED_PROCESS: &str = "AKIRA_RESTARTED_PID";
+
+/// Defines if the server is stopping
+static STOPPING: AtomicBool = AtomicBool::new(false);
+/// Defines if a new process has to be started
+static RESTART: AtomicBool = AtomicBool::new(false);
+
+/// Checks if the server is stopping, responses are sent with `Connection: close`
+pub fn is_stopping() -> bool {
+    STOPPING.load(Ordering::SeqCst)
+}
+
+/// Checks if a restart was requested, clearing the request
+pub fn take_restart_request() -> bool {
+    RESTART.swap(false, Ordering::SeqCst)
+}
+
+/// Handles the signals on their own thread, waking up the event loop for the ones it acts on
+///
+/// A second SIGTERM or SIGINT while waiting for the connections exits right away
+#[cfg(unix)]
+pub fn handle_signals(waker: Arc<Waker>) -> std::io::Result<()> {
+    use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM, SIGUSR2};
+    use signal_hook::iterator::Signals;
+
+    let mut signals = Signals::new([SIGTERM, SIGINT, SIGUSR2, SIGHUP])?;
+    std::thread::spawn(move || {
+        for signal in signals.forever() {
+            if signal == SIGHUP {
+                APP_CONFIG.reload();
+                continue;
+            }
+            if signal == SIGUSR2 {
+                RESTART.store(true, Ordering::SeqCst);
+            } else if STOPPING.swap(true, Ordering::SeqCst) {
+                log_warning(&"Stopping without waiting for active connections");
+                std::process::exit(1);
+            }
+            waker.wake().ok();
+        }
+    });
+    Ok(())
+}
+
+/// Starts a new process of the server with the same arguments that inherits the listeners
+///
+/// The executable is taken from the arguments instead of the running one so a replaced binary
+/// is the one started
+#[cfg(unix)]
+pub fn restart(listeners: &[&Listener]) -> std::io::Result<()> {
+    use std::process::Command;
+
+    let mut args = std::env::args_os();
+    let program = match args.next() {
+        Some(value) => value.into(),
+        None => std::env::current_exe()?,
+    };
+    let shared = share_listeners(listeners)?;
+    let result = Command::new(program)
+        .args(args)
+        .env(INHERITED_LISTENERS, shared.join(","))
+        .env(RESTARTED_PROCESS, std::process::id().to_string())
+        .spawn()
+        .map(|_child| ());
+
+    // The new process has its own copies
+    close_shared(&shared);
+    result
+}
+
+/// Duplicates the listeners for a new process, returns the entries of `INHERITED_LISTENERS`
+#[cfg(unix)]
+fn share_listeners(listeners: &[&Listener]) -> std::io::Result<Vec<String>> {
+    let mut shared: Vec<String> = Vec::new();
+    for listener in listeners {
+        match listener.share() {
+            Ok(value) => shared.push(value),
+            Err(error) => {
+                close_shared(&shared);
+                return Err(error);
+            }
+        }
+    }
+    Ok(shared)
+}
+
+/// Closes the descriptors duplicated by `share_listeners`
+#[cfg(unix)]
+fn close_shared(shared: &[String]) {
+    for (_kind, fd) in shared.iter().filter_map(|entry| parse_inherited_entry(entry)) {
+        unsafe { libc::close(fd) };
+    }
+}
+
+/// Stops the process that started this one, called once the listeners are ready
+#[cfg(unix)]
+pub fn stop_restarted_process() {
+    let pid = match std::env::var(RESTARTED_PROCESS) {
+        Ok(value) => value,
+        Err(_err) => return,
+    };
+    std::env::remove_var(RESTARTED_PROCESS);
+    if let Ok(pid) = pid.parse::<libc::pid_t>() {
+        unsafe { libc::kill(pid, libc::SIGTERM) };
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::connection::listener::parse_inherited_listeners;
+    use std::net::TcpStream;
+
+    #[test]
+    fn shares_listeners_with_new_processes() {
+        let path = std::env::temp_dir().join(format!("akira-shutdown-{}.sock", std::process::id()));
+        let unix_address = format!("unix:{}", path.display());
+        let tcp = Listener::bind("127.0.0.1:0", 64).unwrap();
+        let unix = Listener::bind(&unix_address, 64).unwrap();
+        let tcp_address = match &tcp {
+            Listener::Tcp(listener) => listener.local_addr().unwrap().to_string(),
+            Listener::Unix(_) => panic!("Not a TCP listener"),
+        };
+
+        let shared = share_listeners(&[&tcp, &unix]).unwrap();
+        assert!(shared[0].starts_with("tcp:"));
+        assert!(shared[1].starts_with("unix:"));
+        let inherited = parse_inherited_listeners(&shared.join(","));
+        assert_eq!(inherited.len(), 2);
+        assert!(inherited[0].matches(&tcp_address));
+        assert!(inherited[1].matches(&unix_address));
+
+        // The original listener is closed but the inherited one keeps accepting
+        drop(tcp);
+        let _client = TcpStream::connect(&tcp_address).unwrap();
+        assert!(inherited[0].accept(None).is_ok());
+
+        drop(inherited);
+        drop(unix);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,318 @@
+//! Client Streams
+//!
+//! Plain and TLS connections behind the same type so handlers do not need to know how the
+//! client is connected
+use std::io::{BufReader, Error, ErrorKind, Read, Result, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+
+/// Connection with a client
+///
+/// Like `TcpStream` it can be read and written through shared references, TLS sessions are
+/// behind a lock as they need exclusive access to encrypt and decrypt
+pub enum Stream {
+    Plain(TcpStream),
+    Tls {
+        /// Handle of the socket used by the session, for timeouts and addresses
+        socket: TcpStream,
+        session: Box<Mutex<StreamOwned<ServerConnection, TcpStream>>>,
+    },
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    /// Wraps a socket on a TLS session, the handshake happens on the first read or write
+    pub fn new_tls(socket: TcpStream, config: Arc<ServerConfig>) -> Result<Stream> {
+        let connection = match ServerConnection::new(config) {
+            Ok(value) => value,
+            Err(err) => return Err(std::io::Error::other(err)),
+        };
+        let session = StreamOwned::new(connection, socket.try_clone()?);
+        Ok(Stream::Tls {
+            socket,
+            session: Box::new(Mutex::new(session)),
+        })
+    }
+
+    /// Defines if the connection is encrypted
+    pub fn is_secure(&self) -> bool {
+        matches!(self, Stream::Tls { .. })
+    }
+
+    /// Sets the maximum time a read waits for the client
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        match self {
+            Stream::Plain(socket) | Stream::Tls { socket, .. } => socket.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Stream::Unix(socket) => socket.set_read_timeout(timeout),
+        }
+    }
+
+    /// Sets the maximum time a write waits for the client to read what was sent before
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        match self {
+            Stream::Plain(socket) | Stream::Tls { socket, .. } => socket.set_write_timeout(timeout),
+            #[cfg(unix)]
+            Stream::Unix(socket) => socket.set_write_timeout(timeout),
+        }
+    }
+
+    /// Makes reads and writes return `ErrorKind::WouldBlock` instead of waiting
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<()> {
+        match self {
+            Stream::Plain(socket) | Stream::Tls { socket, .. } => socket.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Stream::Unix(socket) => socket.set_nonblocking(nonblocking),
+        }
+    }
+
+    /// Checks if there are decrypted bytes waiting to be read, they will not make the socket
+    /// readable again
+    pub fn has_buffered_data(&self) -> bool {
+        match self {
+            Stream::Tls { session, .. } => match lock(session).conn.process_new_packets() {
+                Ok(state) => state.plaintext_bytes_to_read() > 0,
+                Err(_err) => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Reads what the client sent without waiting, the socket has to be non blocking
+    ///
+    /// TLS sessions are driven here: records are read and decrypted into `buf` and the answers
+    /// of the handshake are written. Fails with `ErrorKind::WouldBlock` once nothing else can
+    /// be read
+    pub fn read_available(&self, buf: &mut [u8]) -> Result<usize> {
+        let session = match self {
+            Stream::Tls { session, .. } => session,
+            _ => {
+                let mut stream = self;
+                return stream.read(buf);
+            }
+        };
+        let mut session = lock(session);
+        let StreamOwned { conn, sock } = &mut *session;
+        loop {
+            match conn.reader().read(buf) {
+                Err(ref error) if error.kind() == ErrorKind::WouldBlock => {}
+                // Ok(0) once the client sent close_notify
+                result => return result,
+            }
+            while conn.wants_write() {
+                conn.write_tls(sock)?;
+            }
+            if conn.read_tls(sock)? == 0 {
+                return Ok(0);
+            }
+            if let Err(error) = conn.process_new_packets() {
+                // The alert describing the error is sent before closing
+                conn.write_tls(sock).ok();
+                return Err(Error::new(ErrorKind::InvalidData, error));
+            }
+        }
+    }
+
+    /// Checks if the TLS session has records to send that did not fit on the socket, the
+    /// next `read_available` sends them
+    pub fn wants_write(&self) -> bool {
+        match self {
+            Stream::Tls { session, .. } => lock(session).conn.wants_write(),
+            _ => false,
+        }
+    }
+
+    /// Address of the client, None for Unix sockets
+    pub fn peer_addr(&self) -> Option<Result<SocketAddr>> {
+        match self {
+            Stream::Plain(socket) | Stream::Tls { socket, .. } => Some(socket.peer_addr()),
+            #[cfg(unix)]
+            Stream::Unix(_) => None,
+        }
+    }
+
+    /// Closes the connection, TLS sessions are notified before
+    pub fn shutdown(&self) -> Result<()> {
+        match self {
+            Stream::Plain(socket) => socket.shutdown(Shutdown::Both),
+            Stream::Tls { socket, session } => {
+                let mut session = lock(session);
+                session.conn.send_close_notify();
+                session.flush().ok();
+                socket.shutdown(Shutdown::Both)
+            }
+            #[cfg(unix)]
+            Stream::Unix(socket) => socket.shutdown(Shutdown::Both),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl AsRawFd for Stream {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Stream::Plain(socket) | Stream::Tls { socket, .. } => socket.as_raw_fd(),
+            Stream::Unix(socket) => socket.as_raw_fd(),
+        }
+    }
+}
+
+/// Least bytes a client has to send on each period of the request timeout while a request is
+/// read, so a client trickling its body can not hold a worker
+const MIN_PERIOD_BYTES: usize = 8 * 1024;
+
+/// Reads the bytes received before the reader was created and then the stream
+pub struct StreamReader<'a> {
+    stream: &'a Stream,
+    pending: Vec<u8>,
+    /// Bytes of `pending` already read
+    position: usize,
+    /// Length of the period the client has to send `MIN_PERIOD_BYTES` on, with the start of
+    /// the current one and the bytes received on it
+    period: Option<(Duration, Instant, usize)>,
+}
+
+impl<'a> StreamReader<'a> {
+    pub fn new(stream: &'a Stream, pending: Vec<u8>) -> StreamReader<'a> {
+        StreamReader {
+            stream,
+            pending,
+            position: 0,
+            period: None,
+        }
+    }
+
+    /// Makes reads fail with `ErrorKind::TimedOut` when the client sends less than
+    /// `MIN_PERIOD_BYTES` on a period of `timeout`, None while waiting for a request
+    pub fn set_min_rate(&mut self, timeout: Option<Duration>) {
+        self.period = timeout.map(|timeout| (timeout, Instant::now(), 0));
+    }
+
+    /// Stream being read
+    pub fn stream(&self) -> &'a Stream {
+        self.stream
+    }
+
+    /// Checks if some of the bytes received before were not read yet
+    pub fn has_pending(&self) -> bool {
+        self.position < self.pending.len()
+    }
+}
+
+impl<'a> Read for StreamReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.position < self.pending.len() {
+            let read = (&self.pending[self.position..]).read(buf)?;
+            self.position += read;
+            return Ok(read);
+        }
+        if let Some((timeout, start, received)) = &mut self.period {
+            if start.elapsed() >= *timeout {
+                if *received < MIN_PERIOD_BYTES {
+                    return Err(Error::new(ErrorKind::TimedOut, "The client is sending the request too slowly"));
+                }
+                *start = Instant::now();
+                *received = 0;
+            }
+        }
+        let read = self.stream.read(buf)?;
+        if let Some((_timeout, _start, received)) = &mut self.period {
+            *received += read;
+        }
+        Ok(read)
+    }
+}
+
+/// Buffered reader requests are parsed from
+pub type RequestReader<'a> = BufReader<StreamReader<'a>>;
+
+/// Obtains the bytes read from the stream that were not consumed yet
+pub fn take_unread(reader: RequestReader<'_>) -> Vec<u8> {
+    let mut unread = reader.buffer().to_vec();
+    let inner = reader.into_inner();
+    unread.extend_from_slice(&inner.pending[inner.position..]);
+    unread
+}
+
+/// Locks a session, a poisoned lock is still usable as the session is only used by one thread
+fn lock(
+    session: &Mutex<StreamOwned<ServerConnection, TcpStream>>,
+) -> MutexGuard<'_, StreamOwned<ServerConnection, TcpStream>> {
+    match session.lock() {
+        Ok(value) => value,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+impl Read for &Stream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self {
+            Stream::Plain(socket) => (&*socket).read(buf),
+            Stream::Tls { session, .. } => lock(session).read(buf),
+            #[cfg(unix)]
+            Stream::Unix(socket) => (&*socket).read(buf),
+        }
+    }
+}
+
+impl Write for &Stream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        match self {
+            Stream::Plain(socket) => (&*socket).write(buf),
+            Stream::Tls { session, .. } => lock(session).write(buf),
+            #[cfg(unix)]
+            Stream::Unix(socket) => (&*socket).write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            Stream::Plain(socket) => (&*socket).flush(),
+            Stream::Tls { session, .. } => lock(session).flush(),
+            #[cfg(unix)]
+            Stream::Unix(socket) => (&*socket).flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread::sleep;
+
+    #[test]
+    fn refuses_clients_trickling_the_request() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let stream = Stream::Plain(listener.accept().unwrap().0);
+        let mut reader = StreamReader::new(&stream, Vec::new());
+        reader.set_min_rate(Some(Duration::from_millis(50)));
+        let mut buffer = [0; 16];
+
+        client.write_all(b"a").unwrap();
+        assert_eq!(reader.read(&mut buffer).unwrap(), 1);
+        sleep(Duration::from_millis(60));
+        client.write_all(b"b").unwrap();
+        assert_eq!(reader.read(&mut buffer).unwrap_err().kind(), ErrorKind::TimedOut);
+
+        // Enough bytes on each period keep the request going
+        reader.set_min_rate(Some(Duration::from_millis(50)));
+        client.write_all(&[0; MIN_PERIOD_BYTES]).unwrap();
+        let mut received = 0;
+        while received < MIN_PERIOD_BYTES + 1 {
+            received += reader.read(&mut [0; MIN_PERIOD_BYTES]).unwrap();
+        }
+        sleep(Duration::from_millis(60));
+        client.write_all(b"c").unwrap();
+        assert_eq!(reader.read(&mut buffer).unwrap(), 1);
+    }
+}
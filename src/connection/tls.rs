@@ -0,0 +1,146 @@
+//! TLS Configuration
+//!
+//! Loads the certificates from the settings and picks the one for each client with SNI
+use std::sync::Arc;
+
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+
+use crate::settings::settings::Config;
+use crate::settings::vhost::{normalize_host, VirtualHost};
+
+#[derive(Debug)]
+/// Picks the certificate of the virtual host that matches the name sent by the client
+struct SniResolver {
+    /// Virtual hosts with their own certificate
+    hosts: Vec<(Arc<VirtualHost>, Arc<CertifiedKey>)>,
+    /// Certificate from `[Tls]`
+    default: Arc<CertifiedKey>,
+}
+
+impl SniResolver {
+    /// Obtains the certificate of the most specific server name matching the one sent
+    fn get_key(&self, server_name: Option<&str>) -> Arc<CertifiedKey> {
+        let name = match server_name {
+            Some(value) => normalize_host(value),
+            None => return self.default.clone(),
+        };
+        let best_match = self
+            .hosts
+            .iter()
+            .filter_map(|(vhost, key)| vhost.matches(&name).map(|specificity| (specificity, key)))
+            .max_by_key(|(specificity, _key)| *specificity);
+        match best_match {
+            Some((_specificity, key)) => key.clone(),
+            None => self.default.clone(),
+        }
+    }
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.get_key(client_hello.server_name()))
+    }
+}
+
+/// Builds the TLS configuration with the certificates of `[Tls]` and the virtual hosts
+pub fn load_tls_config(config: &Config) -> Result<Arc<ServerConfig>, String> {
+    let provider = Arc::new(ring::default_provider());
+
+    let default = load_certified_key(&provider, &config.tls.certificate, &config.tls.private_key)?;
+    let mut hosts: Vec<(Arc<VirtualHost>, Arc<CertifiedKey>)> = Vec::new();
+    for vhost in &config.vhost {
+        if let (Some(certificate), Some(private_key)) = (&vhost.certificate, &vhost.private_key) {
+            hosts.push((vhost.clone(), load_certified_key(&provider, certificate, private_key)?));
+        }
+    }
+
+    let mut server_config = match ServerConfig::builder_with_provider(provider).with_safe_default_protocol_versions() {
+        Ok(value) => value
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(SniResolver { hosts, default })),
+        Err(err) => return Err(err.to_string()),
+    };
+    server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(Arc::new(server_config))
+}
+
+/// Reads a certificate chain and its private key from PEM files
+fn load_certified_key(
+    provider: &CryptoProvider,
+    certificate: &str,
+    private_key: &str,
+) -> Result<Arc<CertifiedKey>, String> {
+    let chain = CertificateDer::pem_file_iter(certificate)
+        .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+        .map_err(|err| format!("Could not read certificate {}: {:?}", certificate, err))?;
+    if chain.is_empty() {
+        return Err(format!("No certificates found on {}", certificate));
+    }
+    let key = PrivateKeyDer::from_pem_file(private_key)
+        .map_err(|err| format!("Could not read private key {}: {:?}", private_key, err))?;
+    let signing_key = provider
+        .key_provider
+        .load_private_key(key)
+        .map_err(|err| format!("Invalid private key {}: {}", private_key, err))?;
+    Ok(Arc::new(CertifiedKey::new(chain, signing_key)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::sign::{Signer, SigningKey};
+    use rustls::{SignatureAlgorithm, SignatureScheme};
+
+    #[derive(Debug)]
+    /// Key that can not sign, the resolver only hands it out
+    struct TestKey;
+
+    impl SigningKey for TestKey {
+        fn choose_scheme(&self, _offered: &[SignatureScheme]) -> Option<Box<dyn Signer>> {
+            None
+        }
+
+        fn algorithm(&self) -> SignatureAlgorithm {
+            SignatureAlgorithm::ED25519
+        }
+    }
+
+    /// Certificate told apart by its single byte
+    fn key(id: u8) -> Arc<CertifiedKey> {
+        Arc::new(CertifiedKey::new(vec![CertificateDer::from(vec![id])], Arc::new(TestKey)))
+    }
+
+    fn id(key: Arc<CertifiedKey>) -> u8 {
+        key.cert[0].as_ref()[0]
+    }
+
+    fn vhost(names: &[&str]) -> Arc<VirtualHost> {
+        Arc::new(VirtualHost {
+            server_names: names.iter().map(|name| (*name).to_owned()).collect(),
+            ..VirtualHost::default()
+        })
+    }
+
+    #[test]
+    fn picks_the_certificate_of_the_most_specific_name() {
+        let resolver = SniResolver {
+            hosts: vec![
+                (vhost(&["*.example.com"]), key(1)),
+                (vhost(&["www.example.com"]), key(2)),
+                (vhost(&["example.*"]), key(3)),
+            ],
+            default: key(0),
+        };
+        assert_eq!(id(resolver.get_key(Some("www.example.com"))), 2);
+        assert_eq!(id(resolver.get_key(Some("WWW.Example.com."))), 2);
+        assert_eq!(id(resolver.get_key(Some("static.example.com"))), 1);
+        assert_eq!(id(resolver.get_key(Some("example.org"))), 3);
+        assert_eq!(id(resolver.get_key(Some("other.org"))), 0);
+        assert_eq!(id(resolver.get_key(None)), 0);
+    }
+}
@@ -11,89 +11,189 @@ extern crate lazy_static;
 extern crate derivative;
 
 use std::io::prelude::*;
-use std::net::{Shutdown, TcpListener, TcpStream};
+use std::io::BufReader;
+use std::sync::Arc;
+#[cfg(not(unix))]
+use std::thread;
+use std::time::Instant;
+
+use chrono::Local;
+
+use rustls::ServerConfig;
 
 use num_cpus;
 use threadpool::ThreadPool;
 
-use std::time::Duration;
-
 // Request Handlers
 mod request_handlers;
-use crate::request_handlers::get_handler::handle_get;
+use crate::request_handlers::delete_handler::handle_delete;
+use crate::request_handlers::get_handler::{handle_get, serve_error};
+use crate::request_handlers::options_handler::handle_options;
+use crate::request_handlers::post_handler::handle_post;
+use crate::request_handlers::proxy_handler::handle_proxy;
+use crate::request_handlers::put_handler::handle_put;
+use crate::request_handlers::redirect_handler::{handle_https_redirect, handle_rewrite_redirect};
 use crate::request_handlers::unssuported_handler::handle_unsupported;
 
+// Connections
+mod connection;
+use crate::connection::connection::Connection;
+#[cfg(unix)]
+use crate::connection::event_loop::EventLoop;
+use crate::connection::listener::{inherited_listeners, Listener};
+#[cfg(unix)]
+use crate::connection::shutdown::stop_restarted_process;
+use crate::connection::shutdown::is_stopping;
+use crate::connection::stream::{take_unread, StreamReader};
+use crate::connection::tls::load_tls_config;
+
 // Util
 mod utils;
+use crate::utils::check_stream_write;
 use crate::utils::log::*;
+use crate::utils::log_file::flush_logs;
 
 // For Config
 mod settings;
-use crate::settings::settings::Config;
+use crate::settings::settings::{Config, ConfigHandle};
 
 // Request Module
 mod request;
 
 use crate::request::method::Method;
+use crate::request::reader::get_body_error_status;
 use crate::request::request::Request;
 
 // Response
 mod response;
 
+// Reverse Proxy
+mod proxy;
+use crate::proxy::health::start_health_checks;
+
 lazy_static! {
-    pub static ref APP_CONFIG: Config = load_settings();
+    pub static ref APP_CONFIG: ConfigHandle = ConfigHandle::new(load_settings());
 }
 
+#[cfg(not(test))]
 fn load_settings() -> Config {
-    match Config::new() {
-        Ok(mut value) => {
-            if !value.server.root_folder.ends_with("/") {
-                value.server.root_folder = value.server.root_folder.clone() + &"/";
-            }
-            value
-        }
+    match Config::load() {
+        Ok(value) => value,
         Err(error) => {
             panic!("{}", error);
         }
     }
 }
 
+#[cfg(test)]
+fn load_settings() -> Config {
+    Config::load_for_tests()
+}
+
 fn main() {
-    APP_CONFIG.show();
-    server();
+    let config = APP_CONFIG.get();
+    config.show();
+    config.check_root_folders();
+    if config.server.watch_settings {
+        APP_CONFIG.watch();
+    }
+    start_health_checks();
+    server(&config);
 }
 
-fn server() {
+fn server(config: &Config) {
     // Obtiene numero procesadores logicos
     let core_count = num_cpus::get();
     // Calcula trabajos por procasador logico
-    let n_workers = core_count * APP_CONFIG.server.workers_per_thread;
+    let n_workers = core_count * config.server.workers_per_thread;
     // Inicia piscina de trabajos limitada
     let pool = ThreadPool::new(n_workers);
-    // Bind de la direccion tcp
-    let listener_option = TcpListener::bind(format!(
-        "{host}:{port}",
-        host = APP_CONFIG.server.host,
-        port = APP_CONFIG.server.port
-    ));
-    let listener = match listener_option {
-        Ok(value) => (value),
+
+    let tls_config = if config.tls.active {
+        match load_tls_config(config) {
+            Ok(value) => Some(value),
+            Err(error) => {
+                panic!("{}", error);
+            }
+        }
+    } else {
+        None
+    };
+
+    // Listeners of the process this one replaces on a restart
+    let mut inherited = inherited_listeners();
+    let mut listeners: Vec<(Listener, Option<Arc<ServerConfig>>)> = Vec::new();
+    if tls_config.is_none() || config.tls.serve_http {
+        for address in config.server.get_listen_addresses() {
+            listeners.push((bind(&address, config.server.ttl, &mut inherited), None));
+        }
+    }
+    if let Some(tls_config) = tls_config {
+        for address in config.tls.get_listen_addresses(&config.server) {
+            let listener = bind(&address, config.server.ttl, &mut inherited);
+            if !listener.supports_tls() {
+                panic!("TLS is not supported on {}", address);
+            }
+            listeners.push((listener, Some(tls_config.clone())));
+        }
+    }
+    serve(listeners, pool);
+}
+
+/// Waits for requests on the event loop, workers only handle connections with a request ready
+#[cfg(unix)]
+fn serve(listeners: Vec<(Listener, Option<Arc<ServerConfig>>)>, pool: ThreadPool) {
+    let mut event_loop = match EventLoop::new(listeners, pool, handle_connection) {
+        Ok(value) => value,
         Err(error) => {
-            panic!("{}", error);
+            panic!("Could not start the event loop: {}", error);
         }
     };
+    stop_restarted_process();
+    if let Err(error) = event_loop.run() {
+        panic!("Event loop stopped: {}", error);
+    }
+    log_warning(&"Server stopped");
+    flush_logs();
+}
 
-    listener.set_ttl(APP_CONFIG.server.ttl).expect("could not set TTL");
+/// Accepts connections on a thread per listener, workers wait for the requests themselves
+#[cfg(not(unix))]
+fn serve(listeners: Vec<(Listener, Option<Arc<ServerConfig>>)>, pool: ThreadPool) {
+    let mut threads: Vec<thread::JoinHandle<()>> = Vec::new();
+    for (listener, tls_config) in listeners {
+        let pool = pool.clone();
+        threads.push(thread::spawn(move || accept_connections(listener, pool, tls_config)));
+    }
+    for thread in threads {
+        thread.join().ok();
+    }
+}
 
-    // Bucle para cada peticion tcp
-    for stream in listener.incoming() {
-        // Canal de datos tcp
-        match stream {
+/// Binds a listen address, reusing the inherited listener bound to it if there is one
+fn bind(address: &str, ttl: u32, inherited: &mut Vec<Listener>) -> Listener {
+    if let Some(position) = inherited.iter().position(|listener| listener.matches(address)) {
+        return inherited.remove(position);
+    }
+    match Listener::bind(address, ttl) {
+        Ok(value) => value,
+        Err(error) => {
+            panic!("Could not listen on {}: {}", address, error);
+        }
+    }
+}
+
+/// Sends each accepted connection to the pool, wrapped on a TLS session when a config is given
+#[cfg(not(unix))]
+fn accept_connections(listener: Listener, pool: ThreadPool, tls_config: Option<Arc<ServerConfig>>) {
+    // Bucle para cada peticion
+    loop {
+        match listener.accept(tls_config.as_ref()) {
             Ok(value) => {
                 // Inicia el trabajo en otro hilo su hay tareas disponibles, ni no, espera a que alguna finalize
                 pool.execute(move || {
                     // Hace cosas
-                    handle_connection(value);
+                    handle_connection(Connection::new(value));
                 });
             }
             Err(error) => log_error(&error),
@@ -101,33 +201,143 @@ fn server() {
     }
 }
 
-fn handle_connection(mut stream: TcpStream) {
-    // Create a Duration ans set is as timeout
-    // That way the server doesnt keep waiting for more bytes
-    let timeout = Some(Duration::new(0, APP_CONFIG.timeout.get_nanoseconds()));
+/// Serves the requests of a connection, returns it when it has to wait for the next request and
+/// the event loop can do the waiting
+fn handle_connection(mut connection: Connection) -> Option<Connection> {
+    // The reader is kept between requests so pipelined requests are not lost
+    let pending = std::mem::take(&mut connection.pending);
+    let mut stream = &connection.stream;
+    let mut reader = BufReader::new(StreamReader::new(stream, pending));
+    let mut served = connection.served;
+    let first = served;
 
-    //Parse request data
-    let request = Request::parse(&stream, timeout);
+    loop {
+        // Idle connections wait for the next request on the event loop
+        let idle = reader.buffer().is_empty() && !reader.get_ref().has_pending() && !stream.has_buffered_data();
+        if cfg!(unix) && served > first && idle {
+            let unread = take_unread(reader);
+            connection.pending = unread;
+            connection.served = served;
+            return Some(connection);
+        }
 
-    if request.is_valid_request {
-        log_verbose(&request);
+        // Create a Duration ans set is as timeout
+        // That way the server doesnt keep waiting for more bytes from a stalled client
+        let config = APP_CONFIG.get();
+        let timeout = Some(config.timeout.get_request_duration());
+        let keep_alive_timeout = Some(config.timeout.get_keep_alive_duration());
+
+        // Wait for the next request, closing idle connections
+        let idle_timeout = if served == 0 { timeout } else { keep_alive_timeout };
+        stream.set_read_timeout(idle_timeout).ok();
+        reader.get_mut().set_min_rate(None);
+        match reader.fill_buf() {
+            Ok(buffer) if !buffer.is_empty() => {}
+            _ => break,
+        }
+        // A client that stops reading the response or trickles the request does not hold
+        // the worker
+        stream.set_write_timeout(timeout).ok();
+        reader.get_mut().set_min_rate(timeout);
+
+        // The access log measures from the first byte of the request
+        let received = Local::now();
+        let started = Instant::now();
 
-        // Switch Equivalent
-        match request.method {
-            Method::GET => {
-                handle_get(&stream, &request);
+        //Parse request data
+        let mut request = Request::parse(&mut reader, timeout);
+        served += 1;
+        set_request_id(Some(&request.id));
+
+        if !request.is_valid_request {
+            log_warning(&"Invalid Request");
+            // Heads that can not be parsed are answered before closing the connection
+            if let Some(status) = request.invalid_status.take() {
+                serve_error(stream, &request, status);
+                log_access(&request, received, started.elapsed());
             }
-            _ => {
-                handle_unsupported(&stream);
+            break;
+        }
+
+        // Bodies that could be delimited in more than one way are never read
+        if let Err(err) = request.request_headers.check_framing() {
+            log_warning(&err);
+            serve_error(stream, &request, get_body_error_status(&err));
+            log_access(&request, received, started.elapsed());
+            break;
+        }
+
+        request.keep_alive = request.wants_keep_alive()
+            && served < request.config.server.max_requests_per_connection
+            && !is_stopping();
+        log_verbose(&request);
+
+        let config = request.config.clone();
+        let proxy_rule = config.get_proxy_rule(&request.path);
+        let checked = if proxy_rule.is_some() || request.redirect.is_some() {
+            request.check_target()
+        } else {
+            request.check_path()
+        };
+        // Bodies sent to handlers that do not use them are dropped, if they are small
+        let uses_body = proxy_rule.is_some() || request.method == Method::POST || request.method == Method::PUT;
+        let discarded = if checked.is_ok() && !uses_body {
+            request.discard_body(&mut reader)
+        } else {
+            Ok(())
+        };
+        if let Err(err) = checked {
+            // The body of refused uploads is not read
+            request.keep_alive = false;
+            serve_error(stream, &request, err.status());
+        } else if let Err(err) = discarded {
+            log_warning(&err);
+            request.keep_alive = false;
+            serve_error(stream, &request, get_body_error_status(&err));
+        } else if request.needs_https_redirect() {
+            handle_https_redirect(stream, &mut request);
+        } else if request.redirect.is_some() {
+            handle_rewrite_redirect(stream, &mut request);
+        } else if let Some(rule) = proxy_rule {
+            handle_proxy(stream, &mut reader, &mut request, rule);
+        } else {
+            // Switch Equivalent
+            match request.method {
+                Method::GET | Method::HEAD => {
+                    handle_get(stream, &mut request);
+                }
+                Method::OPTIONS => {
+                    handle_options(stream, &request);
+                }
+                Method::POST => {
+                    handle_post(stream, &mut reader, &mut request);
+                }
+                Method::PUT => {
+                    handle_put(stream, &mut reader, &mut request);
+                }
+                Method::DELETE => {
+                    handle_delete(stream, &request);
+                }
+                _ => {
+                    handle_unsupported(stream, &request);
+                }
             }
         }
-    } else {
-        log_warning(&"Invalid Request");
+
+        // TLS sessions may hold the end of the response
+        check_stream_write(stream.flush());
+        log_access(&request, received, started.elapsed());
+        set_request_id(None);
+
+        if !request.keep_alive {
+            break;
+        }
     }
+    set_request_id(None);
 
     // Avoid Dead Connections?
     match stream.flush().ok() {
-        Some(_value) => match stream.shutdown(Shutdown::Both).ok() {
+        Some(_value) => match stream.shutdown().ok() {
             None => {
                 log_warning(&"Could Not Shutdown The Stream");
             }
@@ -137,4 +347,5 @@ fn handle_connection(mut stream: TcpStream) {
             log_warning(&"Could Not Flush The Stream");
         }
     };
+    None
 }
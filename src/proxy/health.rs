@@ -0,0 +1,75 @@
+//! Health Checks
+//!
+//! Requests the `health_check` path of each upstream, upstreams that do not answer it with a
+//! 2xx or 3xx status stop receiving requests until they do. Every upstream is checked on its
+//! own thread so slow ones do not delay the rest
+use std::io::{BufRead, BufReader, Write};
+use std::thread;
+use std::time::Duration;
+
+use crate::proxy::upstream::{get_group, retain_groups, Server};
+use crate::response::headers::SERVER_NAME;
+use crate::APP_CONFIG;
+
+/// How often the thread looks for due health checks
+const CHECK_TICK: Duration = Duration::from_millis(500);
+
+/// Starts checking the upstreams of the rules with a `health_check` path, the rules are taken
+/// from the current config so reloads are followed
+///
+/// A group is not checked again until its previous round ends
+pub fn start_health_checks() {
+    thread::spawn(|| loop {
+        let config = APP_CONFIG.get();
+        retain_groups(&config.proxy);
+        for rule in &config.proxy {
+            let path = match &rule.health_check {
+                Some(value) => value.to_owned(),
+                None => continue,
+            };
+            let group = get_group(rule);
+            if !group.take_health_check(rule.get_health_check_interval_duration()) {
+                continue;
+            }
+            let timeout = config.timeout.get_proxy_connect_duration();
+            thread::spawn(move || {
+                thread::scope(|scope| {
+                    for server in &group.servers {
+                        let path = &path;
+                        scope.spawn(move || server.set_healthy(is_healthy(server, path, timeout)));
+                    }
+                });
+                group.end_health_check();
+            });
+        }
+        thread::sleep(CHECK_TICK);
+    });
+}
+
+/// Requests the path from the server, the timeout is used both to connect and to wait for the
+/// status line
+///
+/// The probe is not counted on the active requests of the server
+fn is_healthy(server: &Server, path: &str, timeout: Duration) -> bool {
+    let socket = match server.open_socket(timeout) {
+        Ok(value) => value,
+        Err(_err) => return false,
+    };
+    let mut stream = &socket;
+    stream.set_read_timeout(Some(timeout)).ok();
+    stream.set_write_timeout(Some(timeout)).ok();
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: {}\r\nConnection: close\r\n\r\n",
+        path, server.address, SERVER_NAME
+    );
+    if stream.write_all(request.as_bytes()).is_err() {
+        return false;
+    }
+
+    let mut status_line = String::new();
+    if BufReader::new(stream).read_line(&mut status_line).is_err() {
+        return false;
+    }
+    let code = status_line.split(' ').nth(1).and_then(|code| code.parse::<u16>().ok());
+    code.is_some_and(|code| (200..400).contains(&code))
+}
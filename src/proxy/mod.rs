@@ -0,0 +1,3 @@
+//! # Proxy Module
+pub mod health;
+pub mod upstream;
@@ -0,0 +1,405 @@
+//! Upstream Servers
+//!
+//! State kept for the servers of each `[[proxy]]` rule across requests: the requests each one
+//! is serving, the ones that are failing and the idle connections that can be reused
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::io::{Error, ErrorKind, Result};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::settings::proxy::ProxyRule;
+use crate::utils::log::*;
+
+lazy_static! {
+    /// Servers of each rule, a rule gets new ones when its prefix or upstreams change
+    static ref GROUPS: Mutex<HashMap<String, Arc<UpstreamGroup>>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Default)]
+/// State of a server that changes with the requests and the health checks
+struct ServerState {
+    /// Failed requests in a row
+    fails: u32,
+    /// The server is skipped until then after failing `max_fails` times
+    down_until: Option<Instant>,
+    /// Defines if the last health check failed
+    unhealthy: bool,
+    /// Connections waiting to be reused, with when they were released
+    idle: Vec<(TcpStream, Instant)>,
+}
+
+/// Server requests of a rule are forwarded to
+pub struct Server {
+    /// Address as `host:port`
+    pub address: String,
+    /// Requests being forwarded to the server
+    active: AtomicUsize,
+    state: Mutex<ServerState>,
+}
+
+impl Server {
+    fn new(address: &str) -> Server {
+        Server {
+            address: address.to_owned(),
+            active: AtomicUsize::new(0),
+            state: Mutex::new(ServerState::default()),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ServerState> {
+        match self.state.lock() {
+            Ok(value) => value,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Checks if the server can receive requests
+    fn is_available(&self, now: Instant) -> bool {
+        let state = self.lock();
+        !state.unhealthy && state.down_until.is_none_or(|until| now >= until)
+    }
+
+    /// Takes an idle connection to the server or opens a new one
+    ///
+    /// Idle connections closed by the server or kept for longer than `keep_alive` are dropped
+    pub fn connect(self: &Arc<Self>, timeout: Duration, keep_alive: Duration) -> Result<UpstreamConnection> {
+        loop {
+            let idle = self.lock().idle.pop();
+            let (stream, released) = match idle {
+                Some(value) => value,
+                None => break,
+            };
+            if released.elapsed() < keep_alive && is_open(&stream) {
+                return Ok(UpstreamConnection::new(self.clone(), stream, true));
+            }
+        }
+        self.open(timeout)
+    }
+
+    /// Opens a new connection to the first address of the server that accepts it
+    pub fn open(self: &Arc<Self>, timeout: Duration) -> Result<UpstreamConnection> {
+        let stream = self.open_socket(timeout)?;
+        Ok(UpstreamConnection::new(self.clone(), stream, false))
+    }
+
+    /// Opens a socket to the first address of the server that accepts it, without counting it
+    /// on the active requests
+    pub fn open_socket(&self, timeout: Duration) -> Result<TcpStream> {
+        let mut last_error = Error::new(ErrorKind::NotFound, "The upstream address did not resolve");
+        for address in self.address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last_error = err,
+            }
+        }
+        Err(last_error)
+    }
+
+    /// Counts a failed request, the server is skipped once it fails `max_fails` times in a row
+    pub fn report_failure(&self, rule: &ProxyRule) {
+        let max_fails = rule.get_max_fails();
+        let mut state = self.lock();
+        state.fails += 1;
+        if max_fails == 0 || state.fails < max_fails {
+            return;
+        }
+        state.fails = 0;
+        state.down_until = Some(Instant::now() + rule.get_fail_timeout_duration());
+        state.idle.clear();
+        drop(state);
+        log_warning(&format!(
+            "Upstream {} failed {} times, skipping it",
+            self.address, max_fails
+        ));
+    }
+
+    /// Counts a request the server answered
+    pub fn report_success(&self) {
+        self.lock().fails = 0;
+    }
+
+    /// Stores the result of a health check
+    pub fn set_healthy(&self, healthy: bool) {
+        let mut state = self.lock();
+        let changed = state.unhealthy == healthy;
+        state.unhealthy = !healthy;
+        if !healthy {
+            state.idle.clear();
+        }
+        drop(state);
+        if changed {
+            let status = if healthy {
+                "is healthy again"
+            } else {
+                "failed its health check"
+            };
+            log_warning(&format!("Upstream {} {}", self.address, status));
+        }
+    }
+}
+
+/// Connection to a server, counted on its active requests until it is dropped or released
+pub struct UpstreamConnection {
+    pub server: Arc<Server>,
+    stream: Option<TcpStream>,
+    /// Defines if the connection was used before, the server may have closed it meanwhile
+    pub reused: bool,
+}
+
+impl UpstreamConnection {
+    fn new(server: Arc<Server>, stream: TcpStream, reused: bool) -> UpstreamConnection {
+        server.active.fetch_add(1, Ordering::SeqCst);
+        UpstreamConnection {
+            server,
+            stream: Some(stream),
+            reused,
+        }
+    }
+
+    pub fn stream(&self) -> &TcpStream {
+        match &self.stream {
+            Some(value) => value,
+            None => unreachable!("The stream is only taken when the connection is released"),
+        }
+    }
+
+    /// Keeps the connection to be reused if the server has less than `max_idle` idle ones
+    pub fn release(mut self, max_idle: usize) {
+        let stream = match self.stream.take() {
+            Some(value) => value,
+            None => return,
+        };
+        let mut state = self.server.lock();
+        if state.idle.len() < max_idle {
+            state.idle.push((stream, Instant::now()));
+        }
+    }
+}
+
+impl Drop for UpstreamConnection {
+    fn drop(&mut self) {
+        self.server.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Servers of a rule
+pub struct UpstreamGroup {
+    pub servers: Vec<Arc<Server>>,
+    /// Counter used to take turns between the servers
+    next: AtomicUsize,
+    /// When the next health checks are due
+    next_check: Mutex<Instant>,
+    /// Defines if a round of health checks is running
+    checking: AtomicBool,
+}
+
+impl UpstreamGroup {
+    fn new(rule: &ProxyRule) -> UpstreamGroup {
+        UpstreamGroup {
+            servers: rule
+                .get_upstreams()
+                .into_iter()
+                .map(|address| Arc::new(Server::new(address)))
+                .collect(),
+            next: AtomicUsize::new(0),
+            next_check: Mutex::new(Instant::now()),
+            checking: AtomicBool::new(false),
+        }
+    }
+
+    /// Orders the servers a request is sent to, the first one is chosen by the strategy of the
+    /// rule and the rest are tried if it can not be reached
+    ///
+    /// Failing servers are left out unless all of them are failing
+    pub fn select(&self, rule: &ProxyRule, remote_ip: &str) -> Vec<Arc<Server>> {
+        if self.servers.is_empty() {
+            return Vec::new();
+        }
+        let now = Instant::now();
+
+        let mut selected = if rule.get_strategy() == "ip_hash" {
+            // Taken from every server so clients keep theirs while it is up
+            let mut hasher = DefaultHasher::new();
+            remote_ip.hash(&mut hasher);
+            let mut ordered = self.servers.clone();
+            ordered.rotate_left(hasher.finish() as usize % self.servers.len());
+            get_available(ordered, now)
+        } else {
+            let mut available = get_available(self.servers.clone(), now);
+            let start = self.next.fetch_add(1, Ordering::Relaxed) % available.len();
+            available.rotate_left(start);
+            available
+        };
+        if rule.get_strategy() == "least_connections" {
+            // Stable, servers with the same load keep taking turns
+            selected.sort_by_key(|server| server.active.load(Ordering::SeqCst));
+        }
+        selected
+    }
+
+    /// Checks if the health checks are due and the previous ones ended, scheduling the next
+    /// ones
+    pub fn take_health_check(&self, interval: Duration) -> bool {
+        if self.checking.load(Ordering::SeqCst) {
+            return false;
+        }
+        let mut next_check = match self.next_check.lock() {
+            Ok(value) => value,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let now = Instant::now();
+        if now < *next_check {
+            return false;
+        }
+        *next_check = now + interval;
+        self.checking.store(true, Ordering::SeqCst);
+        true
+    }
+
+    /// Marks the round of health checks taken as finished
+    pub fn end_health_check(&self) {
+        self.checking.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Leaves out the failing servers keeping their order, unless all of them are failing
+fn get_available(servers: Vec<Arc<Server>>, now: Instant) -> Vec<Arc<Server>> {
+    let available: Vec<Arc<Server>> = servers
+        .iter()
+        .filter(|server| server.is_available(now))
+        .cloned()
+        .collect();
+    if available.is_empty() {
+        servers
+    } else {
+        available
+    }
+}
+
+/// Obtains the servers of a rule, their state is kept while the rule does not change
+pub fn get_group(rule: &ProxyRule) -> Arc<UpstreamGroup> {
+    let mut groups = match GROUPS.lock() {
+        Ok(value) => value,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    groups
+        .entry(get_group_key(rule))
+        .or_insert_with(|| Arc::new(UpstreamGroup::new(rule)))
+        .clone()
+}
+
+/// Forgets the servers of the rules that were removed from the config
+pub fn retain_groups(rules: &[ProxyRule]) {
+    let keys: Vec<String> = rules.iter().map(get_group_key).collect();
+    let mut groups = match GROUPS.lock() {
+        Ok(value) => value,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    groups.retain(|key, _group| keys.contains(key));
+}
+
+fn get_group_key(rule: &ProxyRule) -> String {
+    format!("{} {}", rule.prefix, rule.get_upstreams().join(","))
+}
+
+/// Checks that an idle connection was not closed by the server, idle connections should not
+/// have anything to read
+fn is_open(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let mut byte = [0u8; 1];
+    let open = match stream.peek(&mut byte) {
+        Err(ref err) => err.kind() == ErrorKind::WouldBlock,
+        Ok(_read) => false,
+    };
+    open && stream.set_nonblocking(false).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::sleep;
+
+    fn rule(strategy: &str, upstreams: &[&str]) -> ProxyRule {
+        let mut settings = ::config::Config::new();
+        let rule = format!(
+            "[[proxy]]\nprefix = \"/api\"\nupstreams = {:?}\nstrategy = \"{}\"\nmax_fails = 2\n\
+             fail_timeout_miliseconds = 100",
+            upstreams, strategy
+        );
+        settings
+            .merge(::config::File::from_str(&rule, ::config::FileFormat::Toml))
+            .unwrap();
+        let mut rules: Vec<ProxyRule> = settings.get("proxy").unwrap();
+        rules.remove(0)
+    }
+
+    fn addresses(servers: &[Arc<Server>]) -> Vec<&str> {
+        servers.iter().map(|server| server.address.as_str()).collect()
+    }
+
+    #[test]
+    fn takes_turns_between_available_servers() {
+        let rule = rule("round_robin", &["a:1", "b:1", "c:1"]);
+        let group = UpstreamGroup::new(&rule);
+        assert_eq!(addresses(&group.select(&rule, "")), ["a:1", "b:1", "c:1"]);
+        assert_eq!(addresses(&group.select(&rule, "")), ["b:1", "c:1", "a:1"]);
+
+        group.servers[2].set_healthy(false);
+        assert_eq!(addresses(&group.select(&rule, "")), ["a:1", "b:1"]);
+        assert_eq!(addresses(&group.select(&rule, "")), ["b:1", "a:1"]);
+    }
+
+    #[test]
+    fn prefers_servers_with_less_connections() {
+        let rule = rule("least_connections", &["a:1", "b:1", "c:1"]);
+        let group = UpstreamGroup::new(&rule);
+        group.servers[0].active.store(2, Ordering::SeqCst);
+        group.servers[1].active.store(1, Ordering::SeqCst);
+        assert_eq!(addresses(&group.select(&rule, "")), ["c:1", "b:1", "a:1"]);
+    }
+
+    #[test]
+    fn keeps_clients_on_their_server_while_it_is_up() {
+        let rule = rule("ip_hash", &["a:1", "b:1", "c:1", "d:1"]);
+        let group = UpstreamGroup::new(&rule);
+        let first = group.select(&rule, "10.0.0.1");
+        assert_eq!(first.len(), 4);
+        assert_eq!(addresses(&group.select(&rule, "10.0.0.1")), addresses(&first));
+
+        first[0].set_healthy(false);
+        let moved = group.select(&rule, "10.0.0.1");
+        assert_eq!(addresses(&moved), addresses(&first[1..]));
+
+        first[0].set_healthy(true);
+        assert_eq!(addresses(&group.select(&rule, "10.0.0.1")), addresses(&first));
+    }
+
+    #[test]
+    fn skips_servers_that_keep_failing() {
+        let rule = rule("round_robin", &["a:1", "b:1"]);
+        let group = UpstreamGroup::new(&rule);
+        let server = &group.servers[0];
+
+        server.report_failure(&rule);
+        assert!(server.is_available(Instant::now()));
+        server.report_success();
+        server.report_failure(&rule);
+        assert!(server.is_available(Instant::now()));
+        server.report_failure(&rule);
+        assert!(!server.is_available(Instant::now()));
+        assert_eq!(addresses(&group.select(&rule, "")), ["b:1"]);
+
+        // Every server failing is better than none
+        group.servers[1].set_healthy(false);
+        assert_eq!(group.select(&rule, "").len(), 2);
+
+        sleep(Duration::from_millis(150));
+        assert!(server.is_available(Instant::now()));
+    }
+}
@@ -0,0 +1,107 @@
+//! Content Negotiation
+//!
+//! Selection of the content coding from the `Accept-Encoding` request header as defined on the
+//! [RFC](https://tools.ietf.org/html/rfc7231#section-5.3.4)
+
+/// Parses the Accept-Encoding values into content codings and their quality
+///
+/// Values without a `q` parameter have quality 1
+pub fn parse_accept_encoding(values: &[String]) -> Vec<(String, f32)> {
+    let mut codings: Vec<(String, f32)> = Vec::new();
+    for value in values {
+        let params: Vec<&str> = value.split(';').map(|param| param.trim()).collect();
+        if params[0].is_empty() {
+            continue;
+        }
+        let mut quality: f32 = 1.0;
+        for param in &params[1..] {
+            if let Some(value) = param.strip_prefix("q=") {
+                quality = value.parse::<f32>().unwrap_or(0.0);
+            }
+        }
+        codings.push((params[0].to_lowercase(), quality));
+    }
+    codings
+}
+
+/// Selects the best content coding from `available` (sorted in server preference) that the
+/// client accepts, None means the response must be sent without coding
+pub fn select_encoding(accept_encoding: &[String], available: &[String]) -> Option<String> {
+    let accepted = parse_accept_encoding(accept_encoding);
+    let quality_of = |coding: &str| -> f32 {
+        match accepted.iter().find(|(name, _quality)| name == coding) {
+            Some((_name, quality)) => *quality,
+            None => match accepted.iter().find(|(name, _quality)| name == "*") {
+                Some((_name, quality)) => *quality,
+                None => 0.0,
+            },
+        }
+    };
+
+    let mut best: Option<(String, f32)> = None;
+    for coding in available {
+        let quality = quality_of(coding);
+        if quality <= 0.0 {
+            continue;
+        }
+        match &best {
+            Some((_coding, best_quality)) if *best_quality >= quality => {}
+            _ => best = Some((coding.to_owned(), quality)),
+        }
+    }
+    best.map(|(coding, _quality)| coding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| (*value).to_owned()).collect()
+    }
+
+    fn select(accept_encoding: &[&str]) -> Option<String> {
+        select_encoding(&list(accept_encoding), &list(&["br", "gzip", "deflate"]))
+    }
+
+    #[test]
+    fn parses_qualities() {
+        let parsed = parse_accept_encoding(&list(&["GZIP", "br;q=0.5", "deflate ; q=0", "identity;q=bad", ""]));
+        assert_eq!(
+            parsed,
+            vec![
+                ("gzip".to_owned(), 1.0),
+                ("br".to_owned(), 0.5),
+                ("deflate".to_owned(), 0.0),
+                ("identity".to_owned(), 0.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn prefers_the_server_order_on_equal_qualities() {
+        assert_eq!(select(&["gzip", "deflate", "br"]), Some("br".to_owned()));
+        assert_eq!(select(&["gzip", "deflate"]), Some("gzip".to_owned()));
+    }
+
+    #[test]
+    fn prefers_higher_qualities() {
+        assert_eq!(select(&["br;q=0.5", "gzip;q=0.8"]), Some("gzip".to_owned()));
+        assert_eq!(select(&["br;q=0.1", "deflate"]), Some("deflate".to_owned()));
+    }
+
+    #[test]
+    fn skips_refused_codings() {
+        assert_eq!(select(&["br;q=0", "gzip"]), Some("gzip".to_owned()));
+        assert_eq!(select(&["*;q=0"]), None);
+        assert_eq!(select(&["identity"]), None);
+        assert_eq!(select(&[]), None);
+    }
+
+    #[test]
+    fn uses_the_wildcard_for_unlisted_codings() {
+        assert_eq!(select(&["*"]), Some("br".to_owned()));
+        assert_eq!(select(&["br;q=0", "*;q=0.5"]), Some("gzip".to_owned()));
+        assert_eq!(select(&["deflate", "*;q=0.5"]), Some("deflate".to_owned()));
+    }
+}
@@ -1,4 +1,14 @@
-use crate::request::utils::*;
+use std::fs::{self, File};
+use std::io::{Read, Result, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::request::multipart::MultipartParser;
+use crate::utils::log::log_warning;
+
+/// Counter that keeps temporary file names unique inside the process
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
 
 #[derive(Debug, Default)] // , PartialEq
 /// Struct that contains all data from request forms
@@ -17,7 +27,7 @@ impl FormData {
         let fields: Vec<&str> = data.rsplit("&").collect();
         for field in fields {
             if field.contains("=") {
-                let entry: Vec<&str> = field.split("=").collect();
+                let entry: Vec<&str> = field.splitn(2, "=").collect();
                 self.form_field.push(FormField {
                     name: percent_encoding::percent_decode(entry[0].as_bytes())
                         .decode_utf8_lossy()
@@ -31,27 +41,38 @@ impl FormData {
             }
         }
     }
-    /// Adds Multipart elemtent to FormData
-    pub fn add_multipart(&mut self, data: String, bounds: &String) {
-        let elements: Vec<&str> = data.split(bounds).collect();
-        for mut element_str in elements {
-            element_str = &element_str.trim_end_matches("\r\n--");
-            if element_str != "\r\n" || element_str != "" {
-                let content_disposition = "Content-Disposition: form-data; ";
-                if element_str.contains(content_disposition) {
-                    let element = MultipartFormElement::new(element_str.replace(content_disposition, "").to_owned());
-                    if element.is_file {
-                        self.multipart_file.push(MultipartFile::new(element));
+
+    /// Adds the fields and files of a multipart body to FormData reading it as it arrives
+    ///
+    /// At most `memory_limit` bytes of the whole body are kept in memory, parts that do not fit
+    /// in what is left are stored on temporary files
+    pub fn add_multipart<R: Read>(&mut self, body: R, boundary: &str, memory_limit: usize) -> Result<()> {
+        let mut parser = MultipartParser::new(body, boundary);
+        let mut memory_left = memory_limit;
+        while let Some(part) = parser.next_part()? {
+            let mut content = FileContent::new(memory_left);
+            parser.read_part(&mut content)?;
+            memory_left -= content.memory.len();
+            match part.filename {
+                Some(filename) => self.multipart_file.push(MultipartFile {
+                    name: part.name,
+                    filename,
+                    content_type: part.content_type,
+                    file: content,
+                }),
+                None => {
+                    if content.is_on_disk() {
+                        log_warning(&format!("Form field \"{}\" too large, ignored", part.name));
                     } else {
-                        self.form_field.push(FormField::new(element))
-                    }
-                } else {
-                    if element_str != "--" && element_str != "--\r\n" {
-                        self.other.push(element_str.to_owned());
+                        self.form_field.push(FormField {
+                            name: part.name,
+                            value: String::from_utf8_lossy(&content.memory).to_string(),
+                        });
                     }
                 }
             }
         }
+        Ok(())
     }
 }
 
@@ -64,20 +85,8 @@ pub struct MultipartFile {
     pub filename: String,
     /// Kind of file uploaded
     pub content_type: String,
-    /// File contents on bytes
-    pub file: Vec<u8>,
-}
-
-impl MultipartFile {
-    /// Creates new MultipartFile Struct from MultipartFormElement
-    pub fn new(element: MultipartFormElement) -> MultipartFile {
-        MultipartFile {
-            name: element.name,
-            filename: element.filename,
-            content_type: element.content_type,
-            file: element.file,
-        }
-    }
+    /// File contents
+    pub file: FileContent,
 }
 
 #[derive(Debug)]
@@ -89,70 +98,143 @@ pub struct FormField {
     pub value: String,
 }
 
-impl FormField {
-    /// Creates new FormField Struct from MultipartFormElement
-    pub fn new(element: MultipartFormElement) -> FormField {
-        FormField {
-            name: element.name,
-            value: element.content,
+#[derive(Debug)]
+/// Contents of an uploaded part, kept in memory until they grow over the limit and moved to a
+/// temporary file after that
+pub struct FileContent {
+    /// Contents while they fit in memory
+    memory: Vec<u8>,
+    /// Temporary file holding the contents once they are over the limit
+    temp_file: Option<TempFile>,
+    /// Maximum number of bytes kept in memory
+    memory_limit: usize,
+    /// Total number of bytes written
+    length: u64,
+}
+
+impl FileContent {
+    /// Creates empty contents that will be kept in memory up to `memory_limit` bytes
+    pub fn new(memory_limit: usize) -> FileContent {
+        FileContent {
+            memory: Vec::new(),
+            temp_file: None,
+            memory_limit,
+            length: 0,
         }
     }
-}
 
-#[derive(Debug, Default, PartialEq)]
-/// Structure that have each field in a MultiPartForm before processing them and splitting them on
-/// Files and Fields
-pub struct MultipartFormElement {
-    //pub data: Vec<String>,
-    name: String,
-    content: String,
-    is_file: bool,
-    filename: String,
-    content_type: String,
-    file: Vec<u8>,
-    other: Vec<String>,
-}
+    /// Size in bytes of the contents
+    pub fn size(&self) -> u64 {
+        self.length
+    }
 
-impl MultipartFormElement {
-    /// Creates new MultipartFormElement Struct from raw body content (String)
-    pub fn new(raw: String) -> MultipartFormElement {
-        let mut element = MultipartFormElement::default();
-        //element.data = ;
-        let data: Vec<&str> = raw.split("\r\n\r\n").collect();
-
-        let len = data.len();
-
-        if len > 1 {
-            let info: Vec<&str> = data[0].split("\r\n").collect();
-            for current in info {
-                if current.starts_with("Content-Type: ") {
-                    element.content_type = generate_field_string(&current.trim_start_matches("Content-Type: "));
-                    element.is_file = true;
-                } else {
-                    let inf: Vec<&str> = current.split("\"; ").collect();
-                    for i in inf {
-                        if i.starts_with("name=\"") {
-                            element.name = generate_field_string(&i.trim_start_matches("name=\""));
-                            element.name = element.name.replace("\"", "");
-                        } else if i.starts_with("filename=\"") {
-                            element.filename = generate_field_string(&i.trim_start_matches("filename=\""));
-                            element.filename = element.filename.replace("\"", "");
-                        } else {
-                            element.other.push(i.to_owned());
-                        }
-                    }
+    /// Checks if the contents were moved to a temporary file
+    pub fn is_on_disk(&self) -> bool {
+        self.temp_file.is_some()
+    }
+
+    /// Writes the contents to `target`, temporary files are moved instead of copied when
+    /// possible
+    pub fn persist(&self, target: &Path) -> Result<()> {
+        match &self.temp_file {
+            Some(temp_file) => {
+                // Renames fail between file systems
+                if fs::rename(&temp_file.path, target).is_err() {
+                    fs::copy(&temp_file.path, target)?;
                 }
+                Ok(())
             }
-        } /*else {  //Unreachable code?
-              for s in &data[2..] {
-                  element.other.push(s.to_string();
-              }
-          }*/
-        if element.is_file {
-            element.file = generate_field_vec_u8(&data[1]);
-        } else {
-            element.content = data[1].to_owned();
+            None => fs::write(target, &self.memory),
+        }
+    }
+}
+
+impl Write for FileContent {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if self.temp_file.is_none() && self.memory.len() + buf.len() > self.memory_limit {
+            let mut temp_file = TempFile::create()?;
+            temp_file.file.write_all(&self.memory)?;
+            self.memory = Vec::new();
+            self.temp_file = Some(temp_file);
         }
-        element
+        match &mut self.temp_file {
+            Some(temp_file) => temp_file.file.write_all(buf)?,
+            None => self.memory.extend_from_slice(buf),
+        }
+        self.length += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match &mut self.temp_file {
+            Some(temp_file) => temp_file.file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+/// File on the system temporary directory that is deleted when dropped
+pub struct TempFile {
+    path: PathBuf,
+    file: File,
+}
+
+impl TempFile {
+    /// Creates a new empty file with an unique name
+    fn create() -> Result<TempFile> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.subsec_nanos())
+            .unwrap_or(0);
+        let path = std::env::temp_dir().join(format!(
+            "akira-multipart-{}-{:x}-{}",
+            std::process::id(),
+            nanos,
+            TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = fs::OpenOptions::new().write(true).create_new(true).open(&path)?;
+        Ok(TempFile { path, file })
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        // The file is already gone if it was moved to its final location
+        fs::remove_file(&self.path).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shares_the_memory_limit_between_parts() {
+        let mut body = Vec::new();
+        for index in 0..4 {
+            body.extend_from_slice(
+                format!(
+                    "--xyz\r\nContent-Disposition: form-data; name=\"f{0}\"; filename=\"{0}.bin\"\r\n\r\n{1}\r\n",
+                    index,
+                    "a".repeat(40)
+                )
+                .as_bytes(),
+            );
+        }
+        body.extend_from_slice(b"--xyz\r\nContent-Disposition: form-data; name=\"field\"\r\n\r\nvalue\r\n--xyz--\r\n");
+
+        let mut form = FormData::default();
+        form.add_multipart(&body[..], "xyz", 100).unwrap();
+        let on_disk: Vec<bool> = form.multipart_file.iter().map(|file| file.file.is_on_disk()).collect();
+        assert_eq!(on_disk, [false, false, true, true]);
+        assert!(form.multipart_file.iter().all(|file| file.file.size() == 40));
+        // Small fields still fit in what is left
+        assert_eq!(form.form_field[0].value, "value");
+
+        let mut form = FormData::default();
+        form.add_multipart(&body[..], "xyz", 1000).unwrap();
+        assert!(form.multipart_file.iter().all(|file| !file.file.is_on_disk()));
+        assert_eq!(form.form_field[0].value, "value");
     }
 }
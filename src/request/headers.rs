@@ -1,4 +1,7 @@
+use crate::request::multipart::parse_params;
+use crate::request::reader::is_chunked;
 use crate::request::utils::*;
+use std::io;
 // https://en.wikipedia.org/wiki/List_of_HTTP_header_fields
 
 #[derive(Debug, Default)]
@@ -19,6 +22,8 @@ pub struct RequestHeaders {
     pub accept_datetime: String,
     /// Initiates a request for cross-origin resource sharing with Origin
     pub access_control_request_method: String,
+    /// Initiates a request for cross-origin resource sharing with the headers that will be sent
+    pub access_control_request_headers: String,
     /// Authentication credentials for HTTP authentication
     pub authorization: String,
     /// Used to specify directives that must be obeyed by all caching mechanisms along the
@@ -28,6 +33,8 @@ pub struct RequestHeaders {
     pub connection: String,
     /// The length of the request body in octets (8-bit bytes)
     pub content_length: u64,
+    /// Defines if the length was sent, a length of 0 is not the same as no length
+    pub has_content_length: bool,
     /// A Base64-encoded binary MD5 sum of the content of the request body
     pub content_md5: String,
     /// The Media type of the body of the request (used with POST and PUT requests)
@@ -47,6 +54,17 @@ pub struct RequestHeaders {
     /// The domain name of the server (for virtual hosting), and the TCP port number on which the
     /// server is listening
     pub host: String,
+    /// Only perform the action if the client supplied entity matches the same entity on the server
+    pub if_match: String,
+    /// Allows a 304 Not Modified to be returned if content is unchanged
+    pub if_modified_since: String,
+    /// Allows a 304 Not Modified to be returned if content is unchanged, see HTTP ETag
+    pub if_none_match: String,
+    /// If the entity is unchanged, send me the part(s) that I am missing; otherwise, send me the
+    /// entire new entity
+    pub if_range: String,
+    /// Only send the response if the entity has not been modified since a specific time
+    pub if_unmodified_since: String,
     /// Limit the number of times the message can be forwarded through proxies or gateways
     pub max_forwards: String,
     /// Initiates a request for cross-origin resource sharing (asks server for Access-Control-*
@@ -67,6 +85,8 @@ pub struct RequestHeaders {
     plus the "trailers" value (related to the "chunked" transfer method)
     to notify the server it expects to receive additional fields in the trailer after the last, zero-sized, chunk **/
     pub transfer_encodings: String,
+    /// The form of encoding used to safely transfer the request body
+    pub transfer_encoding: String,
     /// user agent Stringing **/
     pub user_agent: String,
 
@@ -89,6 +109,9 @@ pub struct RequestHeaders {
 
     /// Undefined headers
     pub other: Vec<String>,
+
+    /// Problem found on the headers that delimit the body, the request can not be read safely
+    pub framing_error: Option<&'static str>,
 }
 
 impl RequestHeaders {
@@ -102,127 +125,195 @@ impl RequestHeaders {
         for current in client_arr {
             parse_header(&mut headers, current);
         }
+        headers.framing_error = get_framing_error(raw);
 
         headers
     }
+
+    /// Fails with `ErrorKind::InvalidData` when the end of the body can not be known for sure,
+    /// such requests are refused and their connection closed
+    pub fn check_framing(&self) -> io::Result<()> {
+        match self.framing_error {
+            Some(error) => Err(io::Error::new(io::ErrorKind::InvalidData, error)),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks if the request announces a body
+    pub fn has_body(&self) -> bool {
+        self.content_length > 0 || is_chunked(&self.transfer_encoding)
+    }
+}
+
+/// Finds what keeps the body of a request from being delimited the same way by every reader:
+/// repeated or non numeric Content-Length values, transfer codings other than a single chunked
+/// and both headers at once
+fn get_framing_error(raw: &str) -> Option<&'static str> {
+    let mut lengths: Vec<&str> = Vec::new();
+    let mut codings: Vec<String> = Vec::new();
+    for line in raw.split("\r\n") {
+        let (name, value) = match line.split_once(':') {
+            Some(value) => value,
+            None => continue,
+        };
+        match name.trim().to_lowercase().as_str() {
+            "content-length" => lengths.push(value.trim()),
+            "transfer-encoding" => codings.extend(value.split(',').map(|coding| coding.trim().to_lowercase())),
+            _ => {}
+        }
+    }
+
+    if lengths.len() > 1 {
+        return Some("Repeated Content-Length header");
+    }
+    let valid_length =
+        |length: &&str| length.bytes().all(|byte| byte.is_ascii_digit()) && length.parse::<u64>().is_ok();
+    if !lengths.iter().all(valid_length) {
+        return Some("Invalid Content-Length header");
+    }
+    if !codings.is_empty() {
+        if !lengths.is_empty() {
+            return Some("Content-Length sent along Transfer-Encoding");
+        }
+        if codings != ["chunked"] {
+            return Some("Unsupported Transfer-Encoding");
+        }
+    }
+    None
 }
 
 fn parse_header(headers: &mut RequestHeaders, current: &str) {
-    let header: Vec<&str> = current.split(": ").collect();
+    let header: Vec<&str> = current.splitn(2, ':').collect();
 
     if header.len() == 2 {
-        match header[0] {
-            "A-IM" => {
-                headers.acceptable_instance_manipulations = header[1].to_owned();
+        // Field names are case-insensitive
+        let value = header[1].trim();
+        match header[0].trim().to_lowercase().as_str() {
+            "a-im" => {
+                headers.acceptable_instance_manipulations = value.to_owned();
             }
-            "Accept" => {
+            "accept" => {
                 //values = &values.replace(";", ",")[..];
-                let arr: Vec<&str> = header[1].split(";").collect();
+                let arr: Vec<&str> = value.split(";").collect();
 
                 for data in arr {
                     headers.accept.push(generate_field_string(data));
                 }
             }
-            "Accept-Charset" => {
-                headers.accept_charset = header[1].to_owned();
+            "accept-charset" => {
+                headers.accept_charset = value.to_owned();
+            }
+            "accept-encoding" => {
+                headers.accept_encoding = generate_field_string_vec(value);
             }
-            "Accept-Encoding" => {
-                headers.accept_encoding = generate_field_string_vec(header[1]);
+            "accept-language" => {
+                headers.accept_language = value.to_owned();
             }
-            "Accept-Language" => {
-                headers.accept_language = header[1].to_owned();
+            "accept-datetime" => {
+                headers.accept_datetime = value.to_owned();
             }
-            "Accept-Datetime" => {
-                headers.accept_datetime = header[1].to_owned();
+            "access-control-request-method" => {
+                headers.access_control_request_method = generate_field_string(value);
             }
-            "Access-Control-Request-Method" => {
-                headers.access_control_request_method = generate_field_string(header[1]);
+            "access-control-request-headers" => {
+                headers.access_control_request_headers = generate_field_string(value);
             }
-            "Authorization" => {
-                headers.authorization = generate_field_string(header[1]);
+            "authorization" => {
+                headers.authorization = generate_field_string(value);
             }
 
-            "Cache-Control" => {
-                headers.cache_control = generate_field_string(header[1]);
+            "cache-control" => {
+                headers.cache_control = generate_field_string(value);
             }
-            "Connection" => {
-                headers.connection = generate_field_string(header[1]);
+            "connection" => {
+                headers.connection = generate_field_string(value);
             }
-            "Content-Length" => {
-                headers.content_length = generate_field_u64(header[1]);
+            "content-length" => {
+                headers.content_length = generate_field_u64(value);
+                headers.has_content_length = true;
             }
-            "Content-MD5" => {
-                headers.content_md5 = generate_field_string(header[1]);
+            "content-md5" => {
+                headers.content_md5 = generate_field_string(value);
             }
-            "Content-Type" => {
-                headers.content_type = generate_field_string(header[1]);
-                let t: Vec<&str> = current.split("; ").collect();
-                if t.len() > 1 {
-                    let z = t[1];
-                    let bound_str = "boundary=";
-                    if z.starts_with(bound_str) {
-                        let bond_str_len = bound_str.len();
-                        let bounds = &z[bond_str_len..];
-                        headers.content_type = generate_field_string(headers.content_type.trim_end_matches(&bounds));
-                        headers.content_type =
-                            generate_field_string(headers.content_type.trim_end_matches("; boundary="));
-                        headers.content_bounds = generate_field_string(bounds);
+            "content-type" => {
+                headers.content_type = generate_field_string(value.split(';').next().unwrap_or("").trim());
+                for (name, param) in parse_params(value) {
+                    if name == "boundary" {
+                        headers.content_bounds = param;
                     }
                 }
             }
-            "Cookie" => {
-                headers.cookie = generate_field_string(header[1]);
+            "cookie" => {
+                headers.cookie = generate_field_string(value);
+            }
+            "date" => {
+                headers.date = generate_field_string(value);
+            }
+            "expect" => {
+                headers.expect = generate_field_string(value);
+            }
+            "forwarded" => {
+                headers.forwarded = generate_field_string(value);
+            }
+            "from" => {
+                headers.from = generate_field_string(value);
+            }
+            "host" => {
+                headers.host = generate_field_string(value);
+            }
+            "if-match" => {
+                headers.if_match = generate_field_string(value);
             }
-            "Date" => {
-                headers.date = generate_field_string(header[1]);
+            "if-modified-since" => {
+                headers.if_modified_since = generate_field_string(value);
             }
-            "Expect" => {
-                headers.expect = generate_field_string(header[1]);
+            "if-none-match" => {
+                headers.if_none_match = generate_field_string(value);
             }
-            "Forwarded" => {
-                headers.forwarded = generate_field_string(header[1]);
+            "if-unmodified-since" => {
+                headers.if_unmodified_since = generate_field_string(value);
             }
-            "From" => {
-                headers.from = generate_field_string(header[1]);
+            "if-range" => {
+                headers.if_range = generate_field_string(value);
             }
-            "Host" => {
-                headers.host = generate_field_string(header[1]);
+            "max-forwards" => {
+                headers.max_forwards = generate_field_string(value);
             }
-            "Max-Forwards" => {
-                headers.max_forwards = generate_field_string(header[1]);
+            "origin" => {
+                headers.origin = generate_field_string(value);
             }
-            "Origin" => {
-                headers.origin = generate_field_string(header[1]);
+            "pragma" => {
+                headers.pragma = generate_field_string(value);
             }
-            "Pragma" => {
-                headers.pragma = generate_field_string(header[1]);
+            "proxy-authorization" => {
+                headers.proxy_authorization = generate_field_string(value);
             }
-            "Proxy-Authorization" => {
-                headers.proxy_authorization = generate_field_string(header[1]);
+            "range" => {
+                headers.range = generate_field_string(value);
             }
-            "Range" => {
-                headers.range = generate_field_string(header[1]);
+            "referer" => {
+                headers.referer = generate_field_string(value);
             }
-            "Referer" => {
-                headers.referer = generate_field_string(header[1]);
+            "transfer-encoding" => {
+                headers.transfer_encoding = generate_field_string(value);
             }
-            "TE" => {
-                headers.transfer_encodings = generate_field_string(header[1]);
+            "te" => {
+                headers.transfer_encodings = generate_field_string(value);
             }
-            "User-Agent" => {
-                headers.user_agent = generate_field_string(header[1]);
+            "user-agent" => {
+                headers.user_agent = generate_field_string(value);
             }
-            "Via" => {
-                headers.via = generate_field_string(header[1]);
+            "via" => {
+                headers.via = generate_field_string(value);
             }
-            "Warning" => {
-                headers.warning = generate_field_string(header[1]);
+            "warning" => {
+                headers.warning = generate_field_string(value);
             }
-            "Upgrade-Insecure-Requests" => {
-                headers.upgrade_insecure_requests = generate_field_string(header[1]);
+            "upgrade-insecure-requests" => {
+                headers.upgrade_insecure_requests = generate_field_string(value);
             }
-            "DNT" => {
-                headers.dnt = generate_field_string(header[1]);
+            "dnt" => {
+                headers.dnt = generate_field_string(value);
             }
             _ => {
                 headers.other.push(generate_field_string(current));
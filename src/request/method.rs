@@ -54,4 +54,12 @@ impl Method {
             Method::Unsupported => "Unsupported",
         }
     }
+    /// Checks if sending the request more than once has the same effect as sending it once, as
+    /// defined on the [RFC](https://tools.ietf.org/html/rfc7231#section-4.2.2)
+    pub fn is_idempotent(&self) -> bool {
+        matches!(
+            self,
+            Method::DELETE | Method::GET | Method::HEAD | Method::OPTIONS | Method::PUT | Method::TRACE
+        )
+    }
 }
@@ -1,7 +1,12 @@
 //! # Request Module
+pub mod encoding;
 pub mod form;
 pub mod headers;
 pub mod method;
+pub mod multipart;
 pub mod other;
+pub mod path;
+pub mod range;
+pub mod reader;
 pub mod request;
 pub mod utils;
@@ -0,0 +1,383 @@
+//! Multipart Parser
+//!
+//! Byte oriented `multipart/form-data` parser as defined on the
+//! [RFC](https://tools.ietf.org/html/rfc7578), parts are read as they arrive so binary contents
+//! are kept intact and big files never need to fit in memory
+use std::io::{sink, Error, ErrorKind, Read, Result, Write};
+
+/// Size of each read from the body
+const READ_SIZE: usize = 8192;
+/// Maximum size in bytes of the headers of a single part
+const MAX_PART_HEADERS: usize = 16 * 1024;
+
+#[derive(Debug, Default)]
+/// Headers of a single part
+pub struct PartHeaders {
+    /// Field name from Content-Disposition
+    pub name: String,
+    /// File name from Content-Disposition, None for regular fields
+    pub filename: Option<String>,
+    /// Content-Type of the part
+    pub content_type: String,
+}
+
+/// Splits a multipart body into parts
+pub struct MultipartParser<R: Read> {
+    reader: R,
+    /// Bytes read from `reader` and not yet consumed
+    buffer: Vec<u8>,
+    /// `\r\n--boundary`
+    delimiter: Vec<u8>,
+    /// Defines if the first delimiter was already found
+    started: bool,
+    /// Defines if the contents of the current part are still on the reader
+    in_part: bool,
+    /// Defines if the closing delimiter was found
+    finished: bool,
+}
+
+impl<R: Read> MultipartParser<R> {
+    /// Creates a parser for the given boundary, quotes around it are removed
+    pub fn new(reader: R, boundary: &str) -> MultipartParser<R> {
+        let boundary = boundary.trim().trim_matches('"');
+        MultipartParser {
+            reader,
+            // The line break before the first delimiter is optional, adding it lets every
+            // delimiter be found the same way
+            buffer: b"\r\n".to_vec(),
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            started: false,
+            in_part: false,
+            finished: false,
+        }
+    }
+
+    /// Advances to the next part and returns its headers, None after the closing delimiter
+    ///
+    /// The contents of the previous part are skipped if they were not read
+    pub fn next_part(&mut self) -> Result<Option<PartHeaders>> {
+        if self.finished {
+            return Ok(None);
+        }
+        if !self.started {
+            // Preamble
+            self.read_part(&mut sink())?;
+            self.started = true;
+        } else if self.in_part {
+            self.read_part(&mut sink())?;
+        }
+
+        while self.buffer.len() < 2 {
+            if !self.fill()? {
+                return Err(unexpected_eof());
+            }
+        }
+        if self.buffer.starts_with(b"--") {
+            self.finished = true;
+            return Ok(None);
+        }
+
+        // Transport padding and line break after the delimiter
+        let mut headers = PartHeaders::default();
+        let padding = self.read_line()?;
+        if !padding.iter().all(|byte| *byte == b' ' || *byte == b'\t') {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid multipart delimiter"));
+        }
+        let mut headers_length = 0;
+        loop {
+            let line = self.read_line()?;
+            if line.is_empty() {
+                break;
+            }
+            headers_length += line.len();
+            if headers_length > MAX_PART_HEADERS {
+                return Err(Error::new(ErrorKind::InvalidData, "Multipart headers too large"));
+            }
+            parse_part_header(&mut headers, &String::from_utf8_lossy(&line));
+        }
+        self.in_part = true;
+        Ok(Some(headers))
+    }
+
+    /// Streams the contents of the current part into `writer`
+    pub fn read_part<W: Write>(&mut self, writer: &mut W) -> Result<u64> {
+        let mut written: u64 = 0;
+        loop {
+            if let Some(position) = find(&self.buffer, &self.delimiter) {
+                writer.write_all(&self.buffer[..position])?;
+                written += position as u64;
+                self.buffer.drain(..position + self.delimiter.len());
+                self.in_part = false;
+                return Ok(written);
+            }
+            // Keep enough bytes to find a delimiter split between two reads
+            let keep = self.delimiter.len() - 1;
+            if self.buffer.len() > keep {
+                let ready = self.buffer.len() - keep;
+                writer.write_all(&self.buffer[..ready])?;
+                written += ready as u64;
+                self.buffer.drain(..ready);
+            }
+            if !self.fill()? {
+                return Err(unexpected_eof());
+            }
+        }
+    }
+
+    /// Reads more bytes into the buffer, returns false when the body ended
+    fn fill(&mut self) -> Result<bool> {
+        let mut chunk = [0u8; READ_SIZE];
+        let read = self.reader.read(&mut chunk)?;
+        self.buffer.extend_from_slice(&chunk[..read]);
+        Ok(read > 0)
+    }
+
+    /// Reads a line from the buffer without the line break
+    fn read_line(&mut self) -> Result<Vec<u8>> {
+        loop {
+            if let Some(position) = self.buffer.iter().position(|byte| *byte == b'\n') {
+                let mut line: Vec<u8> = self.buffer.drain(..=position).collect();
+                line.pop();
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                return Ok(line);
+            }
+            if self.buffer.len() > MAX_PART_HEADERS {
+                return Err(Error::new(ErrorKind::InvalidData, "Multipart headers too large"));
+            }
+            if !self.fill()? {
+                return Err(unexpected_eof());
+            }
+        }
+    }
+}
+
+/// Stores the value of a part header in the struct
+fn parse_part_header(headers: &mut PartHeaders, line: &str) {
+    let header: Vec<&str> = line.splitn(2, ':').collect();
+    if header.len() != 2 {
+        return;
+    }
+    let value = header[1].trim();
+    match header[0].trim().to_lowercase().as_str() {
+        "content-disposition" => {
+            let mut filename_ext: Option<String> = None;
+            for (name, param) in parse_params(value) {
+                match name.as_str() {
+                    "name" => headers.name = param,
+                    "filename" => headers.filename = Some(param),
+                    "filename*" => filename_ext = decode_ext_value(&param),
+                    _ => {}
+                }
+            }
+            // filename* has preference over filename
+            if filename_ext.is_some() {
+                headers.filename = filename_ext;
+            }
+        }
+        "content-type" => {
+            headers.content_type = value.to_owned();
+        }
+        _ => {}
+    }
+}
+
+/// Parses the `; name=value` parameters of a header value, values can be quoted and contain
+/// escaped characters
+pub fn parse_params(value: &str) -> Vec<(String, String)> {
+    let mut params: Vec<(String, String)> = Vec::new();
+    let mut chars = value.chars().peekable();
+
+    // Skip the value before the first parameter
+    for c in chars.by_ref() {
+        if c == ';' {
+            break;
+        }
+    }
+
+    loop {
+        let mut name = String::new();
+        for c in chars.by_ref() {
+            if c == '=' || c == ';' {
+                break;
+            }
+            name.push(c);
+        }
+        let name = name.trim().to_lowercase();
+        if name.is_empty() && chars.peek().is_none() {
+            break;
+        }
+
+        while chars.peek() == Some(&' ') {
+            chars.next();
+        }
+        let mut param = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => {
+                        if let Some(escaped) = chars.next() {
+                            param.push(escaped);
+                        }
+                    }
+                    '"' => break,
+                    _ => param.push(c),
+                }
+            }
+            // Anything until the next parameter is ignored
+            for c in chars.by_ref() {
+                if c == ';' {
+                    break;
+                }
+            }
+        } else {
+            for c in chars.by_ref() {
+                if c == ';' {
+                    break;
+                }
+                param.push(c);
+            }
+            param = param.trim().to_owned();
+        }
+
+        if !name.is_empty() {
+            params.push((name, param));
+        }
+        if chars.peek().is_none() {
+            break;
+        }
+    }
+    params
+}
+
+/// Decodes an extended parameter value as defined on the
+/// [RFC](https://tools.ietf.org/html/rfc5987#section-3.2) like `UTF-8''%e2%82%ac%20rates`
+pub fn decode_ext_value(value: &str) -> Option<String> {
+    let parts: Vec<&str> = value.splitn(3, '\'').collect();
+    if parts.len() != 3 {
+        return None;
+    }
+    let bytes: Vec<u8> = percent_encoding::percent_decode(parts[2].as_bytes()).collect();
+    if parts[0].eq_ignore_ascii_case("utf-8") {
+        Some(String::from_utf8_lossy(&bytes).to_string())
+    } else if parts[0].eq_ignore_ascii_case("iso-8859-1") {
+        Some(bytes.iter().map(|byte| *byte as char).collect())
+    } else {
+        None
+    }
+}
+
+/// Finds the first position of `needle` on `haystack`
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if haystack.len() < needle.len() {
+        return None;
+    }
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// Error for bodies that end before the closing delimiter
+fn unexpected_eof() -> Error {
+    Error::new(ErrorKind::UnexpectedEof, "Multipart body ended before the closing delimiter")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reader that returns one byte at a time so delimiters are split between reads
+    struct OneByte<'a>(&'a [u8]);
+
+    impl<'a> Read for OneByte<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            if self.0.is_empty() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.0[0];
+            self.0 = &self.0[1..];
+            Ok(1)
+        }
+    }
+
+    /// Reads every part as its headers and contents
+    fn parse_all<R: Read>(reader: R, boundary: &str) -> Result<Vec<(PartHeaders, Vec<u8>)>> {
+        let mut parser = MultipartParser::new(reader, boundary);
+        let mut parts = Vec::new();
+        while let Some(headers) = parser.next_part()? {
+            let mut contents = Vec::new();
+            parser.read_part(&mut contents)?;
+            parts.push((headers, contents));
+        }
+        Ok(parts)
+    }
+
+    const BODY: &[u8] = b"preamble\r\n--xyz\r\n\
+Content-Disposition: form-data; name=\"field\"\r\n\r\n\
+value\r\n--xyz \t\r\n\
+Content-Disposition: form-data; name=\"file\"; filename=\"a.bin\"\r\n\
+Content-Type: application/octet-stream\r\n\r\n\
+line\r\n--xy\r\n\0\xff\r\n\r\n--xyz--\r\nepilogue";
+
+    #[test]
+    fn parses_fields_and_binary_files() {
+        let parts = parse_all(BODY, "xyz").unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].0.name, "field");
+        assert_eq!(parts[0].0.filename, None);
+        assert_eq!(parts[0].1, b"value");
+        assert_eq!(parts[1].0.name, "file");
+        assert_eq!(parts[1].0.filename, Some("a.bin".to_owned()));
+        assert_eq!(parts[1].0.content_type, "application/octet-stream");
+        // Line breaks and partial delimiters inside the contents are kept
+        assert_eq!(parts[1].1, b"line\r\n--xy\r\n\0\xff\r\n");
+    }
+
+    #[test]
+    fn finds_delimiters_split_between_reads() {
+        let parts = parse_all(OneByte(BODY), "xyz").unwrap();
+        assert_eq!(parts[0].1, b"value");
+        assert_eq!(parts[1].1, b"line\r\n--xy\r\n\0\xff\r\n");
+    }
+
+    #[test]
+    fn removes_quotes_from_the_boundary() {
+        let body = b"--a:b\r\nContent-Disposition: form-data; name=\"x\"\r\n\r\n1\r\n--a:b--";
+        let parts = parse_all(&body[..], "\"a:b\"").unwrap();
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].1, b"1");
+    }
+
+    #[test]
+    fn prefers_extended_file_names() {
+        let mut headers = PartHeaders::default();
+        parse_part_header(
+            &mut headers,
+            "Content-Disposition: form-data; filename*=UTF-8''%e2%82%ac%20rates.txt; filename=\"rates.txt\"",
+        );
+        assert_eq!(headers.filename, Some("\u{20ac} rates.txt".to_owned()));
+        assert_eq!(decode_ext_value("iso-8859-1'en'%E9t%E9"), Some("\u{e9}t\u{e9}".to_owned()));
+        assert_eq!(decode_ext_value("utf-16''x"), None);
+        assert_eq!(decode_ext_value("x"), None);
+    }
+
+    #[test]
+    fn parses_quoted_params() {
+        let params = parse_params("form-data; name=\"a\\\"b; c\"; filename=plain.txt ;x=\"\"");
+        assert_eq!(
+            params,
+            vec![
+                ("name".to_owned(), "a\"b; c".to_owned()),
+                ("filename".to_owned(), "plain.txt".to_owned()),
+                ("x".to_owned(), String::new())
+            ]
+        );
+    }
+
+    #[test]
+    fn fails_without_closing_delimiter() {
+        let body = b"--xyz\r\nContent-Disposition: form-data; name=\"x\"\r\n\r\nvalue";
+        let err = parse_all(&body[..], "xyz").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+}
@@ -1,5 +1,5 @@
+use crate::connection::stream::Stream;
 use crate::utils::log::log_error;
-use std::net::TcpStream;
 
 #[derive(Debug, Default)]
 pub struct Other {
@@ -8,16 +8,18 @@ pub struct Other {
 }
 
 impl Other {
-    pub fn parse(stream: &TcpStream) -> Other {
+    pub fn parse(stream: &Stream) -> Other {
         match stream.peer_addr() {
-            Ok(remote_addr) => Other {
+            Some(Ok(remote_addr)) => Other {
                 remote_ip: remote_addr.ip().to_string(),
                 remote_port: remote_addr.port().to_string(),
             },
-            Err(error) => {
+            Some(Err(error)) => {
                 log_error(&error);
                 Other::default()
             }
+            // Unix sockets
+            None => Other::default(),
         }
     }
 }
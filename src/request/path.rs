@@ -0,0 +1,283 @@
+//! Path Normalization
+//!
+//! Turns the request target into a clean absolute path and checks that the files it points to
+//! stay inside the root folder
+use std::fs;
+use std::path::PathBuf;
+
+use crate::response::status::HttpStatus;
+
+#[derive(Debug, PartialEq)]
+/// Reasons to refuse a request path
+pub enum PathError {
+    /// The path is malformed: invalid encoding, NUL bytes, backslashes or `..` above the root
+    Invalid,
+    /// The path is well formed but resolves outside the root folder or through a forbidden
+    /// symlink
+    Forbidden,
+}
+
+impl PathError {
+    /// Status sent to the client for this error
+    pub fn status(&self) -> HttpStatus {
+        match self {
+            PathError::Invalid => HttpStatus::BadRequest,
+            PathError::Forbidden => HttpStatus::Forbidden,
+        }
+    }
+}
+
+/// Splits the request target in its decoded and normalized path and its raw query string
+///
+/// Segments are decoded before being normalized so encoded dots and slashes are handled like
+/// plain ones, `.` segments and repeated slashes are removed and `..` segments remove the
+/// previous one, going above the root is an error
+pub fn normalize_path(target: &str) -> Result<(String, String), PathError> {
+    if target == "*" {
+        return Ok((target.to_owned(), String::new()));
+    }
+
+    // Absolute form, used when talking to proxies
+    let mut target = target;
+    for scheme in &["http://", "https://"] {
+        if target.get(..scheme.len()).is_some_and(|value| value.eq_ignore_ascii_case(scheme)) {
+            let rest = &target[scheme.len()..];
+            target = match rest.find('/') {
+                Some(position) => &rest[position..],
+                None => "/",
+            };
+        }
+    }
+
+    let target = target.split('#').next().unwrap_or("");
+    let mut parts = target.splitn(2, '?');
+    let raw_path = parts.next().unwrap_or("");
+    let query = parts.next().unwrap_or("").to_owned();
+    if !raw_path.starts_with('/') {
+        return Err(PathError::Invalid);
+    }
+
+    let decoded = match percent_encoding::percent_decode(raw_path.as_bytes()).decode_utf8() {
+        Ok(value) => value,
+        Err(_err) => return Err(PathError::Invalid),
+    };
+    if decoded.contains('\0') || decoded.contains('\\') {
+        return Err(PathError::Invalid);
+    }
+
+    let mut segments: Vec<&str> = Vec::new();
+    let mut is_directory = false;
+    for segment in decoded.split('/') {
+        is_directory = true;
+        match segment {
+            "" | "." => {}
+            ".." => {
+                if segments.pop().is_none() {
+                    return Err(PathError::Invalid);
+                }
+            }
+            _ => {
+                segments.push(segment);
+                is_directory = false;
+            }
+        }
+    }
+
+    let mut path = String::from("/");
+    path.push_str(&segments.join("/"));
+    if is_directory && !segments.is_empty() {
+        path.push('/');
+    }
+    Ok((path, query))
+}
+
+/// Checks if a path has `..` segments, also when they are percent encoded
+pub fn has_parent_segments(path: &str) -> bool {
+    let decoded = percent_encoding::percent_decode(path.as_bytes()).decode_utf8_lossy();
+    decoded.split('/').any(|segment| segment == "..")
+}
+
+/// Encodes a decoded path to send it on a Location header or parse it again, `%` is encoded
+/// too so the path decodes back to itself
+pub fn encode_path(path: &str) -> String {
+    percent_encoding::utf8_percent_encode(&path.replace('%', "%25"), percent_encoding::DEFAULT_ENCODE_SET).to_string()
+}
+
+/// Obtains the local path of a normalized request path, checking that it does not leave `root`
+///
+/// The path returned starts at the canonical root and has the followed symlinks resolved
+///
+/// Every existing component is checked, symlinks are refused unless `follow_symlinks` is set
+/// and even then their target has to be inside the canonical root, components that do not
+/// exist yet (like files being uploaded) are accepted
+pub fn resolve_path(root: &str, path: &str, follow_symlinks: bool) -> Result<PathBuf, PathError> {
+    let canonical_root = match fs::canonicalize(root) {
+        Ok(value) => value,
+        Err(_err) => return Err(PathError::Forbidden),
+    };
+
+    let mut current = canonical_root.clone();
+    let mut segments = path.split('/').filter(|segment| !segment.is_empty());
+    for segment in segments.by_ref() {
+        current.push(segment);
+        let metadata = match fs::symlink_metadata(&current) {
+            Ok(value) => value,
+            // Nothing below a missing component can be a symlink
+            Err(_err) => break,
+        };
+        if metadata.file_type().is_symlink() {
+            if !follow_symlinks {
+                return Err(PathError::Forbidden);
+            }
+            current = match fs::canonicalize(&current) {
+                Ok(value) => value,
+                // Broken link
+                Err(_err) => return Err(PathError::Forbidden),
+            };
+            if !current.starts_with(&canonical_root) {
+                return Err(PathError::Forbidden);
+            }
+        }
+    }
+
+    current.extend(segments);
+    // A trailing `/` is kept so files are not found as directories
+    if path.ends_with('/') {
+        current.push("");
+    }
+    Ok(current)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(target: &str) -> Result<String, PathError> {
+        normalize_path(target).map(|(path, _query)| path)
+    }
+
+    #[test]
+    fn normalizes_clean_paths() {
+        assert_eq!(normalize("/"), Ok("/".to_owned()));
+        assert_eq!(normalize("/index.html"), Ok("/index.html".to_owned()));
+        assert_eq!(normalize("/a/./b/../c"), Ok("/a/c".to_owned()));
+        assert_eq!(normalize("//a///b/"), Ok("/a/b/".to_owned()));
+        assert_eq!(normalize("/a/b/.."), Ok("/a/".to_owned()));
+        assert_eq!(normalize("/a/.."), Ok("/".to_owned()));
+        assert_eq!(normalize("/my%20file.txt"), Ok("/my file.txt".to_owned()));
+        assert_eq!(normalize("/%E2%82%AC"), Ok("/€".to_owned()));
+        assert_eq!(normalize("*"), Ok("*".to_owned()));
+    }
+
+    #[test]
+    fn splits_query_and_fragment() {
+        assert_eq!(
+            normalize_path("/a/b?x=1&y=/../..#top"),
+            Ok(("/a/b".to_owned(), "x=1&y=/../..".to_owned()))
+        );
+        assert_eq!(normalize_path("/a#b?c"), Ok(("/a".to_owned(), String::new())));
+    }
+
+    #[test]
+    fn accepts_absolute_form() {
+        assert_eq!(normalize("http://example.com/a/../b"), Ok("/b".to_owned()));
+        assert_eq!(normalize("HTTPS://example.com"), Ok("/".to_owned()));
+        assert_eq!(normalize("http://example.com/../etc/passwd"), Err(PathError::Invalid));
+    }
+
+    #[test]
+    fn rejects_traversal_payloads() {
+        let payloads = [
+            "/..",
+            "/../",
+            "/../etc/passwd",
+            "/a/../../etc/passwd",
+            "/a/b/../../../etc/passwd",
+            "/./../etc/passwd",
+            "/%2e%2e/etc/passwd",
+            "/%2E%2E/etc/passwd",
+            "/.%2e/etc/passwd",
+            "/%2e./etc/passwd",
+            "/a/%2e%2e/%2e%2e/etc/passwd",
+            "/..%2fetc%2fpasswd",
+            "/%2e%2e%2f%2e%2e%2fetc%2fpasswd",
+            "/a%2f..%2f..%2fetc%2fpasswd",
+            "/%252e%252e/../../etc/passwd",
+        ];
+        for payload in payloads.iter() {
+            assert_eq!(normalize(payload), Err(PathError::Invalid), "{}", payload);
+        }
+    }
+
+    #[test]
+    fn double_encoding_is_decoded_once() {
+        assert_eq!(normalize("/%252e%252e/etc"), Ok("/%2e%2e/etc".to_owned()));
+    }
+
+    #[test]
+    fn rejects_nul_and_backslashes() {
+        let payloads = [
+            "/file.txt%00.html",
+            "/%00",
+            "/..\\..\\windows\\win.ini",
+            "/..%5c..%5cwindows%5cwin.ini",
+            "/a%5C..%5C..%5Cetc",
+            "/\\\\server\\share",
+        ];
+        for payload in payloads.iter() {
+            assert_eq!(normalize(payload), Err(PathError::Invalid), "{}", payload);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_encodings() {
+        let payloads = [
+            // Overlong UTF-8 encodings of `.` and `/`
+            "/%c0%ae%c0%ae/etc/passwd",
+            "/%c0%af..%c0%afetc",
+            "/%e0%80%ae%e0%80%ae/etc",
+            "/%ff",
+            "relative/path",
+            "",
+        ];
+        for payload in payloads.iter() {
+            assert_eq!(normalize(payload), Err(PathError::Invalid), "{}", payload);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn jails_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let base = std::env::temp_dir().join(format!("akira-path-test-{}", std::process::id()));
+        let root = base.join("root");
+        let outside = base.join("outside");
+        fs::create_dir_all(root.join("dir")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(root.join("dir/file.txt"), b"inside").unwrap();
+        fs::write(outside.join("secret.txt"), b"outside").unwrap();
+        symlink(&outside, root.join("escape")).unwrap();
+        symlink(root.join("dir"), root.join("inner")).unwrap();
+        symlink(base.join("missing"), root.join("broken")).unwrap();
+        let root_str = root.to_str().unwrap();
+        let root = fs::canonicalize(&root).unwrap();
+
+        assert_eq!(
+            resolve_path(root_str, "/dir/file.txt", false),
+            Ok(root.join("dir/file.txt"))
+        );
+        assert_eq!(resolve_path(root_str, "/dir/new.txt", false), Ok(root.join("dir/new.txt")));
+        assert_eq!(resolve_path(root_str, "/", false), Ok(root.clone()));
+        assert!(resolve_path(root_str, "/dir/file.txt/", false).unwrap().metadata().is_err());
+
+        assert_eq!(resolve_path(root_str, "/escape/secret.txt", false), Err(PathError::Forbidden));
+        assert_eq!(resolve_path(root_str, "/escape/secret.txt", true), Err(PathError::Forbidden));
+        assert_eq!(resolve_path(root_str, "/escape/new.txt", true), Err(PathError::Forbidden));
+        assert_eq!(resolve_path(root_str, "/inner/file.txt", false), Err(PathError::Forbidden));
+        assert_eq!(resolve_path(root_str, "/inner/file.txt", true), Ok(root.join("dir/file.txt")));
+        assert_eq!(resolve_path(root_str, "/broken", true), Err(PathError::Forbidden));
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
@@ -0,0 +1,239 @@
+//! Byte Ranges
+//!
+//! Parsing of the `Range` request header as defined on the
+//! [RFC](https://tools.ietf.org/html/rfc7233#section-2.1)
+
+/// Maximum number of ranges accepted on a single request, more than that will be served as a
+/// full response
+pub const MAX_RANGES: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Range of bytes of a resource, both ends included
+pub struct ByteRange {
+    /// First byte of the range
+    pub start: u64,
+    /// Last byte of the range
+    pub end: u64,
+}
+
+impl ByteRange {
+    /// Number of bytes in the range
+    pub fn length(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// Value for the `Content-Range` header of this range
+    pub fn content_range(&self, total_length: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, total_length)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+/// Body of a `multipart/byteranges` response, sent as each part header followed by its range
+/// of the file and then the closing boundary
+pub struct MultipartRanges {
+    /// Header of each part with the range sent after it
+    pub parts: Vec<(String, ByteRange)>,
+    pub closing: String,
+    /// Length of the whole body
+    pub length: u64,
+}
+
+impl MultipartRanges {
+    /// Builds the parts of the ranges of a resource of `total_length` bytes, the Content-Type of
+    /// the parts is left out when `mime` is empty
+    pub fn new(ranges: &[ByteRange], boundary: &str, mime: &str, total_length: u64) -> MultipartRanges {
+        let mut parts: Vec<(String, ByteRange)> = Vec::new();
+        let mut length: u64 = 0;
+        for range in ranges {
+            let mut part = format!("\r\n--{}\r\n", boundary);
+            if !mime.is_empty() {
+                part += &format!("Content-Type: {}\r\n", mime);
+            }
+            part += &format!("Content-Range: {}\r\n\r\n", range.content_range(total_length));
+            length += part.len() as u64 + range.length();
+            parts.push((part, *range));
+        }
+        let closing = format!("\r\n--{}--\r\n", boundary);
+        length += closing.len() as u64;
+        MultipartRanges { parts, closing, length }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+/// What part of the resource the client asked for
+pub enum RangeRequest {
+    /// The whole resource, no range or a range that must be ignored
+    Full,
+    /// One or more satisfiable ranges
+    Partial(Vec<ByteRange>),
+    /// None of the requested ranges overlap the resource
+    Unsatisfiable,
+}
+
+impl RangeRequest {
+    /// Parse Range header value for a resource of `total_length` bytes
+    ///
+    /// Invalid or unknown range units are ignored as the RFC requires
+    pub fn parse(range: &str, total_length: u64) -> RangeRequest {
+        let range = range.trim();
+        if !range.starts_with("bytes=") {
+            return RangeRequest::Full;
+        }
+
+        let specs: Vec<&str> = range["bytes=".len()..].split(',').map(|spec| spec.trim()).collect();
+        if specs.len() > MAX_RANGES {
+            return RangeRequest::Full;
+        }
+
+        let mut ranges: Vec<ByteRange> = Vec::new();
+        let mut specs_found = 0;
+        for spec in specs {
+            if spec.is_empty() {
+                continue;
+            }
+            specs_found += 1;
+            let bounds: Vec<&str> = spec.splitn(2, '-').collect();
+            if bounds.len() != 2 {
+                return RangeRequest::Full;
+            }
+            let (first, last) = (bounds[0].trim(), bounds[1].trim());
+            if first.is_empty() {
+                // Suffix range, the last N bytes
+                let suffix = match last.parse::<u64>() {
+                    Ok(value) => value,
+                    Err(_err) => return RangeRequest::Full,
+                };
+                if suffix > 0 && total_length > 0 {
+                    ranges.push(ByteRange {
+                        start: total_length - suffix.min(total_length),
+                        end: total_length - 1,
+                    });
+                }
+            } else {
+                let start = match first.parse::<u64>() {
+                    Ok(value) => value,
+                    Err(_err) => return RangeRequest::Full,
+                };
+                let end = if last.is_empty() {
+                    u64::MAX
+                } else {
+                    match last.parse::<u64>() {
+                        Ok(value) => value,
+                        Err(_err) => return RangeRequest::Full,
+                    }
+                };
+                if end < start {
+                    return RangeRequest::Full;
+                }
+                if start < total_length {
+                    ranges.push(ByteRange {
+                        start,
+                        end: end.min(total_length - 1),
+                    });
+                }
+            }
+        }
+
+        if specs_found == 0 {
+            RangeRequest::Full
+        } else if ranges.is_empty() {
+            RangeRequest::Unsatisfiable
+        } else if ranges.iter().map(|range| range.length()).sum::<u64>() > total_length {
+            // Overlapping ranges asking for more than the resource are served as a full response
+            RangeRequest::Full
+        } else {
+            RangeRequest::Partial(merge_ranges(ranges))
+        }
+    }
+}
+
+/// Sorts the ranges and joins the ones that overlap or are adjacent
+fn merge_ranges(mut ranges: Vec<ByteRange>) -> Vec<ByteRange> {
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end.saturating_add(1) => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn partial(ranges: &[(u64, u64)]) -> RangeRequest {
+        RangeRequest::Partial(ranges.iter().map(|&(start, end)| ByteRange { start, end }).collect())
+    }
+
+    #[test]
+    fn parses_single_ranges() {
+        assert_eq!(RangeRequest::parse("bytes=0-99", 1000), partial(&[(0, 99)]));
+        assert_eq!(RangeRequest::parse("bytes=500-", 1000), partial(&[(500, 999)]));
+        assert_eq!(RangeRequest::parse("bytes=-100", 1000), partial(&[(900, 999)]));
+        assert_eq!(RangeRequest::parse("bytes=900-5000", 1000), partial(&[(900, 999)]));
+        assert_eq!(RangeRequest::parse("bytes=-5000", 1000), partial(&[(0, 999)]));
+    }
+
+    #[test]
+    fn parses_multiple_ranges() {
+        assert_eq!(
+            RangeRequest::parse("bytes=0-0, 10-19 ,-1", 100),
+            partial(&[(0, 0), (10, 19), (99, 99)])
+        );
+        // Unsatisfiable ranges are dropped when others are satisfiable
+        assert_eq!(RangeRequest::parse("bytes=0-9,200-300", 100), partial(&[(0, 9)]));
+    }
+
+    #[test]
+    fn merges_overlapping_ranges() {
+        assert_eq!(RangeRequest::parse("bytes=50-59,0-9,5-19", 100), partial(&[(0, 19), (50, 59)]));
+        assert_eq!(RangeRequest::parse("bytes=0-9,10-19,-80", 100), partial(&[(0, 99)]));
+        assert_eq!(RangeRequest::parse("bytes=10-19,12-15", 100), partial(&[(10, 19)]));
+        // Asking for more bytes than the resource has
+        assert_eq!(RangeRequest::parse("bytes=0-59,10-69", 100), RangeRequest::Full);
+        let many = format!("bytes={}", ["0-9"; 11].join(","));
+        assert_eq!(RangeRequest::parse(&many, 100), RangeRequest::Full);
+        assert_eq!(RangeRequest::parse("bytes=0-9,200-300", 100), partial(&[(0, 9)]));
+    }
+
+    #[test]
+    fn ignores_invalid_ranges() {
+        assert_eq!(RangeRequest::parse("", 100), RangeRequest::Full);
+        assert_eq!(RangeRequest::parse("items=0-9", 100), RangeRequest::Full);
+        assert_eq!(RangeRequest::parse("bytes=9-0", 100), RangeRequest::Full);
+        assert_eq!(RangeRequest::parse("bytes=a-9", 100), RangeRequest::Full);
+        assert_eq!(RangeRequest::parse("bytes=5", 100), RangeRequest::Full);
+        assert_eq!(RangeRequest::parse("bytes=,", 100), RangeRequest::Full);
+        let many = format!("bytes={}", vec!["0-0"; MAX_RANGES + 1].join(","));
+        assert_eq!(RangeRequest::parse(&many, 100), RangeRequest::Full);
+    }
+
+    #[test]
+    fn detects_unsatisfiable_ranges() {
+        assert_eq!(RangeRequest::parse("bytes=100-", 100), RangeRequest::Unsatisfiable);
+        assert_eq!(RangeRequest::parse("bytes=-0", 100), RangeRequest::Unsatisfiable);
+        assert_eq!(RangeRequest::parse("bytes=0-", 0), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn builds_multipart_byteranges() {
+        let ranges = [ByteRange { start: 0, end: 1 }, ByteRange { start: 5, end: 9 }];
+        let multipart = MultipartRanges::new(&ranges, "b", "text/plain", 10);
+        assert_eq!(multipart.parts[0].0, "\r\n--b\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/10\r\n\r\n");
+        assert_eq!(multipart.parts[1].0, "\r\n--b\r\nContent-Type: text/plain\r\nContent-Range: bytes 5-9/10\r\n\r\n");
+        assert_eq!(multipart.parts[1].1, ranges[1]);
+        assert_eq!(multipart.closing, "\r\n--b--\r\n");
+        let headers: usize = multipart.parts.iter().map(|(part, _range)| part.len()).sum();
+        assert_eq!(multipart.length, (headers + multipart.closing.len()) as u64 + 2 + 5);
+    }
+
+    #[test]
+    fn leaves_out_unknown_content_types() {
+        let multipart = MultipartRanges::new(&[ByteRange { start: 0, end: 0 }], "b", "", 1);
+        assert_eq!(multipart.parts[0].0, "\r\n--b\r\nContent-Range: bytes 0-0/1\r\n\r\n");
+    }
+}
@@ -0,0 +1,259 @@
+//! Request Framing
+//!
+//! Reads exactly one request from the connection: the head until the empty line that ends it,
+//! then the body as announced by `Content-Length` or `Transfer-Encoding: chunked`
+use std::io::{copy, BufRead, Error, ErrorKind, Read, Result, Write};
+
+use crate::request::headers::RequestHeaders;
+use crate::response::status::HttpStatus;
+
+/// Maximum size in bytes accepted for the request line plus all the headers
+pub const MAX_HEAD_BYTES: usize = 64 * 1024;
+/// Maximum size in bytes of the bodies read only to be dropped, bigger ones are refused
+pub const MAX_DISCARDED_BODY_BYTES: u64 = 64 * 1024;
+
+/// Reads the request line and the headers, stops after the empty line that ends them
+///
+/// Empty lines before the request line are ignored as recommended by the
+/// [RFC](https://tools.ietf.org/html/rfc7230#section-3.5), they count toward `MAX_HEAD_BYTES`
+/// like the rest of the head
+pub fn read_head<R: BufRead>(reader: &mut R) -> Result<Vec<u8>> {
+    let mut head: Vec<u8> = Vec::new();
+    let mut consumed: usize = 0;
+    loop {
+        let mut line: Vec<u8> = Vec::new();
+        // Lines are only buffered up to the limit, even without a line terminator
+        let limit = (MAX_HEAD_BYTES - consumed + 1) as u64;
+        let read = reader.by_ref().take(limit).read_until(b'\n', &mut line)?;
+        if read == 0 {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "Connection closed before the end of the headers"));
+        }
+        consumed += read;
+        if consumed > MAX_HEAD_BYTES {
+            return Err(Error::new(ErrorKind::InvalidData, "Request headers too large"));
+        }
+        if line == b"\r\n" || line == b"\n" {
+            if head.is_empty() {
+                continue;
+            }
+            return Ok(head);
+        }
+        head.extend_from_slice(&line);
+    }
+}
+
+/// Reads the body announced by the headers as it arrives, decoding the chunked transfer coding
+///
+/// Reads fail with `ErrorKind::InvalidInput` once the body goes over `max_length` and with
+/// `ErrorKind::UnexpectedEof` if the connection is closed before the end of the body
+pub struct BodyReader<'a, R: BufRead> {
+    reader: &'a mut R,
+    chunked: bool,
+    /// Bytes left of the current chunk, or of the whole body if it is not chunked
+    remaining: u64,
+    /// Defines if the data of a chunk was already read and its CRLF is pending
+    in_chunk: bool,
+    finished: bool,
+    max_length: u64,
+    /// Total body bytes read
+    read: u64,
+}
+
+impl<'a, R: BufRead> BodyReader<'a, R> {
+    /// Creates a reader for the body announced by the headers
+    pub fn new(reader: &'a mut R, headers: &RequestHeaders, max_length: u64) -> BodyReader<'a, R> {
+        let chunked = is_chunked(&headers.transfer_encoding);
+        BodyReader {
+            reader,
+            chunked,
+            remaining: if chunked { 0 } else { headers.content_length },
+            in_chunk: false,
+            finished: false,
+            max_length,
+            read: 0,
+        }
+    }
+
+    /// Reads the next chunk size line, returns false after the last chunk and its trailer
+    fn next_chunk(&mut self) -> Result<bool> {
+        if self.in_chunk && !read_line(self.reader)?.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidData, "Chunk data not followed by CRLF"));
+        }
+        let line = read_line(self.reader)?;
+        let size_str = line.split(';').next().unwrap_or("").trim();
+        let size = match u64::from_str_radix(size_str, 16) {
+            Ok(value) => value,
+            Err(_err) => {
+                return Err(Error::new(ErrorKind::InvalidData, "Invalid chunk size"));
+            }
+        };
+        if size == 0 {
+            // Trailer
+            let mut trailer_bytes = 0;
+            loop {
+                let line = read_line(self.reader)?;
+                if line.is_empty() {
+                    return Ok(false);
+                }
+                trailer_bytes += line.len();
+                if trailer_bytes > MAX_HEAD_BYTES {
+                    return Err(Error::new(ErrorKind::InvalidData, "Chunked trailer too large"));
+                }
+            }
+        }
+        self.remaining = size;
+        self.in_chunk = true;
+        Ok(true)
+    }
+}
+
+impl<'a, R: BufRead> Read for BodyReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.finished || buf.is_empty() {
+            return Ok(0);
+        }
+        if !self.chunked && self.remaining > self.max_length - self.read {
+            return Err(too_large());
+        }
+        if self.remaining == 0 {
+            if !self.chunked || !self.next_chunk()? {
+                self.finished = true;
+                return Ok(0);
+            }
+            if self.remaining > self.max_length - self.read {
+                return Err(too_large());
+            }
+        }
+        let max = std::cmp::min(buf.len() as u64, self.remaining) as usize;
+        let read = self.reader.read(&mut buf[..max])?;
+        if read == 0 {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "Connection closed before the end of the body"));
+        }
+        self.remaining -= read as u64;
+        self.read += read as u64;
+        Ok(read)
+    }
+}
+
+/// Streams the body announced by the headers into `writer` without holding it in memory
+///
+/// Fails with `ErrorKind::InvalidInput` if the body is bigger than `max_length`
+pub fn copy_body<R: BufRead, W: Write>(
+    reader: &mut R,
+    headers: &RequestHeaders,
+    writer: &mut W,
+    max_length: u64,
+) -> Result<u64> {
+    copy(&mut BodyReader::new(reader, headers, max_length), writer)
+}
+
+/// Checks if the last transfer coding is chunked
+pub fn is_chunked(transfer_encoding: &str) -> bool {
+    transfer_encoding
+        .rsplit(',')
+        .next()
+        .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"))
+}
+
+/// Status sent to the client when its body can not be read
+pub fn get_body_error_status(error: &Error) -> HttpStatus {
+    match error.kind() {
+        ErrorKind::InvalidInput => HttpStatus::RequestEntityTooLarge,
+        ErrorKind::UnexpectedEof | ErrorKind::InvalidData | ErrorKind::TimedOut | ErrorKind::WouldBlock => {
+            HttpStatus::BadRequest
+        }
+        _ => HttpStatus::InternalServerError,
+    }
+}
+
+/// Error for bodies over the size limit
+fn too_large() -> Error {
+    Error::new(ErrorKind::InvalidInput, "Request body too large")
+}
+
+/// Reads a single line without the line terminator, lines longer than `MAX_HEAD_BYTES` are
+/// refused
+fn read_line<R: BufRead>(reader: &mut R) -> Result<String> {
+    let mut line: Vec<u8> = Vec::new();
+    let read = reader.by_ref().take(MAX_HEAD_BYTES as u64 + 1).read_until(b'\n', &mut line)?;
+    if read == 0 {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "Connection closed before the end of the body"));
+    }
+    if read > MAX_HEAD_BYTES {
+        return Err(Error::new(ErrorKind::InvalidData, "Chunk line too large"));
+    }
+    Ok(String::from_utf8_lossy(&line).trim_end_matches(&['\r', '\n'][..]).to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_head_after_leading_empty_lines() {
+        let mut input: &[u8] = b"\r\nGET / HTTP/1.1\r\nHost: a\r\n\r\nbody";
+        assert_eq!(read_head(&mut input).unwrap(), b"GET / HTTP/1.1\r\nHost: a\r\n".to_vec());
+        assert_eq!(input, b"body");
+    }
+
+    #[test]
+    fn refuses_lines_without_end_over_the_limit() {
+        let line = vec![b'a'; MAX_HEAD_BYTES * 2];
+        let mut input: &[u8] = &line;
+        let err = read_head(&mut input).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        // Only the limit was consumed
+        assert_eq!(input.len(), MAX_HEAD_BYTES - 1);
+    }
+
+    #[test]
+    fn counts_leading_empty_lines_toward_the_limit() {
+        let empty = b"\r\n".repeat(MAX_HEAD_BYTES);
+        let mut input: &[u8] = &empty;
+        assert_eq!(read_head(&mut input).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    fn headers(raw: &str) -> RequestHeaders {
+        RequestHeaders::parse(&format!("HTTP/1.1\r\nHost: a\r\n{}", raw))
+    }
+
+    #[test]
+    fn reads_chunked_bodies_only_when_chunked_is_the_last_coding() {
+        let mut input: &[u8] = b"5\r\nhello\r\n0\r\n\r\n";
+        let chunked = headers("Transfer-Encoding: Chunked\r\n");
+        let mut body = String::new();
+        BodyReader::new(&mut input, &chunked, 100).read_to_string(&mut body).unwrap();
+        assert_eq!(body, "hello");
+        assert!(chunked.has_body() && chunked.check_framing().is_ok());
+
+        for coding in ["chunked, gzip", "xchunked", "gzip", "chunked, chunked"] {
+            let headers = headers(&format!("Transfer-Encoding: {}\r\n", coding));
+            assert!(headers.check_framing().is_err(), "{} was accepted", coding);
+            // Refused requests are still framed the same way by every reader
+            let mut input: &[u8] = b"5\r\nhello\r\n0\r\n\r\n";
+            let mut body = String::new();
+            BodyReader::new(&mut input, &headers, 100).read_to_string(&mut body).unwrap();
+            assert_eq!(is_chunked(&headers.transfer_encoding), !body.is_empty());
+            assert_eq!(headers.has_body(), !body.is_empty());
+        }
+        let repeated = headers("Transfer-Encoding: chunked\r\nTransfer-Encoding: chunked\r\n");
+        assert!(repeated.check_framing().is_err());
+    }
+
+    #[test]
+    fn refuses_invalid_content_lengths() {
+        assert!(headers("Content-Length: 12\r\n").check_framing().is_ok());
+        for length in ["abc", "-1", "+5", "1 2", "", "99999999999999999999999"] {
+            let headers = headers(&format!("Content-Length: {}\r\n", length));
+            assert_eq!(headers.check_framing().unwrap_err().kind(), ErrorKind::InvalidData, "{}", length);
+        }
+    }
+
+    #[test]
+    fn refuses_repeated_and_conflicting_lengths() {
+        assert!(headers("Content-Length: 5\r\nContent-Length: 5\r\n").check_framing().is_err());
+        assert!(headers("Content-Length: 5\r\ncontent-length: 6\r\n").check_framing().is_err());
+        assert!(headers("Content-Length: 5, 6\r\n").check_framing().is_err());
+        assert!(headers("Content-Length: 5\r\nTransfer-Encoding: chunked\r\n").check_framing().is_err());
+    }
+}
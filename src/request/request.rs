@@ -1,91 +1,383 @@
+use crate::connection::stream::{RequestReader, Stream};
 use crate::request::form::*;
 use crate::request::headers::*;
 use crate::request::method::*;
 
 use crate::request::other::Other;
+use crate::request::path::*;
+use crate::request::reader::*;
+use crate::request::utils::generate_request_id;
+use crate::response::headers::ResponseHeaders;
+use crate::response::status::HttpStatus;
+use crate::settings::rewrite::RewriteRule;
+use crate::settings::settings::Config;
+use crate::settings::vhost::{normalize_host, VirtualHost};
+use crate::utils::check_stream_write;
 use crate::utils::log::log_warning;
-use std::io::Read;
-use std::net::TcpStream;
+use crate::APP_CONFIG;
+use regex::Captures;
+use std::cell::Cell;
+use std::io::{self, copy, sink, ErrorKind, Read, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
 
+#[derive(Clone, Copy, Debug, Default)]
+/// Status and body size of the response sent to a request, for the access log
+pub struct SentResponse {
+    /// Status code of the final response, 0 if none was sent
+    pub status: u16,
+    /// Bytes of the body, without the framing of the chunked transfer coding
+    pub body_bytes: u64,
+}
+
 #[derive(Derivative)]
-#[derivative(Debug)]
-#[derive(Default)]
+#[derivative(Debug, Default)]
 // ToDo UrlEncoded Variables
 /// This Struct Contains all the request data parsed and ready to use
 pub struct Request {
+    /// Identifies the request on the log lines
+    #[derivative(Default(value = "generate_request_id()"))]
+    pub id: String,
     #[derivative(Debug = "ignore")]
     raw: String,
     #[derivative(Debug = "ignore")]
     pub is_valid_request: bool,
+    /// Status answered to a head that can not be parsed, None if the client closed the
+    /// connection before sending it
+    #[derivative(Debug = "ignore")]
+    pub invalid_status: Option<HttpStatus>,
     pub method: Method,
     pub path: String,
+    /// Raw query string, without the `?`
+    pub query: String,
+    /// Why the path was refused while parsing
+    #[derivative(Debug = "ignore")]
+    path_error: Option<PathError>,
     pub request_headers: RequestHeaders,
     pub form_data: FormData,
     pub other: Other,
+    /// Defines if the connection will be kept open after the response
+    pub keep_alive: bool,
+    /// Defines if the request arrived over HTTPS
+    pub secure: bool,
+    /// Defines if the body is still on the connection, handlers read it once the request is
+    /// accepted
+    pub body_pending: bool,
+    /// Status and Location of the redirect chosen by a rewrite rule
+    pub redirect: Option<(HttpStatus, String)>,
+    /// Local path of the resource, set by `check_path` once it is known to stay inside the
+    /// root folder
+    pub local_path: PathBuf,
+    /// Response recorded by the handlers as they send it
+    #[derivative(Debug = "ignore")]
+    sent: Cell<SentResponse>,
+    /// Site that serves the request
+    #[derivative(Debug = "ignore")]
+    pub vhost: Arc<VirtualHost>,
+    /// Config when the request arrived, used until the response is sent even if it is reloaded
+    #[derivative(Debug = "ignore", Default(value = "APP_CONFIG.get()"))]
+    pub config: Arc<Config>,
 }
 
 impl Request {
-    /// Parse request and headers from byte buffer
-    pub fn parse(stream: &TcpStream, timeout: Option<Duration>) -> Request {
+    /// Parse request and headers from the connection
+    ///
+    /// Reads until the end of the headers, the body is left for the handler, the timeout is
+    /// only the maximum time to wait for the client to send more bytes
+    pub fn parse(reader: &mut RequestReader<'_>, timeout: Option<Duration>) -> Request {
         // Create Structure with default values
         let mut req = Request::default();
-        let mut form_data = FormData::default();
-        let mut headers = RequestHeaders::default();
-
-        // Create Empty Byte Vector
-        let mut buffer_full: Vec<u8> = Vec::new();
 
+        let stream: &Stream = reader.get_ref().stream();
         stream.set_read_timeout(timeout).ok();
-        // Read bytes for the specified timeout
-        stream.to_owned().read_to_end(&mut buffer_full).ok();
+        req.other = Other::parse(stream);
+        req.secure = stream.is_secure();
 
-        //Parse request data
-        req.raw = unsafe { String::from_utf8_unchecked(buffer_full) }
-            .to_owned()
-            .replace('\u{0}', "");
+        let head = match read_head(reader) {
+            Ok(value) => value,
+            Err(err) => {
+                log_warning(&err);
+                req.invalid_status = match err.kind() {
+                    ErrorKind::InvalidData => Some(HttpStatus::RequestHeaderFieldsTooLarge),
+                    ErrorKind::TimedOut | ErrorKind::WouldBlock => Some(HttpStatus::RequestTimeout),
+                    _ => None,
+                };
+                return req;
+            }
+        };
 
-        //println!("{}", &req.raw);
+        //Parse request data
+        req.raw = String::from_utf8_lossy(&head).to_string();
 
         let request_arr: Vec<&str> = req.raw.splitn(3, ' ').collect();
 
         if request_arr.len() >= 3 {
             req.method = Method::from_str(&request_arr[0].to_owned());
+            req.request_headers = RequestHeaders::parse(request_arr[2].trim_end_matches("\r\n"));
 
-            let rs: Vec<&str> = request_arr[2].splitn(2, "\r\n\r\n").collect();
-            for part in rs {
-                if part.starts_with("HTTP") {
-                    headers = RequestHeaders::parse(part);
-                } else if part.contains(&headers.content_bounds) && &headers.content_bounds != &"" {
-                    form_data.add_multipart(part.to_owned(), &headers.content_bounds.to_owned());
-                } else if part.contains("=") {
-                    form_data.add_url_encoded(part.to_owned());
-                } else {
-                    if part != "" {
-                        println!("Failed to parse:\n{}\n", part);
-                    }
+            match normalize_path(request_arr[1]) {
+                Ok((path, query)) => {
+                    req.path = path;
+                    req.query = query;
                 }
-            }
-            req.request_headers = headers;
-            req.form_data = form_data;
-
-            match percent_encoding::percent_decode(request_arr[1].as_bytes()).decode_utf8() {
-                Ok(value) => {
-                    req.path = value.trim_start_matches("/..").to_owned();
-                    req.is_valid_request = true;
-                },
                 Err(err) => {
-                    log_warning(&err);
-                },
+                    log_warning(&format!("Refused path {}", request_arr[1]));
+                    req.path_error = Some(err);
+                }
             }
+
+            req.vhost = req.config.get_virtual_host(&req.request_headers.host).clone();
+            // Requests sent to HTTPS are rewritten there
+            if req.path_error.is_none() && !req.needs_https_redirect() {
+                req.apply_rewrites();
+            }
+
+            // The body is left on the connection, each handler reads it once the request is
+            // accepted or discards it
+            req.body_pending = req.request_headers.has_body();
+            req.is_valid_request = true;
+        } else {
+            req.invalid_status = Some(HttpStatus::BadRequest);
         }
-        req.other = Other::parse(stream);
         req
     }
 
-    /// Obtains resource path relative to the specified location
-    pub fn get_local_path(&self, root_folder: &String) -> String {
-        root_folder.to_owned() + &self.path
+    /// Applies the `[[rewrite]]` rules in order, each one to the path left by the previous ones
+    ///
+    /// A redirect stops at its rule and leaves the path unchanged, rewritten paths that are not
+    /// valid are refused like the ones sent by the client and so are the ones with `..` segments,
+    /// even encoded
+    fn apply_rewrites(&mut self) {
+        let config = self.config.clone();
+        let host = normalize_host(&self.request_headers.host);
+        for rule in &config.rewrite {
+            let (path, query) = {
+                let headers = self.get_raw_headers();
+                let captures = match rule.captures(&self.path, &host, self.method.to_str(), &headers) {
+                    Some(value) => value,
+                    None => continue,
+                };
+                match &rule.to {
+                    Some(to) => rule.expand(to, &captures),
+                    None => self.try_files(rule, &captures),
+                }
+            };
+            let query = query.unwrap_or_else(|| self.query.to_owned());
+
+            if let Some(status) = rule.get_redirect_status() {
+                let mut location = encode_path(&path);
+                if !query.is_empty() {
+                    location += &format!("?{}", query);
+                }
+                self.redirect = Some((status, location));
+                return;
+            }
+            if has_parent_segments(&path) {
+                log_warning(&format!("Refused rewritten path {}", path));
+                self.path_error = Some(PathError::Forbidden);
+                return;
+            }
+            match normalize_path(&encode_path(&path)) {
+                Ok((path, _query)) => {
+                    self.path = path;
+                    self.query = query;
+                }
+                Err(err) => {
+                    log_warning(&format!("Refused rewritten path {}", path));
+                    self.path_error = Some(err);
+                    return;
+                }
+            }
+            if rule.last {
+                return;
+            }
+        }
+    }
+
+    /// Obtains the first path of `try_files` that exists on the site, or the last one
+    fn try_files(&self, rule: &RewriteRule, captures: &Captures<'_>) -> (String, Option<String>) {
+        let root_folder = &self.vhost.root_folder;
+        let follow_symlinks = self.config.server.follow_symlinks;
+        let mut candidates = rule.try_files.iter().map(|path| rule.expand(path, captures)).peekable();
+        while let Some((path, query)) = candidates.next() {
+            if candidates.peek().is_none() {
+                return (path, query);
+            }
+            let local = match normalize_path(&encode_path(&path)) {
+                Ok((value, _query)) => resolve_path(root_folder, &value, follow_symlinks),
+                Err(err) => Err(err),
+            };
+            let exists = match local {
+                Ok(local) if path.ends_with('/') => local.is_dir(),
+                Ok(local) => local.is_file(),
+                Err(_err) => false,
+            };
+            if exists {
+                return (path, query);
+            }
+        }
+        (String::new(), None)
+    }
+
+    /// Reads the body as a form, multipart bodies are stored on `form_data.multipart_file` and
+    /// url-encoded ones on `form_data.url_encoded`
+    ///
+    /// Fails with `ErrorKind::InvalidInput` if the body is bigger than `publish.max_body_bytes`
+    pub fn read_form(&mut self, reader: &mut RequestReader<'_>) -> io::Result<()> {
+        if !self.body_pending {
+            return Ok(());
+        }
+        self.body_pending = false;
+        self.send_continue(reader.get_ref().stream());
+
+        let mut form_data = FormData::default();
+        let headers = &self.request_headers;
+        let mut body = BodyReader::new(reader, headers, self.config.publish.max_body_bytes);
+        if headers.content_type.eq_ignore_ascii_case("multipart/form-data") && !headers.content_bounds.is_empty() {
+            form_data.add_multipart(&mut body, &headers.content_bounds, self.config.server.multipart_memory_limit)?;
+        } else {
+            let mut data: Vec<u8> = Vec::new();
+            body.read_to_end(&mut data)?;
+            let part = String::from_utf8_lossy(&data).to_string();
+            if part.contains('=') {
+                form_data.add_url_encoded(part);
+            }
+        }
+        // The epilogue of multipart bodies is discarded
+        copy(&mut body, &mut sink())?;
+        self.form_data = form_data;
+        Ok(())
+    }
+
+    /// Reads and drops a body the handler does not use so the connection can be reused
+    ///
+    /// Fails with `ErrorKind::InvalidInput` if the body is bigger than `MAX_DISCARDED_BODY_BYTES`
+    pub fn discard_body(&mut self, reader: &mut RequestReader<'_>) -> io::Result<()> {
+        if !self.body_pending {
+            return Ok(());
+        }
+        self.body_pending = false;
+        self.send_continue(reader.get_ref().stream());
+        copy_body(reader, &self.request_headers, &mut sink(), MAX_DISCARDED_BODY_BYTES).map(|_copied| ())
+    }
+
+    /// Sends `100 Continue` if the client is waiting for it before sending the body
+    pub fn send_continue(&self, mut stream: &Stream) {
+        if self.request_headers.expect.eq_ignore_ascii_case("100-continue") {
+            check_stream_write(stream.write_all(&[HttpStatus::Continue.as_bytes(), b"\r\n"].concat()));
+        }
+    }
+
+    /// Sends the head of the response, recording its status
+    pub fn send_head(&self, mut stream: &Stream, headers: &mut ResponseHeaders) -> io::Result<()> {
+        self.record_status(headers.status.to_int() as u16);
+        stream.write_all(headers.get_headers().as_bytes())
+    }
+
+    /// Sends part of the body of the response, counting its bytes
+    pub fn send_body(&self, mut stream: &Stream, body: &[u8]) -> io::Result<()> {
+        stream.write_all(body)?;
+        self.record_body(body.len() as u64);
+        Ok(())
+    }
+
+    /// Records the status of the response whose head is being sent
+    pub fn record_status(&self, status: u16) {
+        self.sent.set(SentResponse { status, body_bytes: 0 });
+    }
+
+    /// Counts bytes of the body sent, once the transfer coding is removed
+    pub fn record_body(&self, bytes: u64) {
+        let mut sent = self.sent.get();
+        sent.body_bytes += bytes;
+        self.sent.set(sent);
+    }
+
+    /// Status and body size of the response sent so far
+    pub fn get_sent_response(&self) -> SentResponse {
+        self.sent.get()
+    }
+
+    /// Sets the response headers that depend on the connection: Connection and, over HTTPS,
+    /// Strict-Transport-Security
+    pub fn set_connection_headers(&self, headers: &mut ResponseHeaders) {
+        headers.set_connection(self.keep_alive);
+        if self.secure {
+            headers.strict_transport_security = self.config.tls.get_hsts();
+        }
+    }
+
+    /// Checks if the request arrived over plain HTTP and has to be redirected to HTTPS
+    pub fn needs_https_redirect(&self) -> bool {
+        self.config.tls.active && self.config.tls.redirect_http && !self.secure
+    }
+
+    /// Checks if the client asked to keep the connection open
+    ///
+    /// HTTP/1.1 connections are persistent unless `Connection: close` is sent, HTTP/1.0 ones only
+    /// when `Connection: keep-alive` is sent
+    pub fn wants_keep_alive(&self) -> bool {
+        let connection = self.request_headers.connection.to_lowercase();
+        let has_option = |option: &str| connection.split(',').any(|value| value.trim() == option);
+        if self.request_headers.version == "HTTP/1.1" {
+            !has_option("close")
+        } else {
+            has_option("keep-alive")
+        }
+    }
+
+    /// Checks if the client can receive bodies with chunked transfer coding, only HTTP/1.1
+    /// clients are required to understand it
+    pub fn accepts_chunked(&self) -> bool {
+        self.request_headers.version == "HTTP/1.1"
+    }
+
+    /// Checks if the response must include the body, HEAD requests get the same headers as GET
+    /// without it
+    pub fn wants_body(&self) -> bool {
+        self.method != Method::HEAD
+    }
+
+    /// Checks if this is a CORS preflight request
+    pub fn is_preflight(&self) -> bool {
+        self.method == Method::OPTIONS
+            && !self.request_headers.origin.is_empty()
+            && !self.request_headers.access_control_request_method.is_empty()
+    }
+
+    /// Checks that the path is well formed and that it stays inside the root folder of the
+    /// site, keeping its local path for the handlers
+    pub fn check_path(&mut self) -> Result<(), PathError> {
+        self.check_target()?;
+        self.local_path = resolve_path(&self.vhost.root_folder, &self.path, self.config.server.follow_symlinks)?;
+        Ok(())
+    }
+
+    /// Checks that the path is well formed, for requests that are not served from the root folder
+    pub fn check_target(&self) -> Result<(), PathError> {
+        match self.path_error {
+            Some(PathError::Invalid) => Err(PathError::Invalid),
+            Some(PathError::Forbidden) => Err(PathError::Forbidden),
+            None => Ok(()),
+        }
+    }
+
+    /// First line of the request as it was received, like `GET /index.html HTTP/1.1`
+    pub fn get_request_line(&self) -> &str {
+        self.raw.lines().next().unwrap_or("")
+    }
+
+    /// Headers as they were received, in order and with their original names
+    pub fn get_raw_headers(&self) -> Vec<(&str, &str)> {
+        self.raw
+            .lines()
+            .skip(1)
+            .filter_map(|line| {
+                let mut parts = line.splitn(2, ':');
+                Some((parts.next()?.trim(), parts.next()?.trim()))
+            })
+            .collect()
     }
 
     /// Returns Raw String
@@ -93,3 +385,184 @@ impl Request {
         self.raw.to_owned()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::stream::StreamReader;
+    use std::fs;
+    use std::io::BufReader;
+    use std::net::{TcpListener, TcpStream};
+    use std::path::Path;
+
+    /// Config of the repository with the given `[[rewrite]]` rules, serving `root`
+    fn config(rules: &str, root: &Path) -> Arc<Config> {
+        let mut settings = ::config::Config::new();
+        settings
+            .merge(::config::File::from_str(rules, ::config::FileFormat::Toml))
+            .unwrap();
+        let mut config = Config::load_for_tests();
+        config.rewrite = settings.get("rewrite").unwrap();
+        for rule in &mut config.rewrite {
+            rule.compile().unwrap();
+        }
+        config.vhost.clear();
+        config.server.root_folder = format!("{}/", root.display());
+        config.setup_virtual_hosts();
+        Arc::new(config)
+    }
+
+    /// Request built from its head like `parse` does, with the rewrites applied
+    fn rewrite(config: &Arc<Config>, head: &str) -> Request {
+        let parts: Vec<&str> = head.splitn(3, ' ').collect();
+        let (path, query) = normalize_path(parts[1]).unwrap();
+        let mut request = Request {
+            raw: head.to_owned(),
+            method: Method::from_str(&parts[0].to_owned()),
+            path,
+            query,
+            request_headers: RequestHeaders::parse(parts[2]),
+            config: config.clone(),
+            ..Request::default()
+        };
+        request.vhost = config.get_virtual_host(&request.request_headers.host).clone();
+        request.apply_rewrites();
+        request
+    }
+
+    fn get(config: &Arc<Config>, target: &str) -> Request {
+        rewrite(config, &format!("GET {} HTTP/1.1\r\nHost: example.com\r\n\r\n", target))
+    }
+
+    #[test]
+    fn expands_captures_and_query() {
+        let config = config(
+            r#"
+            [[rewrite]]
+            pattern = "^/blog/(\\d+)/(?P<slug>[^/]+)$"
+            to = "/posts/${slug}.html?id=$1"
+            [[rewrite]]
+            pattern = "^/old/(.*)$"
+            to = "/new/$1"
+            "#,
+            Path::new("."),
+        );
+        let request = get(&config, "/blog/7/hello?page=2");
+        assert_eq!((request.path.as_str(), request.query.as_str()), ("/posts/hello.html", "id=7"));
+        let request = get(&config, "/old/a%20b?page=2");
+        assert_eq!((request.path.as_str(), request.query.as_str()), ("/new/a b", "page=2"));
+    }
+
+    #[test]
+    fn checks_conditions() {
+        let config = config(
+            r#"
+            [[rewrite]]
+            pattern = "^/api$"
+            to = "/v2"
+            host = "^example\\.com$"
+            methods = ["GET"]
+            headers = { "X-Version" = "^2" }
+            "#,
+            Path::new("."),
+        );
+        let head = |method: &str, host: &str, version: &str| {
+            format!("{} /api HTTP/1.1\r\nHost: {}\r\nX-Version: {}\r\n\r\n", method, host, version)
+        };
+        assert_eq!(rewrite(&config, &head("GET", "Example.com:8080", "2.1")).path, "/v2");
+        assert_eq!(rewrite(&config, &head("POST", "example.com", "2.1")).path, "/api");
+        assert_eq!(rewrite(&config, &head("GET", "other.com", "2.1")).path, "/api");
+        assert_eq!(rewrite(&config, &head("GET", "example.com", "1")).path, "/api");
+        assert_eq!(get(&config, "/api").path, "/api");
+    }
+
+    #[test]
+    fn stops_at_last_rule() {
+        let rules = |last: bool| {
+            format!(
+                "[[rewrite]]\npattern = \"^/a$\"\nto = \"/b\"\nlast = {}\n\
+                 [[rewrite]]\npattern = \"^/b$\"\nto = \"/c\"\n",
+                last
+            )
+        };
+        assert_eq!(get(&config(&rules(true), Path::new(".")), "/a").path, "/b");
+        assert_eq!(get(&config(&rules(false), Path::new(".")), "/a").path, "/c");
+    }
+
+    #[test]
+    fn falls_back_on_try_files() {
+        let root = std::env::temp_dir().join(format!("akira-rewrite-test-{}", std::process::id()));
+        fs::create_dir_all(root.join("dir")).unwrap();
+        fs::write(root.join("file.txt"), b"file").unwrap();
+        let config = config(
+            r#"
+            [[rewrite]]
+            pattern = "^/.*$"
+            try_files = ["$0", "$0/", "/index.html"]
+            "#,
+            &root,
+        );
+
+        assert_eq!(get(&config, "/file.txt").path, "/file.txt");
+        assert_eq!(get(&config, "/dir").path, "/dir/");
+        assert_eq!(get(&config, "/file.txt/").path, "/index.html");
+        assert_eq!(get(&config, "/missing").path, "/index.html");
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn maps_redirect_statuses() {
+        let statuses = [
+            (301, HttpStatus::MovedPermanently),
+            (302, HttpStatus::Found),
+            (307, HttpStatus::TemporaryRedirect),
+            (308, HttpStatus::PermanentRedirect),
+        ];
+        for (code, status) in statuses {
+            let rules = format!("[[rewrite]]\npattern = \"^/old$\"\nto = \"/new page\"\nredirect = {}\n", code);
+            let request = get(&config(&rules, Path::new(".")), "/old?a=1");
+            assert_eq!(request.redirect, Some((status, "/new%20page?a=1".to_owned())));
+            assert_eq!(request.path, "/old");
+        }
+    }
+
+    #[test]
+    fn refuses_parent_segments() {
+        let config = config(
+            r#"
+            [[rewrite]]
+            pattern = "^/plain/(.*)$"
+            to = "/$1/../../secret"
+            [[rewrite]]
+            pattern = "^/encoded/(.*)$"
+            to = "/$1/%2e%2e/%2E%2E/secret"
+            "#,
+            Path::new("."),
+        );
+        assert_eq!(get(&config, "/plain/a").path_error, Some(PathError::Forbidden));
+        assert_eq!(get(&config, "/encoded/a").path_error, Some(PathError::Forbidden));
+        assert_eq!(get(&config, "/encoded/a").path, "/encoded/a");
+    }
+
+    /// Request parsed from the bytes sent by a client
+    fn parse(sent: &[u8]) -> Request {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let stream = Stream::Plain(listener.accept().unwrap().0);
+        let mut reader = BufReader::new(StreamReader::new(&stream, sent.to_vec()));
+        Request::parse(&mut reader, Some(Duration::from_millis(50)))
+    }
+
+    #[test]
+    fn answers_heads_that_can_not_be_parsed() {
+        assert!(parse(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n").is_valid_request);
+        assert_eq!(parse(b"GARBAGE\r\n\r\n").invalid_status, Some(HttpStatus::BadRequest));
+        let long = format!("GET / HTTP/1.1\r\nX: {}\r\n\r\n", "a".repeat(MAX_HEAD_BYTES));
+        let request = parse(long.as_bytes());
+        assert_eq!(request.invalid_status, Some(HttpStatus::RequestHeaderFieldsTooLarge));
+        let request = parse(b"GET / HTTP/1.1\r\nHost: a\r\n");
+        assert_eq!(request.invalid_status, Some(HttpStatus::RequestTimeout));
+        assert!(!request.is_valid_request);
+    }
+}
@@ -1,4 +1,8 @@
 use crate::utils::log::log_warning;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Number of requests received by this process
+static REQUEST_COUNT: AtomicU64 = AtomicU64::new(0);
 
 /// Check if the string estarts with the defined pattern
 /// If starts with the pattern, removes the pattern and stores the remeaning data to the field
@@ -9,12 +13,12 @@ pub fn generate_field_string(data: &str) -> String {
 
 /// Check if the string estarts with the defined pattern
 /// If starts with the pattern, removes the pattern and stores the remeaning data to the field
-/// as `` Vec<String> ``
+/// as `` Vec<String> `` splitting the comma separated list
 pub fn generate_field_string_vec(data: &str) -> Vec<String> {
     let mut v: Vec<String> = Vec::new();
-    let da: Vec<&str> = data.split(" ").collect();
+    let da: Vec<&str> = data.split(',').collect();
     for d in da {
-        v.push(d.to_owned());
+        v.push(d.trim().to_owned());
     }
     return v;
 }
@@ -32,16 +36,15 @@ pub fn generate_field_u64(data: &str) -> u64 {
     };
 }
 
-/// Check if the string estarts with the defined pattern
-/// If starts with the pattern, removes the pattern and stores the remeaning data to the field
-/// as `` [u8] ``
-pub fn generate_field_vec_u8(data: &str) -> Vec<u8> {
-    data.as_bytes().to_owned()
-}
-
 pub fn start_with(data: &str, pattern: &str) -> bool {
     if data.to_lowercase().starts_with(&pattern.to_lowercase()[..]) {
         return true;
     }
     false
 }
+
+/// Generates the id of a request from the process id and a counter, like `1f3a-2c`
+pub fn generate_request_id() -> String {
+    let count = REQUEST_COUNT.fetch_add(1, Ordering::Relaxed);
+    format!("{:x}-{:x}", std::process::id(), count)
+}
@@ -0,0 +1,59 @@
+//! Authentication
+//!
+//! [HTTP Basic](https://tools.ietf.org/html/rfc7617) authentication against the configured
+//! credentials
+extern crate base64;
+
+use base64::Engine;
+
+use crate::connection::stream::Stream;
+use crate::request::request::Request;
+use crate::response::headers::ResponseHeaders;
+use crate::response::status::HttpStatus;
+use crate::request_handlers::get_handler::serve_error_headers;
+
+/// Realm sent to the client when asking for credentials
+const REALM: &str = "AkiraServerV3";
+
+/// Checks if the request has Basic credentials that match any of the allowed `user:password`
+/// pairs
+pub fn is_authorized(request: &Request, credentials: &[String]) -> bool {
+    let authorization = request.request_headers.authorization.trim();
+    if authorization.len() < 6 || !authorization[..6].eq_ignore_ascii_case("basic ") {
+        return false;
+    }
+    let decoded = match base64::engine::general_purpose::STANDARD.decode(authorization[6..].trim()) {
+        Ok(value) => value,
+        Err(_err) => return false,
+    };
+    credentials
+        .iter()
+        .any(|allowed| constant_time_eq(allowed.as_bytes(), &decoded))
+}
+
+/// Obtains the user name sent with Basic credentials, even if they are not valid
+pub fn get_user(request: &Request) -> Option<String> {
+    let authorization = request.request_headers.authorization.trim();
+    if authorization.len() < 6 || !authorization[..6].eq_ignore_ascii_case("basic ") {
+        return None;
+    }
+    let decoded = base64::engine::general_purpose::STANDARD.decode(authorization[6..].trim()).ok()?;
+    let decoded = String::from_utf8_lossy(&decoded);
+    decoded.split(':').next().map(|user| user.to_owned())
+}
+
+/// Asks the client for credentials
+pub fn serve_unauthorized(stream: &Stream, request: &Request) {
+    let mut headers = ResponseHeaders::new(HttpStatus::Unauthorized);
+    headers.www_authenticate = format!("Basic realm=\"{}\", charset=\"UTF-8\"", REALM);
+    serve_error_headers(stream, request, headers);
+}
+
+/// Compares without returning early so the time taken does not leak how much of the password
+/// matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
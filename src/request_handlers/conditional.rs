@@ -0,0 +1,226 @@
+//! Conditional Requests
+//!
+//! Validators for static files and evaluation of the preconditions defined on the
+//! [RFC](https://tools.ietf.org/html/rfc7232#section-6)
+use std::fs::Metadata;
+use std::time::UNIX_EPOCH;
+
+use crate::request::headers::RequestHeaders;
+use crate::request::method::Method;
+use crate::response::status::HttpStatus;
+use crate::utils::date::*;
+
+#[derive(Debug, Default)]
+/// Values that identify a version of a file
+pub struct Validators {
+    /// Strong entity tag, quotes included
+    pub e_tag: String,
+    /// Last modification date as HTTP-date
+    pub last_modified: String,
+    /// Last modification date as seconds since the unix epoch
+    modified: Option<i64>,
+}
+
+impl Validators {
+    /// Generates validators from the file size and modification time
+    pub fn from_metadata(meta: &Metadata) -> Validators {
+        match meta.modified() {
+            Ok(modified) => {
+                let nanos = match modified.duration_since(UNIX_EPOCH) {
+                    Ok(value) => value.as_nanos(),
+                    Err(_err) => 0,
+                };
+                Validators {
+                    e_tag: format!("\"{:x}-{:x}\"", meta.len(), nanos),
+                    last_modified: format_http_date(modified),
+                    modified: Some(get_timestamp(modified)),
+                }
+            }
+            Err(_err) => Validators {
+                e_tag: format!("\"{:x}\"", meta.len()),
+                ..Validators::default()
+            },
+        }
+    }
+
+    /// Marks the entity tag as the one of the representation compressed with the content coding,
+    /// each coding is a different representation of the file
+    pub fn set_coding(&mut self, coding: &str) {
+        self.e_tag = format!("{}-{}\"", self.e_tag.trim_end_matches('"'), coding);
+    }
+
+    /// Evaluates the request preconditions in the order defined by the RFC
+    ///
+    /// Returns the status that must be sent instead of the resource, if any
+    pub fn evaluate(&self, headers: &RequestHeaders, method: &Method) -> Option<HttpStatus> {
+        let is_get_or_head = *method == Method::GET || *method == Method::HEAD;
+
+        if !headers.if_match.is_empty() {
+            if !self.matches_any(&headers.if_match, false) {
+                return Some(HttpStatus::PreconditionFailed);
+            }
+        } else if let (Some(date), Some(modified)) = (parse_http_date(&headers.if_unmodified_since), self.modified) {
+            if modified > date {
+                return Some(HttpStatus::PreconditionFailed);
+            }
+        }
+
+        if !headers.if_none_match.is_empty() {
+            if self.matches_any(&headers.if_none_match, true) {
+                if is_get_or_head {
+                    return Some(HttpStatus::NotModified);
+                }
+                return Some(HttpStatus::PreconditionFailed);
+            }
+        } else if is_get_or_head {
+            if let (Some(date), Some(modified)) = (parse_http_date(&headers.if_modified_since), self.modified) {
+                if modified <= date {
+                    return Some(HttpStatus::NotModified);
+                }
+            }
+        }
+        None
+    }
+
+    /// Checks the If-Range precondition, ranges are only served if the client copy is still the
+    /// current one
+    pub fn if_range_matches(&self, if_range: &str) -> bool {
+        let if_range = if_range.trim();
+        if if_range.is_empty() {
+            return true;
+        }
+        if if_range.starts_with('"') || if_range.starts_with("W/") {
+            return compare_e_tags(if_range, &self.e_tag, false);
+        }
+        match (parse_http_date(if_range), self.modified) {
+            (Some(date), Some(modified)) => modified == date,
+            _ => false,
+        }
+    }
+
+    /// Checks if the list of entity tags sent by the client contains the current one
+    fn matches_any(&self, list: &str, weak: bool) -> bool {
+        list.split(',')
+            .map(|tag| tag.trim())
+            .any(|tag| tag == "*" || compare_e_tags(tag, &self.e_tag, weak))
+    }
+}
+
+/// Evaluates the preconditions of a request that replaces or removes a file, `meta` is None
+/// when the file does not exist
+///
+/// A missing file has no current version so If-Match fails and If-None-Match passes
+pub fn evaluate_change(meta: Option<&Metadata>, headers: &RequestHeaders, method: &Method) -> Option<HttpStatus> {
+    match meta {
+        Some(meta) => Validators::from_metadata(meta).evaluate(headers, method),
+        None if !headers.if_match.is_empty() => Some(HttpStatus::PreconditionFailed),
+        None => None,
+    }
+}
+
+/// Compares two entity tags, weak comparison ignores the `W/` prefix while strong comparison
+/// never matches weak tags
+fn compare_e_tags(a: &str, b: &str, weak: bool) -> bool {
+    if weak {
+        a.trim_start_matches("W/") == b.trim_start_matches("W/")
+    } else {
+        !a.starts_with("W/") && !b.starts_with("W/") && a == b
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Validators of a file modified on Sun, 06 Nov 1994 08:49:37 GMT
+    fn validators() -> Validators {
+        Validators {
+            e_tag: "\"a-1\"".to_owned(),
+            last_modified: "Sun, 06 Nov 1994 08:49:37 GMT".to_owned(),
+            modified: Some(784111777),
+        }
+    }
+
+    fn evaluate(method: Method, set: impl Fn(&mut RequestHeaders)) -> Option<HttpStatus> {
+        let mut headers = RequestHeaders::default();
+        set(&mut headers);
+        validators().evaluate(&headers, &method)
+    }
+
+    #[test]
+    fn evaluates_if_match() {
+        assert_eq!(evaluate(Method::PUT, |h| h.if_match = "\"a-1\"".to_owned()), None);
+        assert_eq!(evaluate(Method::PUT, |h| h.if_match = "\"b\", \"a-1\"".to_owned()), None);
+        assert_eq!(evaluate(Method::PUT, |h| h.if_match = "*".to_owned()), None);
+        assert_eq!(
+            evaluate(Method::PUT, |h| h.if_match = "\"b\"".to_owned()),
+            Some(HttpStatus::PreconditionFailed)
+        );
+        // Strong comparison never matches weak tags
+        assert_eq!(
+            evaluate(Method::PUT, |h| h.if_match = "W/\"a-1\"".to_owned()),
+            Some(HttpStatus::PreconditionFailed)
+        );
+    }
+
+    #[test]
+    fn evaluates_if_none_match() {
+        assert_eq!(
+            evaluate(Method::GET, |h| h.if_none_match = "W/\"a-1\"".to_owned()),
+            Some(HttpStatus::NotModified)
+        );
+        assert_eq!(
+            evaluate(Method::HEAD, |h| h.if_none_match = "*".to_owned()),
+            Some(HttpStatus::NotModified)
+        );
+        assert_eq!(
+            evaluate(Method::PUT, |h| h.if_none_match = "\"a-1\"".to_owned()),
+            Some(HttpStatus::PreconditionFailed)
+        );
+        assert_eq!(evaluate(Method::GET, |h| h.if_none_match = "\"b\"".to_owned()), None);
+    }
+
+    #[test]
+    fn if_none_match_wins_over_if_modified_since() {
+        let result = evaluate(Method::GET, |h| {
+            h.if_none_match = "\"b\"".to_owned();
+            h.if_modified_since = "Sun, 06 Nov 1994 08:49:37 GMT".to_owned();
+        });
+        assert_eq!(result, None);
+    }
+
+    #[test]
+    fn evaluates_dates() {
+        assert_eq!(
+            evaluate(Method::GET, |h| h.if_modified_since = "Sun, 06 Nov 1994 08:49:37 GMT".to_owned()),
+            Some(HttpStatus::NotModified)
+        );
+        assert_eq!(
+            evaluate(Method::GET, |h| h.if_modified_since = "Sat, 05 Nov 1994 08:49:37 GMT".to_owned()),
+            None
+        );
+        assert_eq!(
+            evaluate(Method::PUT, |h| h.if_unmodified_since = "Sat, 05 Nov 1994 08:49:37 GMT".to_owned()),
+            Some(HttpStatus::PreconditionFailed)
+        );
+        assert_eq!(evaluate(Method::GET, |h| h.if_modified_since = "yesterday".to_owned()), None);
+    }
+
+    #[test]
+    fn evaluates_if_range() {
+        let validators = validators();
+        assert!(validators.if_range_matches(""));
+        assert!(validators.if_range_matches("\"a-1\""));
+        assert!(!validators.if_range_matches("W/\"a-1\""));
+        assert!(!validators.if_range_matches("\"b\""));
+        assert!(validators.if_range_matches("Sun, 06 Nov 1994 08:49:37 GMT"));
+        assert!(!validators.if_range_matches("Sun, 06 Nov 1994 08:49:38 GMT"));
+    }
+
+    #[test]
+    fn marks_the_coding_on_the_e_tag() {
+        let mut validators = validators();
+        validators.set_coding("gzip");
+        assert_eq!(validators.e_tag, "\"a-1-gzip\"");
+    }
+}
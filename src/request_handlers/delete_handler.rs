@@ -0,0 +1,83 @@
+use std::fs;
+use std::path::Path;
+
+use crate::connection::stream::Stream;
+use crate::request::request::Request;
+use crate::request_handlers::auth::*;
+use crate::request_handlers::conditional::evaluate_change;
+use crate::request_handlers::get_handler::serve_error;
+use crate::request_handlers::options_handler::serve_method_not_allowed;
+use crate::response::headers::ResponseHeaders;
+use crate::response::status::HttpStatus;
+use crate::utils::check_stream_write;
+use crate::utils::log::*;
+
+/// Removes the requested file, directories are never removed
+pub fn handle_delete(stream: &Stream, request: &Request) {
+    let settings = &request.config.publish;
+    if !settings.active {
+        return serve_method_not_allowed(stream, request);
+    }
+    if !is_authorized(request, &settings.credentials) {
+        return serve_unauthorized(stream, request);
+    }
+
+    let path: &Path = &request.local_path;
+    let meta = match fs::metadata(path) {
+        Ok(value) => value,
+        Err(_err) => return serve_error(stream, request, HttpStatus::NotFound),
+    };
+    if !meta.is_file() {
+        return serve_error(stream, request, HttpStatus::Forbidden);
+    }
+    if let Some(status) = evaluate_change(Some(&meta), &request.request_headers, &request.method) {
+        return serve_error(stream, request, status);
+    }
+
+    match fs::remove_file(path) {
+        Ok(_value) => {
+            let mut headers = ResponseHeaders::new(HttpStatus::NoContent);
+            request.set_connection_headers(&mut headers);
+            check_stream_write(request.send_head(stream, &mut headers));
+        },
+        Err(err) => {
+            log_error(&err);
+            serve_error(stream, request, HttpStatus::InternalServerError);
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request_handlers::conditional::Validators;
+    use crate::request_handlers::test_client::*;
+
+    fn delete(root: &Path, target: &str, headers: &str) -> String {
+        let sent = format!("DELETE {} HTTP/1.1\r\nHost: a\r\n{}\r\n", target, headers);
+        send(config(root), sent.as_bytes(), |stream, _reader, request| handle_delete(stream, request))
+    }
+
+    #[test]
+    fn removes_files_when_allowed() {
+        let root = temp_root("delete-test");
+        let path = root.join("a.txt");
+        fs::write(&path, "old").unwrap();
+        fs::create_dir(root.join("sub")).unwrap();
+
+        assert_eq!(status(&delete(&root, "/a.txt", "")), "401");
+        assert_eq!(status(&delete(&root, "/missing.txt", AUTHORIZATION)), "404");
+        assert_eq!(status(&delete(&root, "/sub", AUTHORIZATION)), "403");
+        let headers = format!("{}If-Match: \"other\"\r\n", AUTHORIZATION);
+        assert_eq!(status(&delete(&root, "/a.txt", &headers)), "412");
+        let headers = format!("{}If-None-Match: *\r\n", AUTHORIZATION);
+        assert_eq!(status(&delete(&root, "/a.txt", &headers)), "412");
+        assert!(path.exists());
+
+        let e_tag = Validators::from_metadata(&fs::metadata(&path).unwrap()).e_tag;
+        let headers = format!("{}If-Match: {}\r\n", AUTHORIZATION, e_tag);
+        assert_eq!(status(&delete(&root, "/a.txt", &headers)), "204");
+        assert!(!path.exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
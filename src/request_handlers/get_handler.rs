@@ -7,118 +7,372 @@ use maud::*;
 use std::fs;
 use std::fs::{File, Metadata};
 use std::io::Write;
-use std::io::{BufRead, BufReader};
-use std::net::TcpStream;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Seek, SeekFrom};
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use crate::connection::stream::Stream;
+use crate::request::encoding::select_encoding;
+use crate::request::path::resolve_path;
+use crate::request::range::{MultipartRanges, RangeRequest};
+use crate::request_handlers::conditional::Validators;
+use crate::request_handlers::options_handler::apply_cors;
 use crate::request::request::Request;
+use crate::response::chunked::ChunkedWriter;
+use crate::response::compression::{is_compressible, Encoder};
 use crate::response::headers::ResponseHeaders;
 use crate::response::status::HttpStatus;
+use crate::settings::publish::Publish;
 use crate::utils::check_stream_write;
 use crate::utils::log::*;
-use crate::APP_CONFIG;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 // Resources
 const BOOTSTRAP_CSS: &'static str = include_str!("../../resources/bootstrap.css");
 // const JQUERY_JS:&'static str = include_str!("../resources/jquery-3.4.1.js");
 
-pub fn handle_get(mut stream: &TcpStream, request: &Request) {
-    let path_str = request.get_local_path(&APP_CONFIG.server.root_folder);
-    let path: &Path = std::path::Path::new(&path_str);
-    if path.exists() {
-        match fs::metadata(&path) {
-            Ok(value) => {
-                if value.is_file() {
-                    serve_file(stream, value, path);
-                } else if value.is_dir() {
-                    serve_directory(stream, request);
-                } else {
-                    log_error(&"The target is neither a file or a directory.");
-                }
-            },
-            Err(err) => {
-                log_warning(&err);
-            },
-        }
-    } else {
-        let mut headers = ResponseHeaders::new(HttpStatus::NotFound);
-        check_stream_write(stream.write(headers.get_headers().as_bytes()));
-        check_stream_write(stream.write(error_page(HttpStatus::NotFound).as_bytes()));
+pub fn handle_get(stream: &Stream, request: &mut Request) {
+    let path = request.local_path.clone();
+    match fs::metadata(&path) {
+        Ok(value) => {
+            if value.is_file() {
+                serve_file(stream, request, value, &path);
+            } else if value.is_dir() {
+                serve_directory(stream, request);
+            } else {
+                log_warning(&"The target is neither a file or a directory.");
+                serve_error(stream, request, HttpStatus::Forbidden);
+            }
+        },
+        Err(err) => serve_error(stream, request, get_file_error_status(err)),
     }
 }
 
-fn serve_file(mut stream: &TcpStream, meta: Metadata, path: &Path) {
-    // Headers
-    let mut headers = ResponseHeaders::new(HttpStatus::OK);
-    headers.set_cross_origin_allow_all();
-    headers.set_content_length(meta.len());
-    // https://docs.rs/mime_guess/2.0.0-alpha.6/mime_guess/fn.octet_stream.html
-    let mime = mime_guess::guess_mime_type_opt(path);
-    match mime {
-        Some(value) => {
-            headers.set_content_type(value.to_string());
+/// Status sent when a file can not be read, unexpected errors are logged
+fn get_file_error_status(error: Error) -> HttpStatus {
+    match error.kind() {
+        ErrorKind::NotFound | ErrorKind::NotADirectory => HttpStatus::NotFound,
+        ErrorKind::PermissionDenied => HttpStatus::Forbidden,
+        _ => {
+            log_error(&error);
+            HttpStatus::InternalServerError
         },
+    }
+}
+
+fn serve_file(mut stream: &Stream, request: &mut Request, meta: Metadata, path: &Path) {
+    // https://docs.rs/mime_guess/2.0.0-alpha.6/mime_guess/fn.octet_stream.html
+    let mime = match mime_guess::guess_mime_type_opt(path) {
+        Some(value) => value.to_string(),
         None => {
             log_warning(&"No mime found");
+            String::new()
         },
     };
+
+    // Precompressed files are served as they are, keeping the Content-Type of the original one
+    let sidecars = find_precompressed(request, path);
+    let stored_coding = select_encoding(&request.request_headers.accept_encoding, &sidecars);
+    let sidecar_path: PathBuf;
+    let root = &request.vhost.root_folder;
+    let follow_symlinks = request.config.server.follow_symlinks;
+    let (meta, path) = match stored_coding
+        .as_ref()
+        .and_then(|coding| get_precompressed_path(root, follow_symlinks, path, coding))
+    {
+        Some(value) => {
+            sidecar_path = value;
+            match fs::metadata(&sidecar_path) {
+                Ok(value) => (value, sidecar_path.as_path()),
+                Err(err) => {
+                    log_error(&err);
+                    return serve_error(stream, request, HttpStatus::InternalServerError);
+                },
+            }
+        },
+        None => (meta, path),
+    };
+
+    // Compressed bodies have unknown length so ranges are served without compression, when a
+    // precompressed file is sent ranges are taken from it as it is the selected representation
+    let encoding = if request.request_headers.range.is_empty() && stored_coding.is_none() {
+        negotiate_compression(request, &mime, meta.len())
+    } else {
+        None
+    };
+
+    let mut validators = Validators::from_metadata(&meta);
+    if let Some(coding) = encoding.as_ref().or(stored_coding.as_ref()) {
+        validators.set_coding(coding);
+    }
+    let vary_encoding = (request.config.compression.active && is_compressible(&mime)) || !sidecars.is_empty();
+
+    if let Some(status) = validators.evaluate(&request.request_headers, &request.method) {
+        let mut headers = ResponseHeaders::new(status);
+        request.set_connection_headers(&mut headers);
+        headers.e_tag = validators.e_tag;
+        headers.last_modified = validators.last_modified;
+        apply_cors(request, &mut headers);
+        if vary_encoding {
+            headers.add_vary("Accept-Encoding");
+        }
+        check_stream_write(request.send_head(stream, &mut headers));
+        return;
+    }
+
+    let total_length = meta.len();
+    let ranges = if !request.request_headers.range.is_empty()
+        && validators.if_range_matches(&request.request_headers.if_range)
+    {
+        RangeRequest::parse(&request.request_headers.range, total_length)
+    } else {
+        RangeRequest::Full
+    };
+
+    // Headers
+    let mut headers = ResponseHeaders::new(HttpStatus::OK);
+    request.set_connection_headers(&mut headers);
+    apply_cors(request, &mut headers);
+    headers.accept_ranges = "bytes".to_owned();
+    headers.e_tag = validators.e_tag;
+    headers.last_modified = validators.last_modified;
+    if vary_encoding {
+        headers.add_vary("Accept-Encoding");
+    }
+    if let Some(coding) = stored_coding {
+        headers.content_encoding = coding;
+    }
+
+    // Multipart Parts, each one with its own headers
+    let mut multipart: Option<MultipartRanges> = None;
+
+    match &ranges {
+        RangeRequest::Full => {
+            match &encoding {
+                Some(coding) => {
+                    headers.content_encoding = coding.to_owned();
+                    headers.set_chunked();
+                },
+                None => headers.set_content_length(total_length),
+            }
+            headers.set_content_type(mime.clone());
+        },
+        RangeRequest::Partial(list) if list.len() == 1 => {
+            headers.status = HttpStatus::PartialContent;
+            headers.content_range = list[0].content_range(total_length);
+            headers.set_content_length(list[0].length());
+            headers.set_content_type(mime.clone());
+        },
+        RangeRequest::Partial(list) => {
+            let boundary = generate_boundary();
+            headers.status = HttpStatus::PartialContent;
+            headers.set_content_type(format!("multipart/byteranges; boundary={}", boundary));
+            let body = MultipartRanges::new(list, &boundary, &mime, total_length);
+            headers.set_content_length(body.length);
+            multipart = Some(body);
+        },
+        RangeRequest::Unsatisfiable => {
+            headers.status = HttpStatus::RequestedRangeNotSatisfiable;
+            headers.content_range = format!("bytes */{}", total_length);
+        },
+    }
+
     let headers_processed = headers.get_headers();
 
-    if APP_CONFIG.debug.active {
+    if request.config.debug.active {
         log_verbose(&headers);
         log_verbose(&headers_processed);
     }
 
-    check_stream_write(stream.write(headers_processed.as_bytes()));
-    // Max buffer Read in bytes (1048576 == 1 Megabytes)
-    const CAP: usize = 1048576;
-    match File::open(&path) {
-        Ok(file) => {
-            let mut reader = BufReader::with_capacity(CAP, file);
-            // Chunked Transfer WORKS!!! \:D/
-            loop {
-                let length = {
-                    let buffer = reader.fill_buf().unwrap();
-                    // do stuff with buffer here
-                    match stream.write(buffer) {
-                        Err(err) => {
-                            log_error(&err);
-                            break;
-                        },
-                        Ok(_value) => {},
-                    }
-                    buffer.len()
-                };
-                if length == 0 {
-                    break;
+    // The file is opened before the head is sent, once it is sent errors can only be reported by
+    // closing the connection
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) => return serve_error(stream, request, get_file_error_status(err)),
+    };
+    request.record_status(headers.status.to_int() as u16);
+    check_stream_write(stream.write_all(headers_processed.as_bytes()));
+    if !request.wants_body() {
+        return;
+    }
+
+    let complete = match ranges {
+        RangeRequest::Full => match &encoding {
+            Some(coding) => {
+                let level = request.config.compression.get_level(coding);
+                match Encoder::new(coding, level, ChunkedWriter::new(stream)) {
+                    Some(mut encoder) => {
+                        if send_file_range(&mut encoder, &mut file, 0, total_length) != total_length {
+                            false
+                        } else {
+                            let result = encoder.finish().and_then(|chunked| {
+                                request.record_body(chunked.length());
+                                chunked.finish(&[])
+                            });
+                            match result {
+                                Ok(_) => true,
+                                Err(err) => {
+                                    log_error(&err);
+                                    false
+                                },
+                            }
+                        }
+                    },
+                    None => false,
                 }
-                reader.consume(length);
-            }
+            },
+            None => {
+                let sent = send_file_range(&mut stream, &mut file, 0, total_length);
+                request.record_body(sent);
+                sent == total_length
+            },
         },
-        Err(err) => {
-            log_error(&err);
+        RangeRequest::Partial(list) => match multipart {
+            None => {
+                let sent = send_file_range(&mut stream, &mut file, list[0].start, list[0].length());
+                request.record_body(sent);
+                sent == list[0].length()
+            },
+            Some(body) => {
+                let mut complete = true;
+                for (part, range) in body.parts {
+                    check_stream_write(request.send_body(stream, part.as_bytes()));
+                    let sent = send_file_range(&mut stream, &mut file, range.start, range.length());
+                    request.record_body(sent);
+                    if sent != range.length() {
+                        complete = false;
+                        break;
+                    }
+                }
+                if complete {
+                    check_stream_write(request.send_body(stream, body.closing.as_bytes()));
+                }
+                complete
+            },
         },
+        RangeRequest::Unsatisfiable => true,
+    };
+    // A body shorter than announced can only be told apart from the next response by closing
+    if !complete {
+        request.keep_alive = false;
+    }
+}
+
+/// Sends `length` bytes of the file starting from `start`, returns the bytes sent which are
+/// less than `length` if the transfer failed
+fn send_file_range<W: Write>(stream: &mut W, file: &mut File, start: u64, length: u64) -> u64 {
+    if let Err(err) = file.seek(SeekFrom::Start(start)) {
+        log_error(&err);
+        return 0;
+    }
+    let mut sent: u64 = 0;
+    // Max buffer Read in bytes (1048576 == 1 Megabytes)
+    const CAP: usize = 1048576;
+    let mut reader = BufReader::with_capacity(CAP, file.take(length));
+    // Chunked Transfer WORKS!!! \:D/
+    loop {
+        let length = {
+            let buffer = match reader.fill_buf() {
+                Ok(buffer) => buffer,
+                Err(err) => {
+                    log_error(&err);
+                    return sent;
+                },
+            };
+            // do stuff with buffer here
+            match stream.write_all(buffer) {
+                Err(err) => {
+                    log_error(&err);
+                    return sent;
+                },
+                Ok(_value) => {},
+            }
+            buffer.len()
+        };
+        if length == 0 {
+            return sent;
+        }
+        reader.consume(length);
+        sent += length as u64;
+    }
+}
+
+/// Selects the content coding for a response of the given type and size, None if it must be
+/// sent uncompressed
+fn negotiate_compression(request: &Request, mime: &str, length: u64) -> Option<String> {
+    let settings = &request.config.compression;
+    // Compressed bodies have unknown length and are sent chunked
+    if !settings.active || !request.accepts_chunked() || length < settings.min_size || !is_compressible(mime) {
+        return None;
+    }
+    select_encoding(&request.request_headers.accept_encoding, &settings.algorithms)
+}
+
+/// Lists the content codings that have a precompressed copy next to the file, in the configured
+/// order of preference
+fn find_precompressed(request: &Request, path: &Path) -> Vec<String> {
+    let settings = &request.config.compression;
+    if !settings.precompressed {
+        return Vec::new();
+    }
+    let root = &request.vhost.root_folder;
+    let follow_symlinks = request.config.server.follow_symlinks;
+    settings
+        .algorithms
+        .iter()
+        .filter(|coding| get_precompressed_path(root, follow_symlinks, path, coding).is_some())
+        .cloned()
+        .collect()
+}
+
+/// Obtains the precompressed copy of the file for the content coding, if it is a regular file
+/// inside the root folder
+///
+/// `file.ext.br` for brotli and `file.ext.gz` for gzip, deflate has no precompressed copies. The
+/// copy is resolved like a requested path, so symlinks are only followed when allowed
+fn get_precompressed_path(root: &str, follow_symlinks: bool, path: &Path, coding: &str) -> Option<PathBuf> {
+    let extension = match coding {
+        "br" => ".br",
+        "gzip" => ".gz",
+        _ => return None,
+    };
+    let canonical_root = fs::canonicalize(root).ok()?;
+    let segments: Option<Vec<&str>> = path
+        .strip_prefix(&canonical_root)
+        .ok()?
+        .iter()
+        .map(|segment| segment.to_str())
+        .collect();
+    let relative = format!("{}{}", segments?.join("/"), extension);
+    let sidecar = resolve_path(root, &relative, follow_symlinks).ok()?;
+    match fs::symlink_metadata(&sidecar) {
+        Ok(meta) if meta.is_file() => Some(sidecar),
+        _ => None,
     }
 }
 
-fn serve_directory(mut stream: &TcpStream, request: &Request) {
-    let request_path = &request.get_local_path(&APP_CONFIG.server.root_folder);
+/// Generates a boundary for multipart responses that is unlikely to appear in the file
+fn generate_boundary() -> String {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(value) => format!("akira{:x}{:x}", value.as_secs(), value.subsec_nanos()),
+        Err(_err) => "akira_byteranges_boundary".to_owned(),
+    }
+}
 
-    let content: DirContent = DirContent::read_dir(request_path);
+fn serve_directory(stream: &Stream, request: &mut Request) {
+    let content: DirContent = DirContent::read_dir(&request.local_path);
 
+    let vhost = request.vhost.clone();
     for file in &content.files {
-        for name in &APP_CONFIG.server.index {
+        for name in vhost.get_index() {
             if file == name {
-                let file: String = request_path.to_owned() + &file;
-
-                let p: &Path = std::path::Path::new(&file);
+                let p: PathBuf = request.local_path.join(file);
 
-                match fs::metadata(p) {
+                match fs::metadata(&p) {
                     Ok(value) => {
-                        serve_file(stream, value, p);
+                        serve_file(stream, request, value, &p);
                     },
                     Err(err) => {
-                        log_error(&err);
+                        serve_error(stream, request, get_file_error_status(err));
                     },
                 }
                 return;
@@ -126,14 +380,96 @@ fn serve_directory(mut stream: &TcpStream, request: &Request) {
         }
     }
 
-    if APP_CONFIG.server.list_directories {
+    if request.vhost.lists_directories() {
+        let body = list_directory(content, &request.path, &request.config.publish);
         let mut headers = ResponseHeaders::new(HttpStatus::OK);
-        check_stream_write(stream.write(headers.get_headers().as_bytes()));
-        check_stream_write(stream.write(list_directory(content, &request.path).as_bytes()));
+        request.set_connection_headers(&mut headers);
+        headers.set_content_type("text/html; charset=utf-8".to_owned());
+        apply_cors(request, &mut headers);
+        if request.config.compression.active {
+            headers.add_vary("Accept-Encoding");
+        }
+        if request.accepts_chunked() {
+            headers.set_chunked();
+            let encoding = negotiate_compression(request, &headers.content_type, body.len() as u64);
+            if let Some(coding) = &encoding {
+                headers.content_encoding = coding.to_owned();
+            }
+            check_stream_write(request.send_head(stream, &mut headers));
+            if !request.wants_body() {
+                return;
+            }
+            let level = request.config.compression.get_level(&headers.content_encoding);
+            let finish = |chunked: ChunkedWriter<&Stream>| {
+                request.record_body(chunked.length());
+                chunked.finish(&[]).map(|_stream| ())
+            };
+            let result = match Encoder::new(&headers.content_encoding, level, ChunkedWriter::new(stream)) {
+                Some(mut encoder) => encoder
+                    .write_all(body.as_bytes())
+                    .and_then(|_| encoder.finish())
+                    .and_then(finish),
+                None => {
+                    let mut writer = ChunkedWriter::new(stream);
+                    writer.write_all(body.as_bytes()).and_then(|_| finish(writer))
+                },
+            };
+            if let Err(err) = result {
+                log_error(&err);
+            }
+        } else {
+            headers.set_content_length(body.len() as u64);
+            check_stream_write(request.send_head(stream, &mut headers));
+            if request.wants_body() {
+                check_stream_write(request.send_body(stream, body.as_bytes()));
+            }
+        }
     } else {
-        let mut headers = ResponseHeaders::new(HttpStatus::Forbidden);
-        check_stream_write(stream.write(headers.get_headers().as_bytes()));
-        check_stream_write(stream.write(error_page(HttpStatus::Forbidden).as_bytes()));
+        serve_error(stream, request, HttpStatus::Forbidden);
+    }
+}
+
+/// Sends the error page for the given status
+pub fn serve_error(stream: &Stream, request: &Request, status: HttpStatus) {
+    serve_error_headers(stream, request, ResponseHeaders::new(status));
+}
+
+/// Sends the error page for the status of the given headers
+pub fn serve_error_headers(stream: &Stream, request: &Request, mut headers: ResponseHeaders) {
+    let body = match read_error_page(request, &headers.status) {
+        Some((body, mime)) => {
+            headers.set_content_type(mime);
+            body
+        }
+        None => {
+            headers.set_content_type("text/html; charset=utf-8".to_owned());
+            error_page(&headers.status).into_bytes()
+        }
+    };
+    request.set_connection_headers(&mut headers);
+    headers.set_content_length(body.len() as u64);
+    check_stream_write(request.send_head(stream, &mut headers));
+    if request.wants_body() {
+        check_stream_write(request.send_body(stream, &body));
+    }
+}
+
+/// Reads the custom page of the site for the status, with its mime type
+fn read_error_page(request: &Request, status: &HttpStatus) -> Option<(Vec<u8>, String)> {
+    let page = request.vhost.get_error_page(status.to_int())?;
+    let path = Path::new(&request.vhost.root_folder).join(page.trim_start_matches('/'));
+    match fs::read(&path) {
+        Ok(body) => {
+            let mime = match mime_guess::guess_mime_type_opt(&path) {
+                Some(value) => value.to_string(),
+                None => "text/html; charset=utf-8".to_owned(),
+            };
+            Some((body, mime))
+        }
+        Err(err) => {
+            log_warning(&err);
+            None
+        }
     }
 }
 
@@ -158,7 +494,7 @@ fn header_template() -> Markup {
     }
 }
 
-fn error_page(error_code: HttpStatus) -> String {
+fn error_page(error_code: &HttpStatus) -> String {
     let template: Markup = html! {
         html{
             (header_template())
@@ -183,10 +519,10 @@ fn error_page(error_code: HttpStatus) -> String {
     template.into_string()
 }
 
-fn list_directory(content: DirContent, path: &String) -> String {
+fn list_directory(content: DirContent, path: &String, publish: &Publish) -> String {
     let dir_len = content.directories.len();
     let file_len = content.files.len();
-    // get_web_path(path, &request_path)
+    let show_upload = publish.active && publish.upload_form;
 
     let template: Markup = html! {
         html{
@@ -216,6 +552,13 @@ fn list_directory(content: DirContent, path: &String) -> String {
                             a href=(percent_encode(uri, false)) style="display:block;" { (uri) }
                         }
                     }
+                    @if show_upload {
+                        br{}
+                        form method="post" enctype="multipart/form-data" {
+                            input type="file" name="file" multiple? class="form-control-file" {}
+                            button type="submit" class="btn btn-primary" { "Upload" }
+                        }
+                    }
                 }
             }
         }
@@ -234,11 +577,6 @@ fn percent_encode(link: &String, is_dir: bool) -> String {
     }
 }
 
-fn get_web_path(full_path: String, current_dir: &String) -> String {
-    let temp = full_path.replace(current_dir, "");
-    temp.trim_start_matches(&APP_CONFIG.server.root_folder).to_owned()
-}
-
 #[derive(Default, Debug)]
 struct DirContent {
     directories: Vec<String>,
@@ -247,17 +585,17 @@ struct DirContent {
 
 impl DirContent {
     // ToDo Test Bytes instead of strings for better performance
-    fn read_dir(path: &String) -> DirContent {
+    fn read_dir(path: &Path) -> DirContent {
         let mut content: DirContent = DirContent::default();
 
-        match fs::read_dir(&path) {
+        match fs::read_dir(path) {
             Ok(paths) => {
                 for item_info in paths {
-                    let item_path = item_info.unwrap().path().display().to_string();
+                    let item_info = item_info.unwrap();
 
-                    match fs::metadata(&item_path) {
+                    match fs::metadata(item_info.path()) {
                         Ok(md) => {
-                            let web_path = get_web_path(item_path, path);
+                            let web_path = item_info.file_name().to_string_lossy().to_string();
                             if md.is_dir() {
                                 content.directories.push(web_path);
                             } else if md.is_file() {
@@ -277,3 +615,62 @@ impl DirContent {
         content
     }
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::request_handlers::test_client::*;
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::symlink;
+
+    fn get(root: &Path, target: &str) -> String {
+        let sent = format!("GET {} HTTP/1.1\r\nHost: a\r\n\r\n", target);
+        send(config(root), sent.as_bytes(), |stream, _reader, request| handle_get(stream, request))
+    }
+
+    #[test]
+    fn answers_targets_that_are_not_files_or_directories() {
+        let root = temp_root("get-special");
+        fs::write(root.join("a.txt"), "file").unwrap();
+        let fifo = CString::new(root.join("fifo").as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o644) }, 0);
+
+        let response = get(&root, "/a.txt");
+        assert_eq!(status(&response), "200");
+        assert!(response.ends_with("\r\n\r\nfile"));
+        assert_eq!(status(&get(&root, "/fifo")), "403");
+        assert_eq!(status(&get(&root, "/missing.txt")), "404");
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn serves_only_precompressed_files_inside_the_root() {
+        let directory = std::env::temp_dir().join(format!("akira-get-test-{}", std::process::id()));
+        let root = directory.join("root");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(directory.join("secret"), "outside").unwrap();
+        for name in ["a.txt", "a.txt.gz", "b.txt", "c.txt"] {
+            fs::write(root.join(name), name).unwrap();
+        }
+        symlink(directory.join("secret"), root.join("b.txt.gz")).unwrap();
+        symlink(root.join("a.txt.gz"), root.join("c.txt.gz")).unwrap();
+        fs::create_dir(root.join("a.txt.br")).unwrap();
+
+        let root_folder = format!("{}/", root.display());
+        let file = |name: &str| fs::canonicalize(&root).unwrap().join(name);
+        let sidecar = |follow_symlinks: bool, name: &str, coding: &str| {
+            get_precompressed_path(&root_folder, follow_symlinks, &file(name), coding)
+        };
+        assert_eq!(sidecar(false, "a.txt", "gzip"), Some(file("a.txt.gz")));
+        assert_eq!(sidecar(false, "a.txt", "br"), None);
+        assert_eq!(sidecar(false, "a.txt", "deflate"), None);
+        assert_eq!(sidecar(false, "b.txt", "gzip"), None);
+        assert_eq!(sidecar(true, "b.txt", "gzip"), None);
+        assert_eq!(sidecar(false, "c.txt", "gzip"), None);
+        assert_eq!(sidecar(true, "c.txt", "gzip"), Some(file("a.txt.gz")));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
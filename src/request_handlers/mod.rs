@@ -1,2 +1,12 @@
+pub mod auth;
+pub mod conditional;
+pub mod delete_handler;
 pub mod get_handler;
+pub mod options_handler;
+pub mod post_handler;
+pub mod proxy_handler;
+pub mod put_handler;
+pub mod redirect_handler;
 pub mod unssuported_handler;
+#[cfg(test)]
+pub mod test_client;
@@ -0,0 +1,89 @@
+use crate::connection::stream::Stream;
+use crate::request::request::Request;
+use crate::request_handlers::get_handler::serve_error_headers;
+use crate::response::headers::ResponseHeaders;
+use crate::response::status::HttpStatus;
+use crate::settings::cors::Cors;
+use crate::settings::publish::Publish;
+use crate::utils::check_stream_write;
+use crate::utils::log::log_verbose;
+
+/// Methods supported by the server, sent on the Allow header
+pub fn get_allowed_methods(settings: &Publish) -> String {
+    if settings.active {
+        "GET, HEAD, OPTIONS, POST, PUT, DELETE".to_owned()
+    } else {
+        "GET, HEAD, OPTIONS".to_owned()
+    }
+}
+
+/// Rejects a method that is known but not enabled
+pub fn serve_method_not_allowed(stream: &Stream, request: &Request) {
+    let mut headers = ResponseHeaders::new(HttpStatus::MethodNotAllowed);
+    headers.allow = get_allowed_methods(&request.config.publish);
+    serve_error_headers(stream, request, headers);
+}
+
+/// Answers OPTIONS requests, both plain ones and CORS preflights
+pub fn handle_options(stream: &Stream, request: &Request) {
+    let mut headers = ResponseHeaders::new(HttpStatus::NoContent);
+    request.set_connection_headers(&mut headers);
+    headers.allow = get_allowed_methods(&request.config.publish);
+
+    if request.is_preflight() {
+        apply_preflight(request, &mut headers);
+    }
+
+    check_stream_write(request.send_head(stream, &mut headers));
+}
+
+/// Adds the CORS headers of a regular request if the origin is allowed
+pub fn apply_cors(request: &Request, headers: &mut ResponseHeaders) {
+    let settings = &request.config.cors;
+    let origin = &request.request_headers.origin;
+    if !settings.active {
+        return;
+    }
+    // The response changes with the origin even when it is not allowed
+    if settings.echoes_origin() {
+        headers.add_vary("Origin");
+    }
+    if origin.is_empty() || !settings.is_origin_allowed(origin) {
+        return;
+    }
+    set_allowed_origin(settings, origin, headers);
+    headers.access_control_expose_headers = settings.exposed_headers.join(", ");
+}
+
+/// Adds the headers of a preflight response, nothing is added if any of the requested origin,
+/// method or headers is not allowed so the browser blocks the request
+fn apply_preflight(request: &Request, headers: &mut ResponseHeaders) {
+    let settings = &request.config.cors;
+    let request_headers = &request.request_headers;
+    if !settings.active
+        || !settings.is_origin_allowed(&request_headers.origin)
+        || !settings.is_method_allowed(&request_headers.access_control_request_method)
+        || !settings.are_headers_allowed(&request_headers.access_control_request_headers)
+    {
+        log_verbose(&"Preflight request rejected by the CORS policy");
+        return;
+    }
+    set_allowed_origin(settings, &request_headers.origin, headers);
+    headers.access_control_allow_methods = settings.allowed_methods.join(", ");
+    headers.access_control_allow_headers = settings.allowed_headers.join(", ");
+    headers.access_control_max_age = settings.max_age.to_string();
+}
+
+/// Sets the allowed origin, `*` is only sent when credentials are not allowed, otherwise the
+/// origin is echoed and caches must vary on it
+fn set_allowed_origin(settings: &Cors, origin: &str, headers: &mut ResponseHeaders) {
+    if settings.allow_credentials {
+        headers.access_control_allow_credentials = "true".to_owned();
+    }
+    if !settings.echoes_origin() {
+        headers.set_cross_origin_allow_all();
+    } else {
+        headers.set_cross_origin_allow_host(origin.to_owned());
+        headers.add_vary("Origin");
+    }
+}
@@ -0,0 +1,317 @@
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::{self, Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use crate::connection::stream::{RequestReader, Stream};
+use crate::request::reader::get_body_error_status;
+use crate::request::request::Request;
+use crate::request_handlers::auth::*;
+use crate::request_handlers::get_handler::serve_error;
+use crate::request_handlers::options_handler::serve_method_not_allowed;
+use crate::request_handlers::put_handler::get_temp_path;
+use crate::response::headers::ResponseHeaders;
+use crate::response::status::HttpStatus;
+use crate::utils::{check_stream_write, truncate_str};
+use crate::utils::log::*;
+
+/// Maximum length in bytes of a stored file name, leaves room for the ` (999)` added when
+/// renaming under the 255 bytes most filesystems allow
+const MAX_FILENAME_BYTES: usize = 249;
+
+/// Saves the files of a multipart form posted to a directory into that directory
+///
+/// The body is only read once the upload is allowed
+pub fn handle_post(stream: &Stream, reader: &mut RequestReader<'_>, request: &mut Request) {
+    let config = request.config.clone();
+    let settings = &config.publish;
+
+    // The body has not been read, the connection can not be reused if the upload is rejected
+    let keep_alive = request.keep_alive;
+    if request.body_pending {
+        request.keep_alive = false;
+    }
+    if !settings.active {
+        return serve_method_not_allowed(stream, request);
+    }
+    if !is_authorized(request, &settings.credentials) {
+        return serve_unauthorized(stream, request);
+    }
+
+    let local_path = request.local_path.clone();
+    let directory: &Path = &local_path;
+    if !directory.is_dir() {
+        return serve_method_not_allowed(stream, request);
+    }
+    if !request.request_headers.content_type.eq_ignore_ascii_case("multipart/form-data") {
+        return serve_error(stream, request, HttpStatus::UnsupportedMediaType);
+    }
+
+    request.keep_alive = keep_alive;
+    if let Err(err) = request.read_form(reader) {
+        log_warning(&err);
+        request.keep_alive = false;
+        return serve_error(stream, request, get_body_error_status(&err));
+    }
+
+    let files = &request.form_data.multipart_file;
+    if files.is_empty() {
+        return serve_error(stream, request, HttpStatus::BadRequest);
+    }
+    if files.iter().any(|file| file.file.size() > settings.max_body_bytes) {
+        return serve_error(stream, request, HttpStatus::RequestEntityTooLarge);
+    }
+
+    let mut names = Vec::new();
+    for file in files {
+        match sanitize_filename(&file.filename) {
+            Some(value) => names.push(value),
+            None => return serve_error(stream, request, HttpStatus::BadRequest),
+        }
+    }
+
+    // Every file is written next to its target before any is placed, a failure leaves the
+    // directory as it was. Staged files are named by their index, long names would not fit
+    let mut staged: Vec<PathBuf> = Vec::new();
+    for (index, file) in files.iter().enumerate() {
+        let temp_path = get_temp_path(&directory.join(index.to_string()));
+        let result = file.file.persist(&temp_path);
+        staged.push(temp_path);
+        if let Err(err) = result {
+            log_error(&err);
+            remove_staged(&staged);
+            return serve_error(stream, request, HttpStatus::InternalServerError);
+        }
+    }
+
+    let mut placed: Vec<Placed> = Vec::new();
+    for (name, temp_path) in names.iter().zip(&staged) {
+        match place_file(temp_path, directory, name, &settings.upload_overwrite) {
+            Ok(value) => placed.push(value),
+            Err(err) => {
+                undo_placed(placed);
+                remove_staged(&staged);
+                if err.kind() == ErrorKind::AlreadyExists {
+                    return serve_error(stream, request, HttpStatus::Conflict);
+                }
+                log_error(&err);
+                return serve_error(stream, request, HttpStatus::InternalServerError);
+            },
+        }
+    }
+    // Replaced files are kept until every upload is in place
+    for item in placed {
+        if let Placed::Replaced(_target, backup) = item {
+            fs::remove_file(backup).ok();
+        }
+    }
+
+    // Browsers are sent back to the listing, other clients get the created status
+    let from_browser = request.request_headers.accept.iter().any(|accept| accept.contains("text/html"));
+    let mut headers = if from_browser {
+        ResponseHeaders::new(HttpStatus::SeeOther)
+    } else {
+        ResponseHeaders::new(HttpStatus::Created)
+    };
+    headers.location = request.path.to_owned();
+    request.set_connection_headers(&mut headers);
+    check_stream_write(request.send_head(stream, &mut headers));
+}
+
+/// Reduces the client supplied name to a single safe path segment
+///
+/// Directories sent by the client are dropped, control characters are removed and hidden or
+/// relative names are rejected
+fn sanitize_filename(filename: &str) -> Option<String> {
+    let name = filename.rsplit(['/', '\\']).next().unwrap_or("");
+    let mut name: String = name
+        .chars()
+        .filter(|c| !c.is_control() && !":*?\"<>|".contains(*c))
+        .collect();
+    name = truncate_str(name.trim(), MAX_FILENAME_BYTES).trim_end().to_owned();
+    if name.is_empty() || name.starts_with('.') {
+        return None;
+    }
+    Some(name)
+}
+
+/// Change made to the directory by placing a file, kept to undo it if a later file fails
+enum Placed {
+    /// A new file was created at the path
+    Created(PathBuf),
+    /// The file at the path was replaced, the previous one was moved to the backup path
+    Replaced(PathBuf, PathBuf),
+}
+
+/// Moves a staged file to its target according to the overwrite policy
+///
+/// * `replace` overwrites the existing file
+/// * `rename` adds a number to the name until it is unique
+/// * `reject` or any other value fails with `AlreadyExists`
+///
+/// Names that must be new are claimed with a hard link, which fails instead of replacing a
+/// file created meanwhile
+fn place_file(temp_path: &Path, directory: &Path, name: &str, policy: &str) -> io::Result<Placed> {
+    let target = directory.join(name);
+    match move_to_new(temp_path, &target) {
+        Err(err) if err.kind() == ErrorKind::AlreadyExists => {},
+        result => return result.map(|_| Placed::Created(target)),
+    }
+    match policy {
+        "replace" if target.is_file() => {
+            let backup = get_temp_path(&target);
+            fs::rename(&target, &backup)?;
+            if let Err(err) = fs::rename(temp_path, &target) {
+                fs::rename(&backup, &target).ok();
+                return Err(err);
+            }
+            Ok(Placed::Replaced(target, backup))
+        },
+        "rename" => {
+            let (stem, extension) = match name.rfind('.') {
+                Some(index) => (&name[..index], &name[index..]),
+                None => (name, ""),
+            };
+            for number in 1..1000 {
+                let candidate = directory.join(format!("{} ({}){}", stem, number, extension));
+                match move_to_new(temp_path, &candidate) {
+                    Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+                    result => return result.map(|_| Placed::Created(candidate)),
+                }
+            }
+            Err(Error::from(ErrorKind::AlreadyExists))
+        },
+        _ => Err(Error::from(ErrorKind::AlreadyExists)),
+    }
+}
+
+/// Moves a file to a path that does not exist yet, fails with `AlreadyExists` if it does
+///
+/// Filesystems without hard links claim the path by creating an empty file first, which is then
+/// replaced by the moved one
+fn move_to_new(source: &Path, target: &Path) -> io::Result<()> {
+    match fs::hard_link(source, target) {
+        Ok(_) => return fs::remove_file(source),
+        Err(err) if err.kind() == ErrorKind::AlreadyExists => return Err(err),
+        Err(_err) => {},
+    }
+    OpenOptions::new().write(true).create_new(true).open(target)?;
+    if let Err(err) = fs::rename(source, target) {
+        fs::remove_file(target).ok();
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Removes the files placed by a failed upload and puts back the ones they replaced
+fn undo_placed(placed: Vec<Placed>) {
+    for item in placed.into_iter().rev() {
+        match item {
+            Placed::Created(target) => {
+                fs::remove_file(target).ok();
+            },
+            Placed::Replaced(target, backup) => {
+                fs::rename(backup, target).ok();
+            },
+        }
+    }
+}
+
+/// Removes the temporary files left by a failed upload, the ones already placed are gone
+fn remove_staged(staged: &[PathBuf]) {
+    for temp_path in staged {
+        fs::remove_file(temp_path).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request_handlers::test_client::*;
+
+    fn post(root: &Path, policy: &str, content_type: &str, filename: &str) -> String {
+        let body = format!(
+            "--b\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\r\nnew\r\n--b--\r\n",
+            filename
+        );
+        let sent = format!(
+            "POST / HTTP/1.1\r\nHost: a\r\n{}Content-Type: {}; boundary=b\r\nContent-Length: {}\r\n\r\n{}",
+            AUTHORIZATION,
+            content_type,
+            body.len(),
+            body
+        );
+        let mut config = config(root);
+        config.publish.upload_overwrite = policy.to_owned();
+        send(config, sent.as_bytes(), handle_post)
+    }
+
+    fn staged(directory: &Path, contents: &str) -> PathBuf {
+        let temp_path = get_temp_path(&directory.join(contents));
+        fs::write(&temp_path, contents).unwrap();
+        temp_path
+    }
+
+    #[test]
+    fn places_files_by_policy() {
+        let directory = std::env::temp_dir().join(format!("akira-post-test-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("a.txt"), "old").unwrap();
+
+        let temp_path = staged(&directory, "rejected");
+        let result = place_file(&temp_path, &directory, "a.txt", "reject");
+        assert_eq!(result.err().map(|err| err.kind()), Some(ErrorKind::AlreadyExists));
+        assert_eq!(fs::read_to_string(directory.join("a.txt")).unwrap(), "old");
+
+        let temp_path = staged(&directory, "renamed");
+        place_file(&temp_path, &directory, "a.txt", "rename").unwrap();
+        assert_eq!(fs::read_to_string(directory.join("a (1).txt")).unwrap(), "renamed");
+        assert!(!temp_path.exists());
+
+        let temp_path = staged(&directory, "replaced");
+        let placed = place_file(&temp_path, &directory, "a.txt", "replace").unwrap();
+        assert_eq!(fs::read_to_string(directory.join("a.txt")).unwrap(), "replaced");
+
+        undo_placed(vec![placed]);
+        assert_eq!(fs::read_to_string(directory.join("a.txt")).unwrap(), "old");
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn uploads_forms_by_policy() {
+        let root = temp_root("post-test");
+        fs::write(root.join("a.txt"), "old").unwrap();
+
+        assert_eq!(status(&post(&root, "reject", "text/plain", "b.txt")), "415");
+        assert!(!root.join("b.txt").exists());
+        assert_eq!(status(&post(&root, "reject", "Multipart/Form-Data", "b.txt")), "201");
+        assert_eq!(fs::read_to_string(root.join("b.txt")).unwrap(), "new");
+
+        assert_eq!(status(&post(&root, "reject", "multipart/form-data", "a.txt")), "409");
+        assert_eq!(fs::read_to_string(root.join("a.txt")).unwrap(), "old");
+        assert_eq!(status(&post(&root, "rename", "multipart/form-data", "dir/a.txt")), "201");
+        assert_eq!(fs::read_to_string(root.join("a (1).txt")).unwrap(), "new");
+
+        // Nothing is left staged
+        let names: Vec<String> = fs::read_dir(&root)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        assert!(names.iter().all(|name| !name.starts_with('.')), "{:?}", names);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn shortens_long_names_on_character_boundaries() {
+        let long = "é".repeat(200);
+        let name = sanitize_filename(&long).unwrap();
+        assert_eq!(name.len(), 248);
+        assert!(name.chars().all(|c| c == 'é'));
+        assert!(format!("{} (999)", name).len() <= 255);
+        assert!(get_temp_path(&PathBuf::from(format!("/{}", long))).file_name().unwrap().len() <= 255);
+        assert_eq!(sanitize_filename("../a.txt"), Some("a.txt".to_owned()));
+        assert_eq!(sanitize_filename(".hidden"), None);
+    }
+}
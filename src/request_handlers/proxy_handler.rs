@@ -0,0 +1,675 @@
+//! Reverse Proxy
+//!
+//! Forwards requests to the upstreams of their `[[proxy]]` rule and streams the responses back,
+//! bodies are never held in memory. Connections to the upstreams are kept open to be reused
+use std::io::{copy, BufRead, BufReader, BufWriter, Error, ErrorKind, Result, Write};
+use std::net::TcpStream;
+
+use crate::connection::stream::{RequestReader, Stream};
+use crate::proxy::upstream::{get_group, UpstreamConnection};
+use crate::request::headers::RequestHeaders;
+use crate::request::reader::{copy_body, is_chunked, read_head, BodyReader};
+use crate::request::request::Request;
+use crate::request_handlers::get_handler::serve_error;
+use crate::response::chunked::ChunkedWriter;
+use crate::response::status::HttpStatus;
+use crate::settings::proxy::ProxyRule;
+use crate::utils::check_stream_write;
+use crate::utils::log::*;
+
+/// Name of this server on the Via header
+const VIA_NAME: &str = "akira";
+
+/// Headers that only apply to a single connection and are never forwarded, the ones listed on
+/// the Connection header are not forwarded either
+const HOP_BY_HOP: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Response headers of the upstream replaced by the ones of this server
+const SERVER_OWNED: [&str; 2] = ["via", "strict-transport-security"];
+
+/// Failure forwarding the request, client errors are answered as bad requests
+enum SendError {
+    Client(Error),
+    Upstream(Error),
+}
+
+/// Writer to the upstream that keeps its errors apart from the ones reading the client body
+struct UpstreamWriter<W: Write> {
+    inner: W,
+    error: Option<Error>,
+}
+
+impl<W: Write> UpstreamWriter<W> {
+    fn check<T>(&mut self, result: Result<T>) -> Result<T> {
+        result.map_err(|err| {
+            let kind = err.kind();
+            self.error = Some(err);
+            Error::new(kind, "Upstream write failed")
+        })
+    }
+}
+
+impl<W: Write> Write for UpstreamWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let result = self.inner.write(buf);
+        self.check(result)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let result = self.inner.flush();
+        self.check(result)
+    }
+}
+
+/// Head of the response sent by the upstream
+struct UpstreamResponse {
+    /// Status line without the version, like `200 OK`
+    status: String,
+    code: u16,
+    headers: Vec<(String, String)>,
+    /// Defines if the upstream will keep the connection open after the response
+    keep_alive: bool,
+}
+
+impl UpstreamResponse {
+    /// Obtains the first value of a header
+    fn get_header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _value)| header.eq_ignore_ascii_case(name))
+            .map(|(_header, value)| value.as_str())
+    }
+
+    /// Checks if the connection can be reused once the response is read, bodies without a
+    /// length end when the upstream closes it
+    fn is_reusable(&self, request: &Request) -> bool {
+        let has_body = request.wants_body() && self.code != 204 && self.code != 304;
+        let framed = self.get_header("content-length").is_some() || self.get_header("transfer-encoding").is_some();
+        self.keep_alive && (!has_body || framed)
+    }
+}
+
+/// Forwards the request to an upstream of the rule and sends its response to the client
+///
+/// The upstreams are tried in the order chosen by the strategy of the rule until one accepts
+/// the connection. Answers `502 Bad Gateway` when none can be reached or the upstream sends an
+/// invalid response and `504 Gateway Timeout` when it takes too long
+pub fn handle_proxy(mut stream: &Stream, reader: &mut RequestReader<'_>, request: &mut Request, rule: &ProxyRule) {
+    let timeouts = &request.config.timeout;
+    let mut connection = match connect(rule, request) {
+        Ok(value) => value,
+        Err(err) => {
+            // The body has not been read
+            request.keep_alive = false;
+            return serve_error(stream, request, get_error_status(&err));
+        }
+    };
+
+    // Reused connections may have been closed by the upstream right before sending the request,
+    // idempotent requests without body are sent again on a new connection
+    let replayable = request.method.is_idempotent() && !request.request_headers.has_body();
+    let (mut upstream_reader, response) = loop {
+        let server = connection.server.clone();
+        let upstream = connection.stream();
+        upstream.set_read_timeout(Some(timeouts.get_proxy_read_duration())).ok();
+        upstream
+            .set_write_timeout(Some(timeouts.get_proxy_read_duration()))
+            .ok();
+
+        let keep_alive = rule.get_keep_alive_connections() > 0;
+        // Defines if the upstream failed before sending any byte of the response
+        let mut unanswered = true;
+        let result = match send_request(upstream, reader, request, rule, &server.address, keep_alive) {
+            Ok(_) => {
+                let mut upstream_reader = BufReader::new(upstream);
+                match upstream_reader.fill_buf().map(|buffer| buffer.is_empty()) {
+                    Ok(true) => Err(Error::new(ErrorKind::UnexpectedEof, "Connection closed by the upstream")),
+                    Ok(false) => {
+                        unanswered = false;
+                        read_response(&mut upstream_reader).map(|response| (upstream_reader, response))
+                    }
+                    Err(err) => Err(err),
+                }
+            }
+            Err(SendError::Client(err)) => {
+                log_warning(&err);
+                request.keep_alive = false;
+                let status = match err.kind() {
+                    ErrorKind::InvalidInput => HttpStatus::RequestEntityTooLarge,
+                    _ => HttpStatus::BadRequest,
+                };
+                return serve_error(stream, request, status);
+            }
+            Err(SendError::Upstream(err)) => Err(err),
+        };
+
+        match result {
+            Ok(value) => break value,
+            Err(err) => {
+                if connection.reused && replayable && unanswered && is_closed_error(&err) {
+                    if let Ok(value) = server.open(timeouts.get_proxy_connect_duration()) {
+                        connection = value;
+                        continue;
+                    }
+                }
+                log_error(&format!("Request to upstream {} failed: {}", server.address, err));
+                server.report_failure(rule);
+                request.keep_alive = false;
+                return serve_error(stream, request, get_error_status(&err));
+            }
+        }
+    };
+    connection.server.report_success();
+
+    // Once the head is sent errors can only be reported by closing the connection
+    let reusable = response.is_reusable(request);
+    match send_response(stream, &mut upstream_reader, request, response) {
+        Ok(_) => {
+            let drained = upstream_reader.buffer().is_empty();
+            drop(upstream_reader);
+            if reusable && drained {
+                connection.release(rule.get_keep_alive_connections());
+            }
+        }
+        Err(err) => {
+            log_error(&format!(
+                "Could not forward the response from upstream {}: {}",
+                connection.server.address, err
+            ));
+            request.keep_alive = false;
+            check_stream_write(stream.flush());
+        }
+    }
+}
+
+/// Connects to the first upstream chosen for the request that accepts the connection, the
+/// ones that do not are counted as failed
+fn connect(rule: &ProxyRule, request: &Request) -> Result<UpstreamConnection> {
+    let timeouts = &request.config.timeout;
+    let mut last_error = Error::new(ErrorKind::NotFound, "The proxy has no upstream");
+    for server in get_group(rule).select(rule, &request.other.remote_ip) {
+        match server.connect(
+            timeouts.get_proxy_connect_duration(),
+            timeouts.get_proxy_keep_alive_duration(),
+        ) {
+            Ok(value) => return Ok(value),
+            Err(err) => {
+                log_error(&format!("Could not connect to upstream {}: {}", server.address, err));
+                server.report_failure(rule);
+                last_error = err;
+            }
+        }
+    }
+    Err(last_error)
+}
+
+/// Sends the head of the request with the forwarding headers followed by its body
+///
+/// `address` is the upstream the request is sent to, `keep_alive` asks it to keep the
+/// connection open after the response
+fn send_request(
+    upstream: &TcpStream,
+    reader: &mut RequestReader<'_>,
+    request: &Request,
+    rule: &ProxyRule,
+    address: &str,
+    keep_alive: bool,
+) -> std::result::Result<(), SendError> {
+    let headers = &request.request_headers;
+    let raw_headers = request.get_raw_headers();
+    let has_body = headers.has_body();
+
+    let mut target = percent_encoding::utf8_percent_encode(
+        &rule.get_upstream_path(&request.path).replace('%', "%25"),
+        percent_encoding::DEFAULT_ENCODE_SET,
+    )
+    .to_string();
+    if !request.query.is_empty() {
+        target += &format!("?{}", request.query);
+    }
+
+    let mut head = format!("{} {} HTTP/1.1\r\n", request.method.to_str(), target);
+    let skipped = get_connection_options(&headers.connection);
+    for (name, value) in &raw_headers {
+        let lowercase = name.to_lowercase();
+        let replaced = [
+            "host",
+            "content-length",
+            "expect",
+            "via",
+            "x-forwarded-for",
+            "forwarded",
+        ];
+        if HOP_BY_HOP.contains(&lowercase.as_str())
+            || replaced.contains(&lowercase.as_str())
+            || skipped.contains(&lowercase)
+        {
+            continue;
+        }
+        head += &format!("{}: {}\r\n", name, value);
+    }
+    let host = if rule.preserve_host && !headers.host.is_empty() {
+        &headers.host
+    } else {
+        address
+    };
+    head += &format!("Host: {}\r\n", host);
+    let received = |name: &str| -> String {
+        let values: Vec<&str> = raw_headers
+            .iter()
+            .filter(|(header, _value)| header.eq_ignore_ascii_case(name))
+            .map(|(_header, value)| *value)
+            .collect();
+        values.join(", ")
+    };
+    head += &format!(
+        "Via: {}\r\n",
+        append_value(&received("via"), &format!("1.1 {}", VIA_NAME))
+    );
+    let remote_ip = &request.other.remote_ip;
+    if !remote_ip.is_empty() {
+        head += &format!(
+            "X-Forwarded-For: {}\r\n",
+            append_value(&received("x-forwarded-for"), remote_ip)
+        );
+    }
+    head += &format!(
+        "Forwarded: {}\r\n",
+        append_value(&received("forwarded"), &get_forwarded(request))
+    );
+    if is_chunked(&headers.transfer_encoding) {
+        head += "Transfer-Encoding: chunked\r\n";
+    } else if has_body {
+        head += &format!("Content-Length: {}\r\n", headers.content_length);
+    }
+    head += if keep_alive {
+        "Connection: keep-alive\r\n\r\n"
+    } else {
+        "Connection: close\r\n\r\n"
+    };
+
+    let mut writer = UpstreamWriter {
+        inner: BufWriter::new(upstream),
+        error: None,
+    };
+    let mut result = writer.write_all(head.as_bytes());
+    if has_body && result.is_ok() {
+        request.send_continue(reader.get_ref().stream());
+        let max_length = request.config.publish.max_body_bytes;
+        result = if is_chunked(&headers.transfer_encoding) {
+            let mut chunked = ChunkedWriter::new(&mut writer);
+            copy_body(reader, headers, &mut chunked, max_length).and_then(|_copied| chunked.finish(&[]).map(|_| ()))
+        } else {
+            copy_body(reader, headers, &mut writer, max_length).map(|_copied| ())
+        };
+    }
+    result = result.and_then(|_| writer.flush());
+
+    match (result, writer.error.take()) {
+        (Ok(_), _) => Ok(()),
+        (Err(_err), Some(error)) => Err(SendError::Upstream(error)),
+        (Err(err), None) => Err(SendError::Client(err)),
+    }
+}
+
+/// Reads the head of the upstream response, skipping interim responses like `100 Continue`
+fn read_response<R: BufRead>(reader: &mut R) -> Result<UpstreamResponse> {
+    loop {
+        let head = read_head(reader)?;
+        let head = String::from_utf8_lossy(&head);
+        let mut lines = head.lines();
+        let status_line = lines.next().unwrap_or("");
+        let mut parts = status_line.splitn(2, ' ');
+        let version = parts.next().unwrap_or("");
+        let status = parts.next().unwrap_or("").trim().to_owned();
+        let code = match status.get(..3).and_then(|code| code.parse::<u16>().ok()) {
+            Some(value) if version.starts_with("HTTP/1.") => value,
+            _ => return Err(Error::new(ErrorKind::InvalidData, "Invalid status line")),
+        };
+        // Protocol upgrades are not forwarded
+        if code == 101 {
+            return Err(Error::new(ErrorKind::InvalidData, "Unexpected protocol upgrade"));
+        }
+        if (100..200).contains(&code) {
+            continue;
+        }
+
+        let headers = lines
+            .filter_map(|line| {
+                let mut parts = line.splitn(2, ':');
+                Some((parts.next()?.trim().to_owned(), parts.next()?.trim().to_owned()))
+            })
+            .collect();
+        let mut response = UpstreamResponse {
+            status,
+            code,
+            headers,
+            keep_alive: false,
+        };
+        let connection = get_connection_options(response.get_header("connection").unwrap_or(""));
+        response.keep_alive = version == "HTTP/1.1" && !connection.iter().any(|option| option == "close");
+        return Ok(response);
+    }
+}
+
+/// Sends the upstream response to the client, its body is sent with the same length or chunked
+/// when the length is not known
+fn send_response<R: BufRead>(
+    mut stream: &Stream,
+    upstream: &mut R,
+    request: &mut Request,
+    response: UpstreamResponse,
+) -> Result<()> {
+    let has_body = request.wants_body() && response.code != 204 && response.code != 304;
+    let content_length = response
+        .get_header("content-length")
+        .and_then(|value| value.parse::<u64>().ok());
+    let chunked_upstream = response.get_header("transfer-encoding").is_some_and(is_chunked);
+    // Without a length the body ends when the upstream closes the connection
+    let known_length = content_length.filter(|_length| !chunked_upstream);
+    let chunked = has_body && known_length.is_none() && request.accepts_chunked();
+    if has_body && known_length.is_none() && !chunked {
+        request.keep_alive = false;
+    }
+
+    let mut head = format!("HTTP/1.1 {}\r\n", response.status);
+    let skipped = get_connection_options(response.get_header("connection").unwrap_or(""));
+    for (name, value) in &response.headers {
+        let lowercase = name.to_lowercase();
+        if HOP_BY_HOP.contains(&lowercase.as_str())
+            || SERVER_OWNED.contains(&lowercase.as_str())
+            || skipped.contains(&lowercase)
+        {
+            continue;
+        }
+        // Bodyless responses keep the length of the resource
+        if lowercase == "content-length" && has_body {
+            continue;
+        }
+        head += &format!("{}: {}\r\n", name, value);
+    }
+    let via = response.get_header("via").unwrap_or("");
+    head += &format!("Via: {}\r\n", append_value(via, &format!("1.1 {}", VIA_NAME)));
+    if let Some(length) = known_length.filter(|_length| has_body) {
+        head += &format!("Content-Length: {}\r\n", length);
+    }
+    if chunked {
+        head += "Transfer-Encoding: chunked\r\n";
+    }
+    head += &format!(
+        "Connection: {}\r\n",
+        if request.keep_alive { "keep-alive" } else { "close" }
+    );
+    let hsts = request.config.tls.get_hsts();
+    if request.secure && !hsts.is_empty() {
+        head += &format!("Strict-Transport-Security: {}\r\n", hsts);
+    }
+    head += "\r\n";
+    request.record_status(response.code);
+    stream.write_all(head.as_bytes())?;
+    if !has_body {
+        return Ok(());
+    }
+
+    let mut framing = RequestHeaders::default();
+    let mut body: Box<dyn std::io::Read + '_> = if chunked_upstream {
+        framing.transfer_encoding = "chunked".to_owned();
+        Box::new(BodyReader::new(upstream, &framing, u64::MAX))
+    } else if let Some(length) = known_length {
+        framing.content_length = length;
+        Box::new(BodyReader::new(upstream, &framing, u64::MAX))
+    } else {
+        Box::new(upstream)
+    };
+    if chunked {
+        let mut writer = ChunkedWriter::new(stream);
+        let result = copy(&mut body, &mut writer);
+        request.record_body(writer.length());
+        result?;
+        writer.finish(&[])?;
+    } else {
+        request.record_body(copy(&mut body, &mut stream)?);
+    }
+    Ok(())
+}
+
+/// Checks if the error means the upstream closed the connection, reused connections fail this
+/// way when the upstream closed them while idle. Timeouts are not, the upstream may be still
+/// processing the request
+fn is_closed_error(error: &Error) -> bool {
+    matches!(
+        error.kind(),
+        ErrorKind::UnexpectedEof | ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe
+    )
+}
+
+/// Value of the Forwarded header added by this server, IPv6 addresses are quoted
+fn get_forwarded(request: &Request) -> String {
+    let remote_ip = &request.other.remote_ip;
+    let mut forwarded = if remote_ip.is_empty() {
+        "for=unknown".to_owned()
+    } else if remote_ip.contains(':') {
+        format!("for=\"[{}]\"", remote_ip)
+    } else {
+        format!("for={}", remote_ip)
+    };
+    let host = &request.request_headers.host;
+    if !host.is_empty() && !host.contains('"') {
+        forwarded += &format!(";host=\"{}\"", host);
+    }
+    forwarded += if request.secure { ";proto=https" } else { ";proto=http" };
+    forwarded
+}
+
+/// Adds a value to the comma separated list of a header
+fn append_value(list: &str, value: &str) -> String {
+    if list.is_empty() {
+        value.to_owned()
+    } else {
+        format!("{}, {}", list, value)
+    }
+}
+
+/// Headers named on a Connection header, in lowercase
+fn get_connection_options(connection: &str) -> Vec<String> {
+    connection
+        .split(',')
+        .map(|option| option.trim().to_lowercase())
+        .filter(|option| !option.is_empty())
+        .collect()
+}
+
+/// Status sent to the client when the upstream fails
+fn get_error_status(error: &Error) -> HttpStatus {
+    match error.kind() {
+        ErrorKind::TimedOut | ErrorKind::WouldBlock => HttpStatus::GatewayTimeout,
+        _ => HttpStatus::BadGateway,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::thread::{spawn, JoinHandle};
+
+    use crate::request_handlers::test_client::send;
+    use crate::settings::settings::Config;
+
+    /// Upstream that reads one request and answers it with `response`, it returns the request
+    /// received. Without response it waits until the proxy closes the connection
+    fn upstream(response: Option<&'static str>) -> (String, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let handle = spawn(move || {
+            let (socket, _address) = listener.accept().unwrap();
+            let mut reader = BufReader::new(&socket);
+            let head = String::from_utf8(read_head(&mut reader).unwrap()).unwrap();
+            let length = head
+                .lines()
+                .filter_map(|line| line.split_once(':'))
+                .find(|(name, _value)| name.eq_ignore_ascii_case("content-length"))
+                .map_or(0, |(_name, value)| value.trim().parse::<usize>().unwrap());
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            match response {
+                Some(response) => (&socket).write_all(response.as_bytes()).unwrap(),
+                None => while reader.read(&mut [0; 64]).unwrap_or(0) > 0 {},
+            }
+            format!("{}{}", head, String::from_utf8(body).unwrap())
+        });
+        (address, handle)
+    }
+
+    /// Sends the request with its body through the proxy to `address` like the client at
+    /// 127.0.0.1 would, returns the response it receives
+    fn proxy(address: &str, head: &str, body: &str, read_miliseconds: u64) -> String {
+        let mut config = Config::load_for_tests();
+        config.timeout.proxy_read_miliseconds = read_miliseconds;
+        proxy_with(config, false, address, head, body)
+    }
+
+    /// Sends the request through the proxy with the given config, as if it arrived over HTTPS
+    /// when `secure` is set
+    fn proxy_with(mut config: Config, secure: bool, address: &str, head: &str, body: &str) -> String {
+        let mut settings = ::config::Config::new();
+        let rule = format!("[[proxy]]\nprefix = \"/api\"\nupstream = \"{}\"", address);
+        settings
+            .merge(::config::File::from_str(&rule, ::config::FileFormat::Toml))
+            .unwrap();
+        config.proxy = settings.get("proxy").unwrap();
+        let rule = config.proxy[0].clone();
+        send(config, format!("{}{}", head, body).as_bytes(), |stream, reader, request| {
+            request.secure = secure;
+            handle_proxy(stream, reader, request, &rule);
+        })
+    }
+
+    /// Body of a chunked response
+    fn decode_chunked(response: &str) -> String {
+        let framing = RequestHeaders {
+            transfer_encoding: "chunked".to_owned(),
+            ..RequestHeaders::default()
+        };
+        let mut chunks = response.split_once("\r\n\r\n").unwrap().1.as_bytes();
+        let mut body = String::new();
+        BodyReader::new(&mut chunks, &framing, u64::MAX)
+            .read_to_string(&mut body)
+            .unwrap();
+        body
+    }
+
+    #[test]
+    fn forwards_request_with_forwarding_headers() {
+        let (address, received) = upstream(Some("HTTP/1.1 201 Created\r\nContent-Length: 2\r\n\r\nok"));
+        let response = proxy(
+            &address,
+            "POST /api/items HTTP/1.1\r\nHost: example.com\r\nContent-Length: 5\r\nVia: 1.0 edge\r\n\
+             X-Forwarded-For: 10.0.0.1\r\n\r\n",
+            "hello",
+            5000,
+        );
+        let received = received.join().unwrap();
+        assert!(received.starts_with("POST /api/items HTTP/1.1\r\n"));
+        assert!(received.ends_with("\r\nhello"));
+        assert!(received.contains("Content-Length: 5\r\n"));
+        assert!(received.contains("Via: 1.0 edge, 1.1 akira\r\n"));
+        assert!(received.contains("X-Forwarded-For: 10.0.0.1, 127.0.0.1\r\n"));
+        assert!(received.contains("Forwarded: for=127.0.0.1;host=\"example.com\";proto=http\r\n"));
+        assert!(response.starts_with("HTTP/1.1 201 Created\r\n"));
+        assert!(response.ends_with("\r\n\r\nok"));
+    }
+
+    #[test]
+    fn removes_hop_by_hop_headers() {
+        let (address, received) = upstream(Some(
+            "HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: X-Secret\r\nX-Secret: 1\r\nKeep-Alive: timeout=5\r\n\
+             X-Kept: 1\r\n\r\n",
+        ));
+        let response = proxy(
+            &address,
+            "GET /api HTTP/1.1\r\nHost: example.com\r\nConnection: keep-alive, X-Private\r\nX-Private: 1\r\n\
+             Keep-Alive: timeout=5\r\nTE: trailers\r\nProxy-Authorization: Basic YTpi\r\nX-Public: 1\r\n\r\n",
+            "",
+            5000,
+        );
+        let received = received.join().unwrap().to_lowercase();
+        for name in ["x-private", "keep-alive:", "te:", "proxy-authorization"] {
+            assert!(!received.contains(name), "{} was forwarded", name);
+        }
+        assert!(received.contains("x-public: 1\r\n"));
+        assert!(received.contains("connection: keep-alive\r\n"));
+        let response = response.to_lowercase();
+        assert!(!response.contains("x-secret") && !response.contains("keep-alive:"));
+        assert!(response.contains("x-kept: 1\r\n"));
+    }
+
+    #[test]
+    fn sends_only_the_configured_strict_transport_security() {
+        let response = "HTTP/1.1 200 OK\r\nContent-Length: 0\r\nStrict-Transport-Security: max-age=1\r\n\r\n";
+        let head = "GET /api HTTP/1.1\r\nHost: example.com\r\n\r\n";
+        let (address, received) = upstream(Some(response));
+        let mut config = Config::load_for_tests();
+        config.tls.hsts_max_age = 0;
+        let sent = proxy_with(config, true, &address, head, "");
+        received.join().unwrap();
+        assert!(!sent.contains("Strict-Transport-Security"));
+
+        let (address, received) = upstream(Some(response));
+        let mut config = Config::load_for_tests();
+        config.tls.hsts_max_age = 600;
+        let sent = proxy_with(config, true, &address, head, "");
+        received.join().unwrap();
+        assert_eq!(sent.matches("Strict-Transport-Security").count(), 1);
+        assert!(sent.contains("Strict-Transport-Security: max-age=600\r\n"));
+    }
+
+    #[test]
+    fn streams_chunked_and_unknown_length_bodies() {
+        let (address, received) = upstream(Some(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n",
+        ));
+        let response = proxy(&address, "GET /api HTTP/1.1\r\nHost: example.com\r\n\r\n", "", 5000);
+        received.join().unwrap();
+        assert!(response.contains("Transfer-Encoding: chunked\r\n"));
+        assert_eq!(decode_chunked(&response), "hello world");
+
+        let (address, received) = upstream(Some("HTTP/1.1 200 OK\r\nConnection: close\r\n\r\nuntil closed"));
+        let response = proxy(&address, "GET /api HTTP/1.1\r\nHost: example.com\r\n\r\n", "", 5000);
+        received.join().unwrap();
+        assert!(response.contains("Transfer-Encoding: chunked\r\n"));
+        assert_eq!(decode_chunked(&response), "until closed");
+
+        let (address, received) = upstream(Some("HTTP/1.1 200 OK\r\nConnection: close\r\n\r\nuntil closed"));
+        let response = proxy(&address, "GET /api HTTP/1.0\r\nHost: example.com\r\n\r\n", "", 5000);
+        received.join().unwrap();
+        assert!(!response.contains("Transfer-Encoding"));
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(response.ends_with("\r\n\r\nuntil closed"));
+    }
+
+    #[test]
+    fn answers_upstream_failures() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        let response = proxy(&address, "GET /api HTTP/1.1\r\nHost: example.com\r\n\r\n", "", 5000);
+        assert!(response.starts_with("HTTP/1.1 502"));
+
+        let (address, received) = upstream(None);
+        let response = proxy(&address, "GET /api HTTP/1.1\r\nHost: example.com\r\n\r\n", "", 200);
+        received.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 504"));
+    }
+}
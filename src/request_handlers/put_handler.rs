@@ -0,0 +1,190 @@
+use std::fs;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::connection::stream::{RequestReader, Stream};
+use crate::request::reader::{copy_body, get_body_error_status, is_chunked};
+use crate::request::request::Request;
+use crate::request_handlers::auth::*;
+use crate::request_handlers::conditional::evaluate_change;
+use crate::request_handlers::get_handler::serve_error;
+use crate::request_handlers::options_handler::serve_method_not_allowed;
+use crate::response::headers::ResponseHeaders;
+use crate::response::status::HttpStatus;
+use crate::utils::{check_stream_write, truncate_str};
+use crate::utils::log::*;
+
+/// Bytes of the target name kept on temporary names, leaves room for the added prefix and suffix
+const MAX_TEMP_NAME_BYTES: usize = 200;
+
+/// Stores the request body on the requested path
+///
+/// The body is streamed to a temporary file next to the target that replaces it only once the
+/// upload is complete, so clients never see partial files
+pub fn handle_put(stream: &Stream, reader: &mut RequestReader<'_>, request: &mut Request) {
+    let settings = &request.config.publish;
+
+    // The body has not been read, the connection can not be reused if it is rejected
+    if !settings.active {
+        request.keep_alive = false;
+        return serve_method_not_allowed(stream, request);
+    }
+    if !is_authorized(request, &settings.credentials) {
+        request.keep_alive = false;
+        return serve_unauthorized(stream, request);
+    }
+
+    // Without a length the end of the body can not be known, it is never taken as empty
+    let headers = &request.request_headers;
+    if !headers.has_content_length && !is_chunked(&headers.transfer_encoding) {
+        request.keep_alive = false;
+        return serve_error(stream, request, HttpStatus::LengthRequired);
+    }
+
+    let local_path = request.local_path.clone();
+    let path: &Path = &local_path;
+    let parent_exists = match path.parent() {
+        Some(parent) => parent.is_dir(),
+        None => false,
+    };
+    if request.path.ends_with('/') || path.is_dir() || !parent_exists {
+        request.keep_alive = false;
+        return serve_error(stream, request, HttpStatus::Conflict);
+    }
+
+    let meta = fs::metadata(path).ok();
+    if let Some(status) = evaluate_change(meta.as_ref(), &request.request_headers, &request.method) {
+        request.keep_alive = false;
+        return serve_error(stream, request, status);
+    }
+
+    let existed = meta.is_some();
+    let temp_path = get_temp_path(path);
+    let file = match File::create(&temp_path) {
+        Ok(value) => value,
+        Err(err) => {
+            log_error(&err);
+            request.keep_alive = false;
+            return serve_error(stream, request, HttpStatus::InternalServerError);
+        },
+    };
+
+    request.send_continue(stream);
+    let mut writer = BufWriter::new(file);
+    let result = copy_body(reader, &request.request_headers, &mut writer, settings.max_body_bytes)
+        .and_then(|_copied| writer.flush())
+        .and_then(|_| fs::rename(&temp_path, path));
+
+    if let Err(err) = result {
+        log_warning(&err);
+        if let Err(err) = fs::remove_file(&temp_path) {
+            log_error(&err);
+        }
+        request.keep_alive = false;
+        return serve_error(stream, request, get_body_error_status(&err));
+    }
+
+    let mut headers = if existed {
+        ResponseHeaders::new(HttpStatus::NoContent)
+    } else {
+        let mut headers = ResponseHeaders::new(HttpStatus::Created);
+        headers.location = request.path.to_owned();
+        headers
+    };
+    request.set_connection_headers(&mut headers);
+    check_stream_write(request.send_head(stream, &mut headers));
+}
+
+/// Obtains a hidden temporary path on the same directory as the target, renaming is only atomic
+/// inside the same filesystem
+///
+/// The name of the target is shortened so the temporary name stays under the filesystem limit
+pub fn get_temp_path(path: &Path) -> PathBuf {
+    let name = match path.file_name() {
+        Some(value) => truncate_str(&value.to_string_lossy(), MAX_TEMP_NAME_BYTES).to_owned(),
+        None => String::new(),
+    };
+    let nanos = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(value) => value.as_nanos(),
+        Err(_err) => 0,
+    };
+    path.with_file_name(format!(".{}.akira-upload-{:x}", name, nanos))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request_handlers::conditional::Validators;
+    use crate::request_handlers::test_client::*;
+
+    fn put(root: &Path, target: &str, headers: &str, body: &str) -> String {
+        let sent = format!("PUT {} HTTP/1.1\r\nHost: a\r\n{}\r\n{}", target, headers, body);
+        send(config(root), sent.as_bytes(), handle_put)
+    }
+
+    #[test]
+    fn creates_and_replaces_files() {
+        let root = temp_root("put-test");
+        let response = put(&root, "/a.txt", &format!("{}Content-Length: 3\r\n", AUTHORIZATION), "one");
+        assert_eq!(status(&response), "201");
+        assert!(response.contains("Location: /a.txt\r\n"));
+        assert_eq!(fs::read_to_string(root.join("a.txt")).unwrap(), "one");
+
+        let headers = format!("{}Transfer-Encoding: chunked\r\n", AUTHORIZATION);
+        let response = put(&root, "/a.txt", &headers, "3\r\ntwo\r\n0\r\n\r\n");
+        assert_eq!(status(&response), "204");
+        assert_eq!(fs::read_to_string(root.join("a.txt")).unwrap(), "two");
+
+        let response = put(&root, "/empty.txt", &format!("{}Content-Length: 0\r\n", AUTHORIZATION), "");
+        assert_eq!(status(&response), "201");
+        assert_eq!(fs::read(root.join("empty.txt")).unwrap().len(), 0);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn refuses_unauthorized_and_unframed_uploads() {
+        let root = temp_root("put-refused-test");
+        fs::write(root.join("a.txt"), "old").unwrap();
+        let response = put(&root, "/a.txt", "Content-Length: 3\r\n", "new");
+        assert_eq!(status(&response), "401");
+        assert!(response.contains("WWW-Authenticate: Basic"));
+        let response = put(&root, "/a.txt", "Authorization: Basic YWRtaW46d3Jvbmc=\r\nContent-Length: 3\r\n", "new");
+        assert_eq!(status(&response), "401");
+
+        let response = put(&root, "/a.txt", AUTHORIZATION, "new");
+        assert_eq!(status(&response), "411");
+        assert!(response.contains("Connection: close\r\n"));
+        assert_eq!(fs::read_to_string(root.join("a.txt")).unwrap(), "old");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn checks_preconditions() {
+        let root = temp_root("put-conditional-test");
+        let path = root.join("a.txt");
+        fs::write(&path, "old").unwrap();
+        let e_tag = Validators::from_metadata(&fs::metadata(&path).unwrap()).e_tag;
+        let length = format!("{}Content-Length: 3\r\n", AUTHORIZATION);
+
+        let response = put(&root, "/a.txt", &format!("{}If-Match: \"other\"\r\n", length), "new");
+        assert_eq!(status(&response), "412");
+        let response = put(&root, "/a.txt", &format!("{}If-None-Match: *\r\n", length), "new");
+        assert_eq!(status(&response), "412");
+        let date = "If-Unmodified-Since: Thu, 01 Jan 1970 00:00:00 GMT\r\n";
+        let response = put(&root, "/a.txt", &format!("{}{}", length, date), "new");
+        assert_eq!(status(&response), "412");
+        let response = put(&root, "/b.txt", &format!("{}If-Match: *\r\n", length), "new");
+        assert_eq!(status(&response), "412");
+        assert!(!root.join("b.txt").exists());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+
+        let response = put(&root, "/a.txt", &format!("{}If-Match: {}\r\n", length, e_tag), "new");
+        assert_eq!(status(&response), "204");
+        let response = put(&root, "/b.txt", &format!("{}If-None-Match: *\r\n", length), "new");
+        assert_eq!(status(&response), "201");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,60 @@
+use crate::connection::stream::Stream;
+use crate::request::method::Method;
+use crate::request::path::encode_path;
+use crate::request::request::Request;
+use crate::response::headers::ResponseHeaders;
+use crate::response::status::HttpStatus;
+use crate::request_handlers::get_handler::serve_error;
+use crate::settings::vhost::normalize_host;
+use crate::utils::check_stream_write;
+
+/// Sends plain HTTP clients to the same resource over HTTPS
+///
+/// GET and HEAD get `301 Moved Permanently`, other methods `308 Permanent Redirect` so the
+/// method and body are kept
+pub fn handle_https_redirect(stream: &Stream, request: &mut Request) {
+    // Bodies uploaded with PUT or proxied are not read
+    if request.body_pending {
+        request.keep_alive = false;
+    }
+
+    let host = normalize_host(&request.request_headers.host);
+    if host.is_empty() {
+        serve_error(stream, request, HttpStatus::BadRequest);
+        return;
+    }
+
+    let mut location = format!("https://{}", host);
+    if request.config.tls.port != 443 {
+        location += &format!(":{}", request.config.tls.port);
+    }
+    location += &encode_path(&request.path);
+    if !request.query.is_empty() {
+        location += &format!("?{}", request.query);
+    }
+
+    let mut headers = match request.method {
+        Method::GET | Method::HEAD => ResponseHeaders::new(HttpStatus::MovedPermanently),
+        _ => ResponseHeaders::new(HttpStatus::PermanentRedirect),
+    };
+    headers.location = location;
+    request.set_connection_headers(&mut headers);
+    check_stream_write(request.send_head(stream, &mut headers));
+}
+
+/// Answers with the redirect chosen by a `[[rewrite]]` rule
+pub fn handle_rewrite_redirect(stream: &Stream, request: &mut Request) {
+    let (status, location) = match request.redirect.take() {
+        Some(value) => value,
+        None => return,
+    };
+    // Bodies uploaded with PUT or proxied are not read
+    if request.body_pending {
+        request.keep_alive = false;
+    }
+
+    let mut headers = ResponseHeaders::new(status);
+    headers.location = location;
+    request.set_connection_headers(&mut headers);
+    check_stream_write(request.send_head(stream, &mut headers));
+}
@@ -0,0 +1,83 @@
+//! Test Client
+//!
+//! Sends raw requests to the handlers over a local connection and returns what the client
+//! receives, with the config of the repository serving a temporary folder
+use std::fs;
+use std::io::{BufReader, Read};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::connection::stream::{RequestReader, Stream, StreamReader};
+use crate::request::request::Request;
+use crate::request_handlers::get_handler::serve_error;
+use crate::settings::settings::Config;
+
+/// Basic credentials of the `admin:secret` user allowed by `config`
+pub const AUTHORIZATION: &str = "Authorization: Basic YWRtaW46c2VjcmV0\r\n";
+
+/// Empty folder on the temporary directory, unique for each test
+pub fn temp_root(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("akira-{}-{}", name, std::process::id()));
+    fs::remove_dir_all(&root).ok();
+    fs::create_dir_all(&root).unwrap();
+    root
+}
+
+/// Config of the repository serving `root`, with publishing enabled for `admin:secret`
+pub fn config(root: &Path) -> Config {
+    let mut config = Config::load_for_tests();
+    config.vhost.clear();
+    config.server.root_folder = format!("{}/", root.display());
+    config.publish.active = true;
+    config.publish.credentials = vec!["admin:secret".to_owned()];
+    config.setup_virtual_hosts();
+    config
+}
+
+/// Parses the bytes sent by a client like the server does and lets `handler` answer them,
+/// returns the response received by the client
+pub fn send<F>(config: Config, sent: &[u8], handler: F) -> String
+where
+    F: FnOnce(&Stream, &mut RequestReader<'_>, &mut Request),
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let stream = Stream::Plain(listener.accept().unwrap().0);
+    let mut reader = BufReader::new(StreamReader::new(&stream, sent.to_vec()));
+    let mut request = Request::parse(&mut reader, None);
+    request.config = Arc::new(config);
+    request.vhost = request.config.get_virtual_host(&request.request_headers.host).clone();
+    request.keep_alive = request.wants_keep_alive();
+    // Proxied paths are not served from the root folder
+    let checked = if request.config.get_proxy_rule(&request.path).is_some() {
+        request.check_target()
+    } else {
+        request.check_path()
+    };
+    match checked {
+        Ok(_) => handler(&stream, &mut reader, &mut request),
+        Err(err) => serve_error(&stream, &request, err.status()),
+    }
+    drop(reader);
+    drop(stream);
+
+    let mut response = Vec::new();
+    (&client).read_to_end(&mut response).unwrap();
+    String::from_utf8_lossy(&response).to_string()
+}
+
+/// Parses the bytes sent by a client at 127.0.0.1 like the server does, for tests that only need
+/// the request
+pub fn parse(sent: &[u8]) -> Request {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let stream = Stream::Plain(listener.accept().unwrap().0);
+    let mut reader = BufReader::new(StreamReader::new(&stream, sent.to_vec()));
+    Request::parse(&mut reader, None)
+}
+
+/// Status code of a response
+pub fn status(response: &str) -> &str {
+    response.get(9..12).unwrap_or("")
+}
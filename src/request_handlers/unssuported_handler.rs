@@ -1,12 +1,13 @@
+use crate::connection::stream::Stream;
+use crate::request::request::Request;
 use crate::response::headers::ResponseHeaders;
 use crate::response::status::HttpStatus;
 use crate::utils::check_stream_write;
 use crate::utils::log::log_warning;
-use std::io::Write;
-use std::net::TcpStream;
 
-pub fn handle_unsupported(mut stream: &TcpStream) {
+pub fn handle_unsupported(stream: &Stream, request: &Request) {
     log_warning(&"Unsupported Method");
     let mut headers = ResponseHeaders::new(HttpStatus::NotImplemented);
-    check_stream_write(stream.write(&headers.get_headers().as_bytes()));
+    request.set_connection_headers(&mut headers);
+    check_stream_write(request.send_head(stream, &mut headers));
 }
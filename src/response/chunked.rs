@@ -0,0 +1,83 @@
+//! Chunked Transfer Coding
+//!
+//! Writer that sends a body of unknown length as a series of chunks, as defined on the
+//! [RFC](https://tools.ietf.org/html/rfc7230#section-4.1)
+use std::io::{Result, Write};
+
+use crate::response::headers::push_header;
+
+/// Wraps the connection, every write is sent as a chunk
+///
+/// `finish` must be called to send the last chunk and the trailer
+pub struct ChunkedWriter<W: Write> {
+    inner: W,
+    /// Bytes of body sent, without the chunk framing
+    length: u64,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    /// Creates a writer that sends chunks to `inner`
+    pub fn new(inner: W) -> ChunkedWriter<W> {
+        ChunkedWriter { inner, length: 0 }
+    }
+
+    /// Bytes of body sent so far, without the chunk framing
+    pub fn length(&self) -> u64 {
+        self.length
+    }
+
+    /// Sends the last chunk followed by the trailer fields, their names should have been announced
+    /// on the Trailer header
+    pub fn finish(mut self, trailers: &[(String, String)]) -> Result<W> {
+        let mut end: Vec<u8> = b"0\r\n".to_vec();
+        for (name, value) in trailers {
+            push_header(&mut end, name, value);
+        }
+        end.extend_from_slice(b"\r\n");
+        self.inner.write_all(&end)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        // An empty chunk would end the body
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut chunk: Vec<u8> = format!("{:x}\r\n", buf.len()).into_bytes();
+        chunk.extend_from_slice(buf);
+        chunk.extend_from_slice(b"\r\n");
+        self.inner.write_all(&chunk)?;
+        self.length += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_chunks_and_trailers() {
+        let mut writer = ChunkedWriter::new(Vec::new());
+        writer.write_all(b"hello world").unwrap();
+        writer.write_all(b"").unwrap();
+        assert_eq!(writer.length(), 11);
+        let trailers = [("Content-MD5".to_owned(), "abc".to_owned())];
+        let body = writer.finish(&trailers).unwrap();
+        assert_eq!(body, b"b\r\nhello world\r\n0\r\nContent-MD5: abc\r\n\r\n".to_vec());
+    }
+
+    #[test]
+    fn removes_line_breaks_from_trailers() {
+        let trailers = [("X-A\r\nSet-Cookie".to_owned(), "1\r\nSet-Cookie: b=2".to_owned())];
+        let body = ChunkedWriter::new(Vec::new()).finish(&trailers).unwrap();
+        assert_eq!(body, b"0\r\nX-ASet-Cookie: 1Set-Cookie: b=2\r\n\r\n".to_vec());
+    }
+}
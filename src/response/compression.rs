@@ -0,0 +1,118 @@
+//! Response Compression
+//!
+//! Writers that compress the body with the negotiated content coding
+extern crate brotli;
+extern crate flate2;
+
+use flate2::write::{GzEncoder, ZlibEncoder};
+use std::io::{Result, Write};
+
+/// Size of the internal brotli buffer in bytes
+const BROTLI_BUFFER: usize = 4096;
+/// Brotli window size
+const BROTLI_WINDOW: u32 = 22;
+
+/// Compresses everything written to it before passing it to the inner writer
+///
+/// `finish` must be called to write the end of the compressed stream
+pub enum Encoder<W: Write> {
+    /// "gzip"
+    Gzip(GzEncoder<W>),
+    /// "deflate", zlib format as the RFC requires
+    Deflate(ZlibEncoder<W>),
+    /// "br", boxed as its state is much bigger than the others
+    Brotli(Box<brotli::CompressorWriter<W>>),
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates the encoder for the content coding, None if the coding is not supported
+    pub fn new(coding: &str, level: u32, inner: W) -> Option<Encoder<W>> {
+        match coding {
+            "gzip" => Some(Encoder::Gzip(GzEncoder::new(inner, flate2::Compression::new(level)))),
+            "deflate" => Some(Encoder::Deflate(ZlibEncoder::new(inner, flate2::Compression::new(level)))),
+            "br" => Some(Encoder::Brotli(Box::new(brotli::CompressorWriter::new(
+                inner,
+                BROTLI_BUFFER,
+                level,
+                BROTLI_WINDOW,
+            )))),
+            _ => None,
+        }
+    }
+
+    /// Writes the end of the compressed stream and returns the inner writer
+    pub fn finish(self) -> Result<W> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Deflate(encoder) => encoder.finish(),
+            Encoder::Brotli(encoder) => Ok(encoder.into_inner()),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            Encoder::Deflate(encoder) => encoder.write(buf),
+            Encoder::Brotli(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Deflate(encoder) => encoder.flush(),
+            Encoder::Brotli(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Checks if the MIME type is text-like and worth compressing, already compressed formats like
+/// images, video or archives are not
+pub fn is_compressible(mime: &str) -> bool {
+    let mime = mime.to_lowercase();
+    mime.starts_with("text/")
+        || mime.contains("javascript")
+        || mime.contains("json")
+        || mime.contains("xml")
+        || mime.starts_with("image/svg")
+        || mime == "application/wasm"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn compress(coding: &str, data: &[u8]) -> Vec<u8> {
+        let mut encoder = Encoder::new(coding, 5, Vec::new()).unwrap();
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn compresses_with_each_coding() {
+        let data = b"akira akira akira akira akira akira".repeat(100);
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(&compress("gzip", &data)[..]).read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+        decoded.clear();
+        flate2::read::ZlibDecoder::new(&compress("deflate", &data)[..]).read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+        decoded.clear();
+        brotli::Decompressor::new(&compress("br", &data)[..], BROTLI_BUFFER).read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+        assert!(Encoder::new("compress", 5, Vec::new()).is_none());
+    }
+
+    #[test]
+    fn detects_compressible_types() {
+        assert!(is_compressible("text/html; charset=utf-8"));
+        assert!(is_compressible("application/javascript"));
+        assert!(is_compressible("application/ld+json"));
+        assert!(is_compressible("image/svg+xml"));
+        assert!(!is_compressible("image/png"));
+        assert!(!is_compressible("application/zip"));
+    }
+}
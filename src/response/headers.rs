@@ -2,10 +2,13 @@
 // https://tools.ietf.org/html/rfc2616
 // https://www.w3.org/Protocols/rfc2616/rfc2616-sec10.html
 use crate::response::status::*;
+use crate::utils::date::format_http_date;
+use std::time::SystemTime;
 
-// https://en.wikipedia.org/wiki/List_of_HTTP_header_fields
+/// Value sent on the Server header when none is set
+pub const SERVER_NAME: &str = concat!("AkiraServerV3/", env!("CARGO_PKG_VERSION"));
 
-// ToDo Complete code for all fields
+// https://en.wikipedia.org/wiki/List_of_HTTP_header_fields
 
 #[derive(Debug, Default)]
 /// Structure That can be used to easily add response headers to any http response
@@ -15,6 +18,16 @@ pub struct ResponseHeaders {
     // Standard response fields
     /// Specifying which web sites can participate in cross-origin resource sharing ( * means any )
     pub access_control_allow_origin: String,
+    /// Indicates whether the response can be shared when the request includes credentials
+    pub access_control_allow_credentials: String,
+    /// Headers allowed on the actual request, sent on preflight responses
+    pub access_control_allow_headers: String,
+    /// Methods allowed on the actual request, sent on preflight responses
+    pub access_control_allow_methods: String,
+    /// Headers that can be exposed to scripts
+    pub access_control_expose_headers: String,
+    /// Seconds the preflight response can be cached
+    pub access_control_max_age: String,
     /// Specifies which patch document formats this server supports
     pub accept_patch: String,
     /// What partial content range types this server supports via byte serving
@@ -79,8 +92,8 @@ pub struct ResponseHeaders {
     pub retry_after: String,
     /// A name for the server
     pub server: String,
-    /// An HTTP cookie
-    pub set_cookie: String,
+    /// HTTP cookies, one Set-Cookie header is sent for each one
+    pub set_cookie: Vec<String>,
     /// A HSTS Policy informing the HTTP client how long to cache the HTTPS only policy and whether
     /// this applies to subdomains
     pub strict_transport_security: String,
@@ -89,7 +102,9 @@ pub struct ResponseHeaders {
     pub trailer: String,
     /// The form of encoding used to safely transfer the entity to the user. Currently defined
     /// methods are: chunked, compress, deflate, gzip, identity
-    pub transfer_encoding: TransferEncoding,
+    ///
+    /// Codings are applied in order, chunked must be the last one
+    pub transfer_encoding: Vec<TransferEncoding>,
     /// Tracking Status header, value suggested to be sent in response to a DNT(do-not-track)
     pub tracking_status: String,
     /// Ask the client to upgrade to another protocol
@@ -110,6 +125,9 @@ pub struct ResponseHeaders {
     allowall - non-standard, allow from any location **/
     pub x_frame_options: String,
     // Common non-standard response fields
+
+    /// Custom headers as name and value pairs
+    pub other: Vec<(String, String)>,
 }
 
 #[allow(dead_code)]
@@ -132,63 +150,247 @@ impl ResponseHeaders {
     pub fn set_content_length(&mut self, content_lenght: u64) {
         self.content_length = content_lenght;
     }
+    /// Sets if the connection will be kept open after this response
+    pub fn set_connection(&mut self, keep_alive: bool) {
+        self.connection = if keep_alive { "keep-alive" } else { "close" }.to_owned();
+    }
+    /// Sends the body with chunked transfer coding, needed when the length is not known beforehand
+    pub fn set_chunked(&mut self) {
+        if !self.is_chunked() {
+            self.transfer_encoding.push(TransferEncoding::Chunked);
+        }
+    }
+    /// Checks if the body will be sent with chunked transfer coding
+    pub fn is_chunked(&self) -> bool {
+        self.transfer_encoding.contains(&TransferEncoding::Chunked)
+    }
     /// Sets response content type
     pub fn set_content_type(&mut self, content_type: String) {
         self.content_type = content_type;
     }
+    /// Adds a request header to the Vary list
+    pub fn add_vary(&mut self, header: &str) {
+        if self.vary.split(',').any(|value| value.trim().eq_ignore_ascii_case(header)) {
+            return;
+        }
+        if !self.vary.is_empty() {
+            self.vary += ", ";
+        }
+        self.vary += header;
+    }
+    /// Adds a cookie, each one is sent on its own Set-Cookie header
+    pub fn add_cookie(&mut self, cookie: String) {
+        self.set_cookie.push(cookie);
+    }
+    /// Adds a header that has no field on this struct
+    pub fn add_header(&mut self, name: &str, value: String) {
+        self.other.push((name.to_owned(), value));
+    }
     /// Obtains headers from data in the struct
+    ///
+    /// Every non empty field is sent, Date and Server are filled automatically if not set
     pub fn get_headers(&mut self) -> String {
+        if self.date.is_empty() {
+            self.date = format_http_date(SystemTime::now());
+        }
+        if self.server.is_empty() {
+            self.server = SERVER_NAME.to_owned();
+        }
+
         let mut headers: Vec<u8> = Vec::new();
-        headers.extend_from_slice(&self.status.as_bytes());
+        headers.extend_from_slice(self.status.as_bytes());
+        push_header(&mut headers, "Date", &self.date);
+        push_header(&mut headers, "Server", &self.server);
         // Cors
-        if self.access_control_allow_origin != "" {
-            let s = format!("Access-Control-Allow-Origin: {}\r\n", &self.access_control_allow_origin);
-            headers.extend_from_slice(s.as_bytes());
+        push_header(&mut headers, "Access-Control-Allow-Origin", &self.access_control_allow_origin);
+        push_header(&mut headers, "Access-Control-Allow-Credentials", &self.access_control_allow_credentials);
+        push_header(&mut headers, "Access-Control-Allow-Headers", &self.access_control_allow_headers);
+        push_header(&mut headers, "Access-Control-Allow-Methods", &self.access_control_allow_methods);
+        push_header(&mut headers, "Access-Control-Expose-Headers", &self.access_control_expose_headers);
+        push_header(&mut headers, "Access-Control-Max-Age", &self.access_control_max_age);
+        push_header(&mut headers, "Accept-Patch", &self.accept_patch);
+        // Ranges
+        push_header(&mut headers, "Accept-Ranges", &self.accept_ranges);
+        push_header(&mut headers, "Age", &self.age);
+        push_header(&mut headers, "Allow", &self.allow);
+        push_header(&mut headers, "Cache-Control", &self.cache_control);
+        // Connection
+        push_header(&mut headers, "Connection", &self.connection);
+        // Content
+        push_header(&mut headers, "Content-Disposition", &self.content_disposition);
+        push_header(&mut headers, "Content-Encoding", &self.content_encoding);
+        push_header(&mut headers, "Content-Language", &self.content_language);
+        // Content Lenght, always needed to know where the body ends on persistent connections
+        // unless the body is chunked
+        if self.status.allows_body() && !self.is_chunked() {
+            push_header(&mut headers, "Content-Length", &self.content_length.to_string());
         }
-        // Content Lenght
-        if self.content_length != 0 {
-            let s = format!("Content-Length: {}\r\n", &self.content_length);
-            headers.extend_from_slice(s.as_bytes());
+        push_header(&mut headers, "Content-Location", &self.content_location);
+        push_header(&mut headers, "Content-MD5", &self.content_md5);
+        push_header(&mut headers, "Content-Range", &self.content_range);
+        push_header(&mut headers, "Content-Type", &self.content_type);
+        push_header(&mut headers, "Delta-Base", &self.delta_base);
+        // Validators
+        push_header(&mut headers, "ETag", &self.e_tag);
+        push_header(&mut headers, "Expires", &self.expires);
+        push_header(&mut headers, "IM", &self.instance_manipulations);
+        push_header(&mut headers, "Last-Modified", &self.last_modified);
+        push_header(&mut headers, "Link", &self.link);
+        push_header(&mut headers, "Location", &self.location);
+        push_header(&mut headers, "P3P", &self.p3p);
+        push_header(&mut headers, "Pragma", &self.pragma);
+        push_header(&mut headers, "Proxy-Authenticate", &self.proxy_authenticate);
+        push_header(&mut headers, "Public-Key-Pins", &self.public_key_pins);
+        push_header(&mut headers, "Retry-After", &self.retry_after);
+        for cookie in &self.set_cookie {
+            push_header(&mut headers, "Set-Cookie", cookie);
         }
-        // Content Type
-        if self.content_type != "" {
-            let s = format!("Content-Type: {}\r\n", &self.content_type);
-            headers.extend_from_slice(s.as_bytes());
+        push_header(&mut headers, "Strict-Transport-Security", &self.strict_transport_security);
+        push_header(&mut headers, "Trailer", &self.trailer);
+        push_header(&mut headers, "Tk", &self.tracking_status);
+        let transfer_encoding: Vec<&str> = self
+            .transfer_encoding
+            .iter()
+            .filter(|coding| **coding != TransferEncoding::Identity)
+            .map(|coding| coding.to_str())
+            .collect();
+        push_header(&mut headers, "Transfer-Encoding", &transfer_encoding.join(", "));
+        push_header(&mut headers, "Upgrade", &self.upgrade);
+        push_header(&mut headers, "Vary", &self.vary);
+        push_header(&mut headers, "Via", &self.via);
+        push_header(&mut headers, "Warning", &self.warning);
+        push_header(&mut headers, "WWW-Authenticate", &self.www_authenticate);
+        push_header(&mut headers, "X-Frame-Options", &self.x_frame_options);
+        // Custom
+        for (name, value) in &self.other {
+            push_header(&mut headers, name, value);
         }
         headers.extend_from_slice(b"\r\n");
         String::from_utf8_lossy(headers.as_slice()).to_string()
     }
 }
 
+/// Appends the header to the buffer if it has a value
+///
+/// Line breaks are removed so names and values can not inject other headers, also used for the
+/// trailer fields of chunked bodies
+pub fn push_header(headers: &mut Vec<u8>, name: &str, value: &str) {
+    if value.is_empty() {
+        return;
+    }
+    let name: String = name.chars().filter(|c| *c != '\r' && *c != '\n').collect();
+    let value: String = value.chars().filter(|c| *c != '\r' && *c != '\n').collect();
+    headers.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+}
+
 #[allow(dead_code)] // Remove unused code warnings on compile time
-#[derive(Debug)]
-/// Possible Transfer Encoding Values
+#[derive(Debug, PartialEq)]
+/// Possible Transfer Codings, [RFC](https://tools.ietf.org/html/rfc7230#section-4)
 pub enum TransferEncoding {
-    /// ""
-    Undefined,
-    /// "!"
-    UnderConstruction,
-    /// "?"
-    Dynamic,
-    /// "G"
-    GatewayToMultipleParties,
-    /// "N"
-    NotTracking,
-    /// "T"
-    Tracking,
-    /// "C"
-    TrackingWithConsent,
-    /// "P"
-    TrackingOnlyIfConsented,
-    /// "D"
-    DisregardingDnt,
-    /// "U"
-    Updated,
+    /// "chunked", the body is sent as a series of chunks prefixed by their size
+    Chunked,
+    /// "gzip"
+    Gzip,
+    /// "deflate"
+    Deflate,
+    /// "identity", no transformation
+    Identity,
 }
 
 impl Default for TransferEncoding {
     /// Default Transfer Encoding Value
     fn default() -> TransferEncoding {
-        TransferEncoding::Undefined
+        TransferEncoding::Identity
+    }
+}
+
+impl TransferEncoding {
+    ///  Converts the given value to a String
+    pub fn to_str(&self) -> &str {
+        match *self {
+            TransferEncoding::Chunked => "chunked",
+            TransferEncoding::Gzip => "gzip",
+            TransferEncoding::Deflate => "deflate",
+            TransferEncoding::Identity => "identity",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(headers: &str) -> Vec<&str> {
+        headers.split("\r\n").collect()
+    }
+
+    #[test]
+    fn sends_the_set_headers_in_order() {
+        let mut headers = ResponseHeaders::new(HttpStatus::OK);
+        headers.date = "Sat, 09 Mar 2024 08:05:01 GMT".to_owned();
+        headers.set_content_type("text/plain".to_owned());
+        headers.set_content_length(4);
+        headers.set_connection(true);
+        headers.add_vary("Accept-Encoding");
+        headers.add_vary("accept-encoding");
+        headers.add_header("X-Custom", "a".to_owned());
+        headers.add_header("X-Other", "b".to_owned());
+
+        assert_eq!(
+            lines(&headers.get_headers()),
+            [
+                "HTTP/1.1 200 OK",
+                "Date: Sat, 09 Mar 2024 08:05:01 GMT",
+                &format!("Server: {}", SERVER_NAME),
+                "Connection: keep-alive",
+                "Content-Length: 4",
+                "Content-Type: text/plain",
+                "Vary: Accept-Encoding",
+                "X-Custom: a",
+                "X-Other: b",
+                "",
+                "",
+            ]
+        );
+    }
+
+    #[test]
+    fn fills_date_and_server_only_when_not_set() {
+        let mut headers = ResponseHeaders::new(HttpStatus::NoContent);
+        let sent = headers.get_headers();
+        assert!(sent.contains(&format!("\r\nDate: {}\r\n", headers.date)));
+        assert!(headers.date.ends_with(" GMT"));
+        assert!(sent.contains(&format!("\r\nServer: {}\r\n", SERVER_NAME)));
+        // No body is allowed, so no length is sent
+        assert!(!sent.contains("Content-Length"));
+
+        let mut headers = ResponseHeaders::new(HttpStatus::OK);
+        headers.server = "Custom".to_owned();
+        assert!(headers.get_headers().contains("\r\nServer: Custom\r\n"));
+    }
+
+    #[test]
+    fn sends_each_cookie_on_its_own_header() {
+        let mut headers = ResponseHeaders::new(HttpStatus::OK);
+        headers.add_cookie("a=1; Path=/".to_owned());
+        headers.add_cookie("b=2, c".to_owned());
+        headers.set_chunked();
+        headers.set_chunked();
+        let sent = headers.get_headers();
+        assert!(sent.contains("\r\nSet-Cookie: a=1; Path=/\r\nSet-Cookie: b=2, c\r\n"));
+        assert!(sent.contains("\r\nTransfer-Encoding: chunked\r\n"));
+        assert!(!sent.contains("Content-Length"));
+    }
+
+    #[test]
+    fn removes_line_breaks_from_names_and_values() {
+        let mut headers = ResponseHeaders::new(HttpStatus::OK);
+        headers.location = "/a\r\nSet-Cookie: injected".to_owned();
+        headers.add_header("X-A\r\nB", "c\nd".to_owned());
+        let sent = headers.get_headers();
+        assert!(sent.contains("\r\nLocation: /aSet-Cookie: injected\r\n"));
+        assert!(sent.contains("\r\nX-AB: cd\r\n"));
+        assert!(!sent.contains("\r\nSet-Cookie"));
+        assert!(!sent.contains("\r\nd"));
     }
 }
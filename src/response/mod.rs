@@ -1,3 +1,5 @@
 //! # Response Module
+pub mod chunked;
+pub mod compression;
 pub mod headers;
 pub mod status;
@@ -1,4 +1,4 @@
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 #[allow(dead_code)] // Removes unused code warnings on compile time
 /// Enum with most common HttpStatus Possible codes
 pub enum HttpStatus {
@@ -41,6 +41,8 @@ pub enum HttpStatus {
     Unused306,
     /// 307
     TemporaryRedirect,
+    /// 308
+    PermanentRedirect,
     // Client Error 4xx
     /// 400
     BadRequest,
@@ -78,6 +80,8 @@ pub enum HttpStatus {
     RequestedRangeNotSatisfiable,
     /// 417
     ExpectationFailed,
+    /// 431
+    RequestHeaderFieldsTooLarge,
     // Server Error 5xx
     /// 500
     InternalServerError,
@@ -136,6 +140,7 @@ impl HttpStatus {
             HttpStatus::UseProxy => b"HTTP/1.1 305 USE PROXY\r\n",
             HttpStatus::Unused306 => b"HTTP/1.1 306 \r\n",
             HttpStatus::TemporaryRedirect => b"HTTP/1.1 307 TEMPORARY REDIRECT\r\n",
+            HttpStatus::PermanentRedirect => b"HTTP/1.1 308 PERMANENT REDIRECT\r\n",
             // Client Error 4xx
             HttpStatus::BadRequest => b"HTTP/1.1 400 BAD REQUEST\r\n",
             HttpStatus::Unauthorized => b"HTTP/1.1 401 UNAUTHORIZED\r\n",
@@ -159,6 +164,7 @@ impl HttpStatus {
                 b"HTTP/1.1 416 REQUEST RANGE NOT SATISFIABLE\r\n"
             }
             HttpStatus::ExpectationFailed => b"HTTP/1.1 417 EXPECTATION FAILED\r\n",
+            HttpStatus::RequestHeaderFieldsTooLarge => b"HTTP/1.1 431 REQUEST HEADER FIELDS TOO LARGE\r\n",
             // Server Error 5xx
             HttpStatus::InternalServerError => b"HTTP/1.1 500 INTERNAL SERVER ERROR\r\n",
             HttpStatus::NotImplemented => b"HTTP/1.1 501 NOT IMPLEMENTED\r\n",
@@ -169,6 +175,14 @@ impl HttpStatus {
         }
     }
 
+    /// Checks if a response with this status can have a body
+    pub fn allows_body(&self) -> bool {
+        !matches!(
+            *self,
+            HttpStatus::Continue | HttpStatus::SwitchingProtocols | HttpStatus::NoContent | HttpStatus::NotModified
+        )
+    }
+
     /// Convert HttpStatus to bytes that can be sent with the response
     pub fn to_int(&self) -> i32 {
         match *self {
@@ -195,6 +209,7 @@ impl HttpStatus {
             HttpStatus::UseProxy => 305,
             HttpStatus::Unused306 => 306,
             HttpStatus::TemporaryRedirect => 307,
+            HttpStatus::PermanentRedirect => 308,
             // Client Error 4xx
             HttpStatus::BadRequest => 400,
             HttpStatus::Unauthorized => 401,
@@ -218,6 +233,7 @@ impl HttpStatus {
                 416
             }
             HttpStatus::ExpectationFailed => 417,
+            HttpStatus::RequestHeaderFieldsTooLarge => 431,
             // Server Error 5xx
             HttpStatus::InternalServerError => 500,
             HttpStatus::NotImplemented => 501,
@@ -0,0 +1,40 @@
+//! Compression Settings
+#[derive(Debug, Deserialize)]
+/// Contains Response Compression Releated Config
+pub struct Compression {
+    /// Define if responses will be compressed when the client accepts it
+    pub active: bool,
+    /// Content codings that can be used, in order of preference
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// ["br", "gzip", "deflate"]
+    /// ```
+    ///
+    /// Brotli will be used if the client accepts it with the same quality as the others
+    pub algorithms: Vec<String>,
+    /// Define if precompressed copies of the files (`file.ext.br`, `file.ext.gz`) will be served
+    /// instead of compressing them on each request
+    pub precompressed: bool,
+    /// Responses smaller than this size in bytes will be sent uncompressed
+    pub min_size: u64,
+    /// Gzip compression level, from 0 to 9
+    pub gzip_level: u32,
+    /// Deflate compression level, from 0 to 9
+    pub deflate_level: u32,
+    /// Brotli compression quality, from 0 to 11
+    pub brotli_level: u32,
+}
+
+impl Compression {
+    /// Obtains the configured level for the given content coding
+    pub fn get_level(&self, coding: &str) -> u32 {
+        match coding {
+            "gzip" => self.gzip_level,
+            "deflate" => self.deflate_level,
+            "br" => self.brotli_level,
+            _ => 0,
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//! Cors Settings
+#[derive(Debug, Deserialize)]
+/// Contains Cross-Origin Resource Sharing Releated Config
+pub struct Cors {
+    /// Define if CORS headers will be sent
+    pub active: bool,
+    /// Origins allowed to read the responses, `*` allows any origin
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// ["https://example.com", "http://localhost:3000"]
+    /// ```
+    pub allowed_origins: Vec<String>,
+    /// Methods allowed on cross-origin requests
+    pub allowed_methods: Vec<String>,
+    /// Request headers allowed on cross-origin requests
+    pub allowed_headers: Vec<String>,
+    /// Response headers that the browser will let scripts read
+    pub exposed_headers: Vec<String>,
+    /// Define if cookies and authorization headers are allowed, when enabled the origin is always
+    /// echoed instead of `*`
+    pub allow_credentials: bool,
+    /// Seconds the browser can cache the preflight response
+    pub max_age: u64,
+}
+
+impl Cors {
+    /// Checks if the origin is allowed by the policy
+    pub fn is_origin_allowed(&self, origin: &str) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(origin))
+    }
+
+    /// Checks if the allowed origin header depends on the request origin, `*` can not be used when
+    /// credentials are allowed
+    pub fn echoes_origin(&self) -> bool {
+        self.allow_credentials || !self.allowed_origins.iter().any(|allowed| allowed == "*")
+    }
+
+    /// Checks if the method is allowed by the policy
+    pub fn is_method_allowed(&self, method: &str) -> bool {
+        self.allowed_methods
+            .iter()
+            .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(method))
+    }
+
+    /// Checks if every header of the comma separated list is allowed by the policy
+    pub fn are_headers_allowed(&self, headers: &str) -> bool {
+        headers
+            .split(',')
+            .map(|header| header.trim())
+            .filter(|header| !header.is_empty())
+            .all(|header| {
+                self.allowed_headers
+                    .iter()
+                    .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(header))
+            })
+    }
+}
@@ -14,4 +14,20 @@ pub struct Debug {
     pub log_to_console: bool,
     /// Define if the program will log data to a file
     pub log_to_file: bool,
+    /// Format of the log lines: `text`, colored on terminals, or `json` with one object per line
+    pub format: String,
+    /// File the log is written to when `log_to_file` is enabled
+    pub log_file: String,
+    /// File each request is logged to, empty to disable the access log
+    pub access_log: String,
+    /// Format of the access log: `common`, `combined` or `json`, combined adds the referer, the
+    /// user agent and the miliseconds taken to answer
+    pub access_log_format: String,
+    /// Log files are rotated when they reach this size in bytes, 0 to never rotate by size
+    pub rotate_bytes: u64,
+    /// Log files are rotated when the `hour` or the `day` changes, `never` to only rotate by size
+    pub rotate_every: String,
+    /// Number of rotated files kept next to the log as `file.1`, `file.2`..., older ones are
+    /// removed
+    pub keep_files: usize,
 }
@@ -1,5 +1,12 @@
 //! # Settings Module
+pub mod compression;
+pub mod cors;
 pub mod debug;
+pub mod proxy;
+pub mod publish;
+pub mod rewrite;
 pub mod server;
 pub mod settings;
 pub mod timeouts;
+pub mod tls;
+pub mod vhost;
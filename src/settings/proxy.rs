@@ -0,0 +1,118 @@
+//! Proxy Settings
+use std::time::Duration;
+
+/// Ways to choose the server of a request
+pub const PROXY_STRATEGIES: [&str; 3] = ["round_robin", "least_connections", "ip_hash"];
+
+#[derive(Clone, Debug, Deserialize)]
+/// Contains the Config of a path prefix whose requests are forwarded to another server
+pub struct ProxyRule {
+    /// Requests whose path starts with this prefix are forwarded, it only matches whole
+    /// segments
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// "/api"
+    /// ```
+    ///
+    /// Will forward `/api` and `/api/users` but not `/apis`
+    pub prefix: String,
+    /// Server the requests are forwarded to as `host:port`
+    #[serde(default)]
+    pub upstream: String,
+    /// Servers the requests are spread across, used with `upstream` when both are set
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// ["10.0.0.1:9000", "10.0.0.2:9000"]
+    /// ```
+    #[serde(default)]
+    pub upstreams: Vec<String>,
+    /// How the server of each request is chosen: `round_robin`, `least_connections` or
+    /// `ip_hash`, which sends each client to the same server while it is up
+    #[serde(default)]
+    pub strategy: Option<String>,
+    /// Failed requests in a row after which a server is skipped for `fail_timeout_miliseconds`
+    #[serde(default)]
+    pub max_fails: Option<u32>,
+    /// How much time a failing server is skipped
+    #[serde(default)]
+    pub fail_timeout_miliseconds: Option<u64>,
+    /// Path requested periodically from each server, servers that do not answer it with a 2xx
+    /// or 3xx status are skipped until they do
+    #[serde(default)]
+    pub health_check: Option<String>,
+    /// How much time is waited between health checks
+    #[serde(default)]
+    pub health_check_interval_miliseconds: Option<u64>,
+    /// Idle connections kept open to each server to be reused by the next requests, 0 opens a
+    /// new connection for each request
+    #[serde(default)]
+    pub keep_alive_connections: Option<usize>,
+    /// Defines if the prefix is removed from the path sent to the upstream
+    #[serde(default)]
+    pub strip_prefix: bool,
+    /// Defines if the Host header of the client is sent instead of the upstream address
+    #[serde(default)]
+    pub preserve_host: bool,
+}
+
+impl ProxyRule {
+    /// Servers the requests are forwarded to
+    pub fn get_upstreams(&self) -> Vec<&str> {
+        std::iter::once(&self.upstream)
+            .filter(|upstream| !upstream.is_empty())
+            .chain(self.upstreams.iter())
+            .map(|upstream| upstream.as_str())
+            .collect()
+    }
+
+    /// How the server of each request is chosen
+    pub fn get_strategy(&self) -> &str {
+        self.strategy.as_deref().unwrap_or("round_robin")
+    }
+
+    /// Failed requests in a row after which a server is skipped
+    pub fn get_max_fails(&self) -> u32 {
+        self.max_fails.unwrap_or(3)
+    }
+
+    /// How much time a failing server is skipped
+    pub fn get_fail_timeout_duration(&self) -> Duration {
+        Duration::from_millis(self.fail_timeout_miliseconds.unwrap_or(10000))
+    }
+
+    /// How much time is waited between health checks
+    pub fn get_health_check_interval_duration(&self) -> Duration {
+        Duration::from_millis(self.health_check_interval_miliseconds.unwrap_or(5000))
+    }
+
+    /// Idle connections kept open to each server
+    pub fn get_keep_alive_connections(&self) -> usize {
+        self.keep_alive_connections.unwrap_or(8)
+    }
+
+    /// Checks if the path is forwarded by this rule
+    pub fn matches(&self, path: &str) -> bool {
+        let prefix = self.prefix.trim_end_matches('/');
+        match path.strip_prefix(prefix) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/'),
+            None => false,
+        }
+    }
+
+    /// Obtains the path sent to the upstream
+    pub fn get_upstream_path(&self, path: &str) -> String {
+        if !self.strip_prefix {
+            return path.to_owned();
+        }
+        let rest = &path[self.prefix.trim_end_matches('/').len()..];
+        if rest.starts_with('/') {
+            rest.to_owned()
+        } else {
+            format!("/{}", rest)
+        }
+    }
+}
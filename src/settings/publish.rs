@@ -0,0 +1,32 @@
+//! Publish Settings
+#[derive(Debug, Deserialize)]
+/// Contains Config Releated to Uploading and Deleting files with PUT and DELETE
+pub struct Publish {
+    /// Define if PUT and DELETE requests will be accepted, when disabled the root folder is
+    /// read-only
+    pub active: bool,
+    /// Users allowed to publish files, used with HTTP Basic authentication
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// ["admin:secret"]
+    /// ```
+    ///
+    /// Allows the user `admin` with the password `secret`
+    pub credentials: Vec<String>,
+    /// Maximum size in bytes of an uploaded file
+    pub max_body_bytes: u64,
+    /// What happens when a file uploaded from a form already exists
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// "rename"
+    /// ```
+    ///
+    /// `file.txt` will be stored as `file (1).txt`, other values are `replace` and `reject`
+    pub upload_overwrite: String,
+    /// Defines if the directory listing will show a form to upload files
+    pub upload_form: bool,
+}
@@ -0,0 +1,135 @@
+//! Rewrite Settings
+use std::collections::HashMap;
+
+use regex::{Captures, Regex};
+
+use crate::response::status::HttpStatus;
+
+/// Status codes a rule can redirect with
+pub const REDIRECT_CODES: [u16; 4] = [301, 302, 307, 308];
+
+#[derive(Clone, Debug, Deserialize)]
+/// Contains the Config of a rule that changes the path of the requests matching it, or
+/// redirects them
+pub struct RewriteRule {
+    /// Regular expression matched against the decoded path, without the query
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// "^/blog/(\\d+)/(?P<slug>[^/]+)$"
+    /// ```
+    pub pattern: String,
+    /// New path, `$1` or `${slug}` are replaced by the captures of the pattern and a `?` starts
+    /// a query that replaces the one of the request, which is kept otherwise
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// "/posts/${slug}.html?id=$1"
+    /// ```
+    #[serde(default)]
+    pub to: Option<String>,
+    /// Paths tried in order instead of `to`, the first one that exists is used and the last one
+    /// when none does
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// ["$0", "/index.html"]
+    /// ```
+    ///
+    /// With `^/.*$` as pattern, serves the files that exist and `/index.html` for the rest
+    #[serde(default)]
+    pub try_files: Vec<String>,
+    /// Answers with this status and `to` as Location instead of serving the new path, `to`
+    /// can be a full URL
+    #[serde(default)]
+    pub redirect: Option<u16>,
+    /// Regular expression the host of the request has to match, without port and in lowercase
+    #[serde(default)]
+    pub host: Option<String>,
+    /// Methods the rule applies to, all of them when empty
+    #[serde(default)]
+    pub methods: Vec<String>,
+    /// Regular expressions the value of each header has to match, requests without the header
+    /// do not match
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Defines if the next rules are skipped when this one matches
+    #[serde(default)]
+    pub last: bool,
+    #[serde(skip)]
+    pattern_regex: Option<Regex>,
+    #[serde(skip)]
+    host_regex: Option<Regex>,
+    #[serde(skip)]
+    header_regexes: Vec<(String, Regex)>,
+}
+
+impl RewriteRule {
+    /// Compiles the regular expressions of the rule, failing if any is not valid
+    pub fn compile(&mut self) -> Result<(), String> {
+        let compile =
+            |value: &str| Regex::new(value).map_err(|error| format!("Invalid rewrite regex {}: {}", value, error));
+        self.pattern_regex = Some(compile(&self.pattern)?);
+        self.host_regex = match &self.host {
+            Some(value) => Some(compile(value)?),
+            None => None,
+        };
+        self.header_regexes = Vec::new();
+        for (name, value) in &self.headers {
+            self.header_regexes.push((name.to_owned(), compile(value)?));
+        }
+        Ok(())
+    }
+
+    /// Checks the conditions of the rule and matches the path, returns the captures of the
+    /// pattern when the rule applies
+    pub fn captures<'p>(
+        &self,
+        path: &'p str,
+        host: &str,
+        method: &str,
+        headers: &[(&str, &str)],
+    ) -> Option<Captures<'p>> {
+        if !self.methods.is_empty() && !self.methods.iter().any(|value| value.eq_ignore_ascii_case(method)) {
+            return None;
+        }
+        if self.host_regex.as_ref().is_some_and(|regex| !regex.is_match(host)) {
+            return None;
+        }
+        for (name, regex) in &self.header_regexes {
+            let matched = headers
+                .iter()
+                .any(|(header, value)| header.eq_ignore_ascii_case(name) && regex.is_match(value));
+            if !matched {
+                return None;
+            }
+        }
+        self.pattern_regex.as_ref()?.captures(path)
+    }
+
+    /// Replaces the captures on a path of `to` or `try_files`, splitting the query from it
+    pub fn expand(&self, template: &str, captures: &Captures<'_>) -> (String, Option<String>) {
+        let mut parts = template.splitn(2, '?');
+        let mut path = String::new();
+        captures.expand(parts.next().unwrap_or(""), &mut path);
+        let query = parts.next().map(|value| {
+            let mut query = String::new();
+            captures.expand(value, &mut query);
+            query
+        });
+        (path, query)
+    }
+
+    /// Status of the redirect, `None` when the path is rewritten
+    pub fn get_redirect_status(&self) -> Option<HttpStatus> {
+        match self.redirect? {
+            301 => Some(HttpStatus::MovedPermanently),
+            302 => Some(HttpStatus::Found),
+            307 => Some(HttpStatus::TemporaryRedirect),
+            _ => Some(HttpStatus::PermanentRedirect),
+        }
+    }
+}
@@ -2,7 +2,7 @@
 #[derive(Debug, Deserialize)]
 /// Contains Server Releated Config
 pub struct Server {
-    /// Wich ip will the server listen on
+    /// Wich ip will the server listen on when `listen` is empty
     ///
     /// # Example
     ///
@@ -12,7 +12,7 @@ pub struct Server {
     ///
     /// Will Listen on all available adresses
     pub host: String,
-    /// Wich port will the server listen on
+    /// Wich port will the server listen on when `listen` is empty
     ///
     /// # Example
     ///
@@ -21,7 +21,18 @@ pub struct Server {
     /// ```
     ///
     /// Will Listen on the default http port
-    pub port: String,
+    pub port: u16,
+    /// Addresses the server will listen on, IPv4, IPv6 or Unix sockets prefixed with `unix:`
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// ["0.0.0.0:80", "[::]:80", "unix:/run/akira.sock"]
+    /// ```
+    ///
+    /// Will Listen on the default http port of every interface and on a local socket
+    #[serde(default)]
+    pub listen: Vec<String>,
     /// [TTL](https://en.wikipedia.org/wiki/Time_to_live)  
     ///
     /// # Example
@@ -52,6 +63,17 @@ pub struct Server {
     ///
     /// The will list directory content
     pub list_directories: bool,
+    /// Defines if symlinks inside the root folder will be followed, even when enabled they can
+    /// only point to files inside the root folder
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// false
+    /// ```
+    ///
+    /// Requests for paths that go through a symlink will be refused with 403
+    pub follow_symlinks: bool,
     /// Defines the maximum number of works that will be running on each logical processor
     ///
     /// # Example
@@ -62,6 +84,75 @@ pub struct Server {
     ///
     /// On a 16 core cpu this will be max 32 total threads
     pub workers_per_thread: usize,
+    /// Maximum number of requests served over the same persistent connection before closing it
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// 100
+    /// ```
+    ///
+    /// The 100th response will be sent with `Connection: close`
+    pub max_requests_per_connection: usize,
+    /// Maximum number of client connections open at the same time, new ones are closed right
+    /// after being accepted while the limit is reached
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// 10000
+    /// ```
+    ///
+    /// Idle connections waiting for their next request count towards the limit
+    pub max_connections: usize,
+    /// Defines if the config will be reloaded when `Settings.toml` changes, SIGHUP reloads it
+    /// too on Unix
+    #[serde(default)]
+    pub watch_settings: bool,
+    /// Maximum size in bytes of a multipart form kept in memory, the parts that do not fit are
+    /// stored on temporary files while the request is handled
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// 1048576
+    /// ```
+    ///
+    /// Uploaded files will be written to the temporary directory once the form goes over 1MiB
+    pub multipart_memory_limit: usize,
 
     pub index: Vec<String>,
 }
+
+impl Server {
+    /// Obtains the addresses to listen on, `host` and `port` when `listen` is empty
+    pub fn get_listen_addresses(&self) -> Vec<String> {
+        get_listen_addresses(&self.listen, &self.host, self.port)
+    }
+}
+
+/// Obtains the listen list or an address built from the host and port when it is empty
+pub fn get_listen_addresses(listen: &[String], host: &str, port: u16) -> Vec<String> {
+    if !listen.is_empty() {
+        listen.to_vec()
+    } else if host.contains(':') {
+        // IPv6
+        vec![format!("[{}]:{}", host, port)]
+    } else {
+        vec![format!("{}:{}", host, port)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_listen_addresses() {
+        let listen = vec!["0.0.0.0:80".to_owned(), "unix:/run/akira.sock".to_owned()];
+        assert_eq!(get_listen_addresses(&listen, "localhost", 8080), listen);
+        assert_eq!(get_listen_addresses(&[], "0.0.0.0", 80), ["0.0.0.0:80"]);
+        assert_eq!(get_listen_addresses(&[], "localhost", 8080), ["localhost:8080"]);
+        assert_eq!(get_listen_addresses(&[], "::", 443), ["[::]:443"]);
+    }
+}
@@ -1,24 +1,64 @@
 /// # Config Manager
 // Config
 extern crate config;
-// extern crate notify;
+extern crate notify;
 extern crate serde;
 extern crate serde_derive;
 
+use std::path::Path;
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use arc_swap::ArcSwap;
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::settings::compression::*;
+use crate::settings::cors::*;
 use crate::settings::debug::*;
+use crate::settings::proxy::*;
+use crate::settings::publish::*;
+use crate::settings::rewrite::*;
 use crate::settings::server::*;
 use crate::settings::timeouts::*;
+use crate::settings::tls::*;
+use crate::settings::vhost::*;
 
+use crate::connection::tls::load_tls_config;
 use crate::utils;
+use crate::utils::log::*;
 use std::result::Result;
 use termcolor::Color;
 
+/// File the config is read from
+const SETTINGS_FILE: &str = "Settings.toml";
+/// Content codings the server can compress responses with
+const COMPRESSION_ALGORITHMS: [&str; 3] = ["br", "gzip", "deflate"];
+
 #[derive(Debug, Deserialize)]
 /// Contains all the settings fragments
 pub struct Config {
     pub server: Server,
     pub timeout: Timeouts,
+    pub tls: Tls,
+    pub compression: Compression,
+    pub cors: Cors,
+    pub publish: Publish,
     pub debug: Debug,
+    /// Sites selected with the Host header, from the `[[vhost]]` tables, shared with the
+    /// requests they serve
+    #[serde(default)]
+    pub vhost: Vec<Arc<VirtualHost>>,
+    /// Path prefixes forwarded to other servers, from the `[[proxy]]` tables
+    #[serde(default)]
+    pub proxy: Vec<ProxyRule>,
+    /// Rules applied in order to the path of each request, from the `[[rewrite]]` tables
+    #[serde(default)]
+    pub rewrite: Vec<RewriteRule>,
+    /// Site built from `[Server]` for requests that do not match any virtual host
+    #[serde(skip)]
+    pub default_host: Arc<VirtualHost>,
 }
 
 impl Config {
@@ -29,7 +69,7 @@ impl Config {
         let mut s = config::Config::new();
 
         // Start off by merging in the "default" configuration file
-        s.merge(config::File::with_name("Settings.toml"))?;
+        s.merge(config::File::with_name(SETTINGS_FILE))?;
 
         // You may also programmatically change settings
         //s.set("database.url", "postgres://")?;
@@ -38,46 +78,342 @@ impl Config {
         s.try_into()
     }
 
+    /// Loads the config and fills the values taken from other sections, failing if any value is
+    /// not valid
+    pub fn load() -> Result<Self, String> {
+        let mut value = Config::new().map_err(|error| error.to_string())?;
+        if !value.server.root_folder.ends_with('/') {
+            value.server.root_folder.push('/');
+        }
+        value.setup_virtual_hosts();
+        for rule in &mut value.rewrite {
+            rule.compile()?;
+        }
+        value.validate()?;
+        Ok(value)
+    }
+
+    /// Config of the repository for the tests, they log to the console only so they do not
+    /// write the log files
+    #[cfg(test)]
+    pub fn load_for_tests() -> Self {
+        let mut value = Config::load().unwrap();
+        value.debug.log_to_file = false;
+        value.debug.access_log.clear();
+        value
+    }
+
+    /// Checks the values that can not be used
+    fn validate(&self) -> Result<(), String> {
+        if self.server.workers_per_thread == 0 {
+            return Err("workers_per_thread must be at least 1".to_owned());
+        }
+        if self.server.max_connections == 0 {
+            return Err("max_connections must be at least 1".to_owned());
+        }
+        for algorithm in &self.compression.algorithms {
+            if !COMPRESSION_ALGORITHMS.contains(&algorithm.as_str()) {
+                return Err(format!("Unknown compression algorithm {}", algorithm));
+            }
+        }
+        if self.compression.gzip_level > 9 || self.compression.deflate_level > 9 || self.compression.brotli_level > 11 {
+            return Err("Compression level out of range".to_owned());
+        }
+        if !["rename", "replace", "reject"].contains(&self.publish.upload_overwrite.as_str()) {
+            return Err(format!("Unknown upload_overwrite {}", self.publish.upload_overwrite));
+        }
+        if !["text", "json"].contains(&self.debug.format.as_str()) {
+            return Err(format!("Unknown log format {}", self.debug.format));
+        }
+        if !["common", "combined", "json"].contains(&self.debug.access_log_format.as_str()) {
+            return Err(format!("Unknown access_log_format {}", self.debug.access_log_format));
+        }
+        if !["hour", "day", "never"].contains(&self.debug.rotate_every.as_str()) {
+            return Err(format!("Unknown rotate_every {}", self.debug.rotate_every));
+        }
+        for rule in &self.proxy {
+            if !rule.prefix.starts_with('/') {
+                return Err(format!("Proxy prefix {} must start with /", rule.prefix));
+            }
+            if rule.get_upstreams().is_empty() {
+                return Err(format!("Proxy {} has no upstream", rule.prefix));
+            }
+            if let Some(upstream) = rule.get_upstreams().iter().find(|upstream| !upstream.contains(':')) {
+                return Err(format!("Proxy upstream {} must be host:port", upstream));
+            }
+            if !PROXY_STRATEGIES.contains(&rule.get_strategy()) {
+                return Err(format!("Unknown proxy strategy {}", rule.get_strategy()));
+            }
+            if rule.health_check.as_ref().is_some_and(|path| !path.starts_with('/')) {
+                return Err(format!("Proxy {} health_check must start with /", rule.prefix));
+            }
+        }
+        for rule in &self.rewrite {
+            if rule.to.is_some() == !rule.try_files.is_empty() {
+                return Err(format!("Rewrite {} needs either to or try_files", rule.pattern));
+            }
+            match rule.redirect {
+                Some(code) if !REDIRECT_CODES.contains(&code) => {
+                    return Err(format!("Rewrite {} can not redirect with {}", rule.pattern, code));
+                }
+                Some(_code) if rule.to.is_none() => {
+                    return Err(format!("Rewrite {} redirects without to", rule.pattern));
+                }
+                None if rule.to.as_ref().is_some_and(|to| !to.starts_with('/')) => {
+                    return Err(format!("Rewrite {} to must start with /", rule.pattern));
+                }
+                _ => {}
+            }
+        }
+        if self.tls.active {
+            load_tls_config(self)?;
+        }
+        Ok(())
+    }
+
+    /// Reports the root folders that do not exist, the server can still answer with errors
+    pub fn check_root_folders(&self) {
+        let roots = std::iter::once(&self.server.root_folder).chain(self.vhost.iter().map(|vhost| &vhost.root_folder));
+        for root in roots {
+            if !Path::new(root).is_dir() {
+                log_warning(&format!("Root folder {} is not a directory", root));
+            }
+        }
+    }
+
+    /// Checks if the changes from `other` can only be applied by restarting the server
+    pub fn needs_restart(&self, other: &Config) -> bool {
+        let certificates = |config: &Config| -> Vec<(Option<String>, Option<String>)> {
+            config
+                .vhost
+                .iter()
+                .map(|vhost| (vhost.certificate.clone(), vhost.private_key.clone()))
+                .collect()
+        };
+        self.server.get_listen_addresses() != other.server.get_listen_addresses()
+            || self.server.ttl != other.server.ttl
+            || self.server.workers_per_thread != other.server.workers_per_thread
+            || self.tls.active != other.tls.active
+            || self.tls.serve_http != other.tls.serve_http
+            || self.tls.redirect_http != other.tls.redirect_http
+            || self.tls.get_listen_addresses(&self.server) != other.tls.get_listen_addresses(&other.server)
+            || self.tls.certificate != other.tls.certificate
+            || self.tls.private_key != other.tls.private_key
+            || certificates(self) != certificates(other)
+    }
+
+    /// Takes the values that are only applied by a restart from the running config, so the
+    /// requests never see listeners or TLS settings the server does not have
+    pub fn keep_restart_values(&mut self, running: &Config) {
+        self.server.host = running.server.host.clone();
+        self.server.port = running.server.port;
+        self.server.listen = running.server.listen.clone();
+        self.server.ttl = running.server.ttl;
+        self.server.workers_per_thread = running.server.workers_per_thread;
+        self.tls.active = running.tls.active;
+        self.tls.port = running.tls.port;
+        self.tls.listen = running.tls.listen.clone();
+        self.tls.certificate = running.tls.certificate.clone();
+        self.tls.private_key = running.tls.private_key.clone();
+        self.tls.serve_http = running.tls.serve_http;
+        self.tls.redirect_http = running.tls.redirect_http;
+        // Sites keep the certificate loaded for their names, new ones use the one from `[Tls]`
+        for host in &mut self.vhost {
+            let loaded = running.vhost.iter().find(|other| other.server_names == host.server_names);
+            let host = Arc::make_mut(host);
+            host.certificate = loaded.and_then(|other| other.certificate.clone());
+            host.private_key = loaded.and_then(|other| other.private_key.clone());
+        }
+    }
+
+    /// Fills the values virtual hosts take from `[Server]` and builds the default host
+    pub fn setup_virtual_hosts(&mut self) {
+        for host in &mut self.vhost {
+            let host = Arc::make_mut(host);
+            if !host.root_folder.ends_with('/') {
+                host.root_folder.push('/');
+            }
+            if host.index.is_none() {
+                host.index = Some(self.server.index.clone());
+            }
+            if host.list_directories.is_none() {
+                host.list_directories = Some(self.server.list_directories);
+            }
+        }
+        self.default_host = Arc::new(VirtualHost {
+            root_folder: self.server.root_folder.clone(),
+            index: Some(self.server.index.clone()),
+            list_directories: Some(self.server.list_directories),
+            default: true,
+            ..VirtualHost::default()
+        });
+    }
+
+    /// Obtains the site that serves the value of the Host header
+    pub fn get_virtual_host(&self, host: &str) -> &Arc<VirtualHost> {
+        let host = normalize_host(host);
+        let best_match = self
+            .vhost
+            .iter()
+            .filter_map(|vhost| vhost.matches(&host).map(|specificity| (specificity, vhost)))
+            .max_by_key(|(specificity, _vhost)| *specificity);
+        match best_match {
+            Some((_specificity, vhost)) => vhost,
+            None => self.vhost.iter().find(|vhost| vhost.default).unwrap_or(&self.default_host),
+        }
+    }
+
+    /// Obtains the proxy rule that forwards the path, the longest matching prefix wins
+    pub fn get_proxy_rule(&self, path: &str) -> Option<&ProxyRule> {
+        self.proxy
+            .iter()
+            .filter(|rule| rule.matches(path))
+            .max_by_key(|rule| rule.prefix.trim_end_matches('/').len())
+    }
+
     /// Prints Current Config to stdout
     pub fn show(&self) {
         utils::log::log_custom(&"", self, Color::Magenta, true);
     }
+}
+
+/// Config shared by the whole server, replaced as a whole when it is reloaded
+///
+/// Each request keeps the config it started with, so a reload only affects new requests
+pub struct ConfigHandle {
+    current: ArcSwap<Config>,
+}
+
+impl ConfigHandle {
+    pub fn new(config: Config) -> ConfigHandle {
+        ConfigHandle {
+            current: ArcSwap::from_pointee(config),
+        }
+    }
+
+    /// Obtains the current config
+    pub fn get(&self) -> Arc<Config> {
+        self.current.load_full()
+    }
+
+    /// Loads the settings file again, the current config is kept if the new one is not valid
+    ///
+    /// Listeners, TLS certificates and workers are only changed by a restart, until then the
+    /// running values are kept
+    pub fn reload(&self) {
+        match Config::load() {
+            Ok(mut config) => {
+                let running = self.get();
+                if config.needs_restart(&running) {
+                    log_warning(&"Listener, TLS and worker changes are applied on the next restart");
+                }
+                config.keep_restart_values(&running);
+                config.check_root_folders();
+                self.current.store(Arc::new(config));
+                log_warning(&"Settings reloaded");
+            }
+            Err(error) => log_error(&format!("Settings not reloaded: {}", error)),
+        }
+    }
 
-    // ToDo funciona con la implementacion actual, WIP
-    /*
-    pub fn watch(&self) {
-        // Watch for settings change
-        thread::spawn(|| {
+    /// Reloads the config each time the settings file changes
+    pub fn watch(&'static self) {
+        thread::spawn(move || {
             // Create a channel to receive the events.
             let (tx, rx) = channel();
 
-            // Automatically select the best implementation for your platform.
-            // You can also access each implementation directly e.g. INotifyWatcher.
-            let mut watcher: RecommendedWatcher = Watcher::new(tx, Duration::from_secs(2)).unwrap();
-
-            // Add a path to be watched. All files and directories at that path and
-            // below will be monitored for changes.
-            watcher
-                .watch("Settings.toml", RecursiveMode::NonRecursive)
-                .unwrap();
-
-            // This is a simple loop, but you may want to use more complex logic here,
-            // for example to handle I/O.
-            loop {
-                match rx.recv() {
-                    Ok(DebouncedEvent::Write(_)) => {
-                        println!(" * Settings.toml written; consider restarting the program...");
-                        //Self = Settings::new();
-                        //refresh();
-                    }
+            let mut watcher: RecommendedWatcher = match Watcher::new(tx, Duration::from_secs(1)) {
+                Ok(value) => value,
+                Err(error) => {
+                    log_error(&error);
+                    return;
+                }
+            };
 
-                    Err(e) => println!("watch error: {:?}", e),
+            // Editors usually replace the file instead of writing it, so the folder is watched
+            if let Err(error) = watcher.watch(".", RecursiveMode::NonRecursive) {
+                log_error(&error);
+                return;
+            }
 
+            for event in rx {
+                match event {
+                    DebouncedEvent::Create(path) | DebouncedEvent::Write(path) | DebouncedEvent::Rename(_, path) => {
+                        if path.file_name().is_some_and(|name| name == SETTINGS_FILE) {
+                            self.reload();
+                        }
+                    }
+                    DebouncedEvent::Error(error, _path) => log_error(&error),
                     _ => {
                         // Ignore event
                     }
                 }
             }
         });
-    }*/
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vhost(name: &str, certificate: Option<&str>) -> Arc<VirtualHost> {
+        Arc::new(VirtualHost {
+            server_names: vec![name.to_owned()],
+            root_folder: format!("./{}/", name),
+            certificate: certificate.map(|value| format!("{}.pem", value)),
+            private_key: certificate.map(|value| format!("{}.key", value)),
+            ..VirtualHost::default()
+        })
+    }
+
+    #[test]
+    fn detects_changes_that_need_a_restart() {
+        let running = Config::load_for_tests();
+        let mut config = Config::load_for_tests();
+        config.server.root_folder = "./other/".to_owned();
+        config.server.list_directories = !running.server.list_directories;
+        config.debug.verbose = !running.debug.verbose;
+        assert!(!config.needs_restart(&running));
+
+        let changes: [fn(&mut Config); 5] = [
+            |config| config.server.port += 1,
+            |config| config.server.workers_per_thread += 1,
+            |config| config.tls.active = !config.tls.active,
+            |config| config.tls.certificate = "other.pem".to_owned(),
+            |config| config.vhost.push(vhost("example.com", Some("example"))),
+        ];
+        for change in changes {
+            let mut config = Config::load_for_tests();
+            change(&mut config);
+            assert!(config.needs_restart(&running));
+        }
+    }
+
+    #[test]
+    fn keeps_the_running_listeners_and_certificates() {
+        let mut running = Config::load_for_tests();
+        running.vhost = vec![vhost("a.com", Some("a")), vhost("b.com", None)];
+
+        let mut config = Config::load_for_tests();
+        config.server.port = running.server.port + 1;
+        config.tls.private_key = "other.key".to_owned();
+        config.vhost = vec![
+            vhost("a.com", Some("changed")),
+            vhost("b.com", Some("b")),
+            vhost("c.com", Some("c")),
+        ];
+        Arc::make_mut(&mut config.vhost[0]).root_folder = "./changed/".to_owned();
+        config.keep_restart_values(&running);
+
+        assert_eq!(config.server.port, running.server.port);
+        assert_eq!(config.tls.private_key, running.tls.private_key);
+        let certificates: Vec<Option<&str>> = config.vhost.iter().map(|host| host.certificate.as_deref()).collect();
+        assert_eq!(certificates, [Some("a.pem"), None, None]);
+        assert_eq!(config.vhost[0].private_key.as_deref(), Some("a.key"));
+        assert_eq!(config.vhost[2].private_key, None);
+        // Everything else is applied
+        assert_eq!(config.vhost[0].root_folder, "./changed/");
+        assert_eq!(config.vhost.len(), 3);
+    }
 }
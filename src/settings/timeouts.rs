@@ -1,14 +1,47 @@
 //! Timeout Settings
+use std::time::Duration;
+
 #[derive(Debug, Deserialize)]
 /// Contains Timeouts Releated Config
 pub struct Timeouts {
-    /// How much time will pass before the server consider the request as done
-    pub request_miliseconds: u32,
+    /// How much time the server will wait for the client to send more bytes of a request before
+    /// dropping it
+    pub request_miliseconds: u64,
+    /// How much time an idle persistent connection will be kept open waiting for the next request
+    pub keep_alive_miliseconds: u64,
+    /// How much time the active connections have to end once the server is asked to stop
+    pub shutdown_miliseconds: u64,
+    /// How much time the server will wait to connect to a proxy upstream
+    pub proxy_connect_miliseconds: u64,
+    /// How much time the server will wait for a proxy upstream to send more bytes of a response
+    pub proxy_read_miliseconds: u64,
+    /// How much time an idle connection to a proxy upstream is kept to be reused
+    pub proxy_keep_alive_miliseconds: u64,
 }
 
 impl Timeouts {
-    /// Converts miliseconds to nanoseconds, unsed creating Duratin type
-    pub fn get_nanoseconds(&self) -> u32 {
-        self.request_miliseconds * 1000000
+    /// Converts miliseconds to Duration
+    pub fn get_request_duration(&self) -> Duration {
+        Duration::from_millis(self.request_miliseconds)
+    }
+    /// Converts miliseconds to Duration
+    pub fn get_keep_alive_duration(&self) -> Duration {
+        Duration::from_millis(self.keep_alive_miliseconds)
+    }
+    /// Converts miliseconds to Duration
+    pub fn get_shutdown_duration(&self) -> Duration {
+        Duration::from_millis(self.shutdown_miliseconds)
+    }
+    /// Converts miliseconds to Duration
+    pub fn get_proxy_connect_duration(&self) -> Duration {
+        Duration::from_millis(self.proxy_connect_miliseconds)
+    }
+    /// Converts miliseconds to Duration
+    pub fn get_proxy_read_duration(&self) -> Duration {
+        Duration::from_millis(self.proxy_read_miliseconds)
+    }
+    /// Converts miliseconds to Duration
+    pub fn get_proxy_keep_alive_duration(&self) -> Duration {
+        Duration::from_millis(self.proxy_keep_alive_miliseconds)
     }
 }
@@ -0,0 +1,62 @@
+//! Tls Settings
+use crate::settings::server::{get_listen_addresses, Server};
+
+#[derive(Debug, Deserialize)]
+/// Contains HTTPS Releated Config
+pub struct Tls {
+    /// Define if the HTTPS listener will be started
+    pub active: bool,
+    /// Public HTTPS port, used for redirects and to listen on the host of `[Server]` when
+    /// `listen` is empty
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// 443
+    /// ```
+    ///
+    /// Will Listen on the default https port
+    pub port: u16,
+    /// Addresses the HTTPS listener will use, IPv4 or IPv6
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// ["0.0.0.0:443", "[::]:443"]
+    /// ```
+    #[serde(default)]
+    pub listen: Vec<String>,
+    /// Path to the PEM file with the certificate chain, used when no virtual host matches the
+    /// name sent by the client
+    pub certificate: String,
+    /// Path to the PEM file with the private key of the certificate
+    pub private_key: String,
+    /// Define if the plain listener will be started too, when false the server only speaks
+    /// HTTPS
+    pub serve_http: bool,
+    /// Define if requests on the plain listener will be redirected to HTTPS instead of served
+    pub redirect_http: bool,
+    /// Seconds browsers will remember to only use HTTPS, sent on HTTPS responses with
+    /// `Strict-Transport-Security`, 0 disables it
+    pub hsts_max_age: u64,
+    /// Define if the HSTS policy also applies to subdomains
+    pub hsts_include_subdomains: bool,
+}
+
+impl Tls {
+    /// Obtains the addresses the HTTPS listener will use
+    pub fn get_listen_addresses(&self, server: &Server) -> Vec<String> {
+        get_listen_addresses(&self.listen, &server.host, self.port)
+    }
+
+    /// Obtains the value of the `Strict-Transport-Security` header, empty when disabled
+    pub fn get_hsts(&self) -> String {
+        if self.hsts_max_age == 0 {
+            String::new()
+        } else if self.hsts_include_subdomains {
+            format!("max-age={}; includeSubDomains", self.hsts_max_age)
+        } else {
+            format!("max-age={}", self.hsts_max_age)
+        }
+    }
+}
@@ -0,0 +1,158 @@
+//! Virtual Host Settings
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+/// Contains the Config of a site served on the same server as others, selected with the Host
+/// header
+pub struct VirtualHost {
+    /// Host names served by this site, a leading `*.` matches any subdomain and a trailing `.*`
+    /// any top level domain
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// ["example.com", "*.example.com"]
+    /// ```
+    ///
+    /// Will serve `example.com`, `www.example.com` and `static.www.example.com`
+    #[serde(default)]
+    pub server_names: Vec<String>,
+    /// Wich directory will the site use as root
+    pub root_folder: String,
+    /// Files served when a directory is requested, the ones from `[Server]` when not set
+    #[serde(default)]
+    pub index: Option<Vec<String>>,
+    /// Defines if the site will list directory content, the value from `[Server]` when not set
+    #[serde(default)]
+    pub list_directories: Option<bool>,
+    /// Pages sent instead of the generated ones for each status code, relative to the root
+    /// folder
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// { 404 = "errors/404.html" }
+    /// ```
+    ///
+    /// Missing files will be answered with the contents of `errors/404.html`
+    #[serde(default)]
+    pub error_pages: HashMap<String, String>,
+    /// Path to the PEM file with the certificate chain sent to HTTPS clients asking for one of
+    /// the server names, the one from `[Tls]` when not set
+    #[serde(default)]
+    pub certificate: Option<String>,
+    /// Path to the PEM file with the private key of the certificate
+    #[serde(default)]
+    pub private_key: Option<String>,
+    /// Defines if this site will serve the requests that do not match any server name, when no
+    /// site is the default the `[Server]` settings are used
+    #[serde(default)]
+    pub default: bool,
+}
+
+impl VirtualHost {
+    /// Files served when a directory is requested
+    pub fn get_index(&self) -> &[String] {
+        match &self.index {
+            Some(value) => value,
+            None => &[],
+        }
+    }
+
+    /// Defines if the site will list directory content
+    pub fn lists_directories(&self) -> bool {
+        self.list_directories.unwrap_or(false)
+    }
+
+    /// Obtains the custom page for a status code
+    pub fn get_error_page(&self, status: i32) -> Option<&String> {
+        self.error_pages.get(&status.to_string())
+    }
+
+    /// Checks if the host is served by this site, returns how specific the matching name is so
+    /// exact names win over wildcards and longer wildcards over shorter ones
+    pub fn matches(&self, host: &str) -> Option<usize> {
+        self.server_names
+            .iter()
+            .filter_map(|name| {
+                let name = name.to_lowercase();
+                if name == host {
+                    Some(usize::MAX)
+                } else if (name.starts_with("*.") && host.ends_with(&name[1..]))
+                    || (name.ends_with(".*") && host.starts_with(&name[..name.len() - 1]))
+                {
+                    Some(name.len())
+                } else {
+                    None
+                }
+            })
+            .max()
+    }
+}
+
+/// Obtains the host name from the value of the Host header, without port and in lowercase
+pub fn normalize_host(host: &str) -> String {
+    let host = host.trim();
+    let name = if host.starts_with('[') {
+        // IPv6 literal
+        match host.find(']') {
+            Some(index) => &host[..=index],
+            None => host,
+        }
+    } else {
+        host.split(':').next().unwrap_or("")
+    };
+    name.trim_end_matches('.').to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vhost(names: &[&str]) -> VirtualHost {
+        VirtualHost {
+            server_names: names.iter().map(|name| (*name).to_owned()).collect(),
+            ..VirtualHost::default()
+        }
+    }
+
+    #[test]
+    fn matches_exact_names() {
+        let site = vhost(&["Example.com"]);
+        assert_eq!(site.matches("example.com"), Some(usize::MAX));
+        assert_eq!(site.matches("www.example.com"), None);
+        assert_eq!(site.matches("example.org"), None);
+    }
+
+    #[test]
+    fn matches_leading_wildcards() {
+        let site = vhost(&["*.example.com"]);
+        assert_eq!(site.matches("www.example.com"), Some("*.example.com".len()));
+        assert_eq!(site.matches("static.www.example.com"), Some("*.example.com".len()));
+        assert_eq!(site.matches("example.com"), None);
+        assert_eq!(site.matches("badexample.com"), None);
+    }
+
+    #[test]
+    fn matches_trailing_wildcards() {
+        let site = vhost(&["example.*"]);
+        assert_eq!(site.matches("example.org"), Some("example.*".len()));
+        assert_eq!(site.matches("example.co.uk"), Some("example.*".len()));
+        assert_eq!(site.matches("www.example.org"), None);
+    }
+
+    #[test]
+    fn prefers_the_most_specific_name() {
+        let site = vhost(&["*.com", "*.example.com", "www.example.com"]);
+        assert_eq!(site.matches("www.example.com"), Some(usize::MAX));
+        assert!(vhost(&["*.example.com"]).matches("a.example.com") > vhost(&["*.com"]).matches("a.example.com"));
+    }
+
+    #[test]
+    fn normalizes_hosts() {
+        assert_eq!(normalize_host(" Example.COM:8080 "), "example.com");
+        assert_eq!(normalize_host("example.com."), "example.com");
+        assert_eq!(normalize_host("[::1]:8080"), "[::1]");
+        assert_eq!(normalize_host(""), "");
+    }
+}
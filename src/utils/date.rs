@@ -0,0 +1,32 @@
+//! Http Dates
+//!
+//! [HTTP-date](https://tools.ietf.org/html/rfc7231#section-7.1.1.1) formatting and parsing
+use chrono::{DateTime, Utc};
+use std::time::SystemTime;
+
+/// Formats the given time as HTTP-date
+///
+/// # Example
+///
+/// ```
+/// Sun, 06 Nov 1994 08:49:37 GMT
+/// ```
+pub fn format_http_date(time: SystemTime) -> String {
+    let date: DateTime<Utc> = DateTime::from(time);
+    date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Parses an HTTP-date into seconds since the unix epoch
+pub fn parse_http_date(date: &str) -> Option<i64> {
+    match DateTime::parse_from_rfc2822(date.trim()) {
+        Ok(value) => Some(value.timestamp()),
+        Err(_err) => None,
+    }
+}
+
+/// Obtains the seconds since the unix epoch of the given time, HTTP-dates have no sub-second
+/// precision so this is the value that should be compared against them
+pub fn get_timestamp(time: SystemTime) -> i64 {
+    let date: DateTime<Utc> = DateTime::from(time);
+    date.timestamp()
+}
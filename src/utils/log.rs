@@ -1,17 +1,54 @@
 // Time
 extern crate chrono;
+use crate::request::request::Request;
+use crate::request_handlers::auth::get_user;
 use crate::utils::check_console_write;
+use crate::utils::log_file::{write_log, LogTarget};
 use crate::APP_CONFIG;
+use chrono::{DateTime, Local, SecondsFormat};
 use core::any::Any;
+use serde_json::{json, Map, Value};
+use std::cell::RefCell;
 use std::fmt::Debug;
-use std::io::Write;
+use std::io::{self, IsTerminal, Write};
+use std::time::Duration;
 use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 
-fn do_log_debug<T: Any + Debug>(tag: &str, data: &T, color: Color, intense: bool) {
+lazy_static! {
+    /// Colors are only written when the output is a terminal, `NO_COLOR` and `TERM=dumb` are
+    /// respected too
+    static ref COLOR_CHOICE: ColorChoice = if io::stdout().is_terminal() {
+        ColorChoice::Auto
+    } else {
+        ColorChoice::Never
+    };
+}
+
+thread_local! {
+    /// Id of the request the thread is serving, added to the JSON lines
+    static REQUEST_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Sets the request the lines logged from this thread belong to, `None` once it is answered
+pub fn set_request_id(id: Option<&str>) {
+    REQUEST_ID.with(|current| *current.borrow_mut() = id.map(str::to_owned));
+}
+
+fn do_log_debug<T: Any + Debug>(tag: &str, level: &str, data: &T, color: Color, intense: bool) {
     // https://en.wikipedia.org/wiki/ANSI_escape_code
-    if APP_CONFIG.debug.active {
-        if APP_CONFIG.debug.log_to_console {
-            let mut colored_stdout: StandardStream = StandardStream::stdout(ColorChoice::Always);
+    if APP_CONFIG.get().debug.active {
+        if APP_CONFIG.get().debug.format == "json" {
+            let line = get_json_line(tag, level, data);
+            if APP_CONFIG.get().debug.log_to_console {
+                check_console_write(io::stdout().lock().write_all(line.as_bytes()));
+            }
+            if APP_CONFIG.get().debug.log_to_file {
+                write_log(LogTarget::Debug, line);
+            }
+            return;
+        }
+        if APP_CONFIG.get().debug.log_to_console {
+            let mut colored_stdout: StandardStream = StandardStream::stdout(*COLOR_CHOICE);
             check_console_write(
                 colored_stdout.set_color(
                     ColorSpec::new()
@@ -37,30 +74,250 @@ fn do_log_debug<T: Any + Debug>(tag: &str, data: &T, color: Color, intense: bool
             );
             check_console_write(writeln!(&mut colored_stdout, ""));
         }
-        if APP_CONFIG.debug.log_to_file {
-            // Todo
+        if APP_CONFIG.get().debug.log_to_file {
+            write_log(LogTarget::Debug, format!("{}\t{}\n{:#?}\n", tag, chrono::Local::now(), data));
         }
     }
 }
 
 pub fn log_custom<T: Any + Debug>(tag: &str, data: &T, color: Color, intense: bool) {
-    do_log_debug(tag, data, color, intense);
+    do_log_debug(tag, "info", data, color, intense);
 }
 
 pub fn log_verbose<T: Any + Debug>(data: &T) {
-    if APP_CONFIG.debug.verbose {
-        do_log_debug(&"Verbose:", data, Color::Cyan, true);
+    if APP_CONFIG.get().debug.verbose {
+        do_log_debug("Verbose:", "verbose", data, Color::Cyan, true);
     }
 }
 
 pub fn log_warning<T: Any + Debug>(data: &T) {
-    if APP_CONFIG.debug.warning {
-        do_log_debug(&"Warning:", data, Color::Yellow, true);
+    if APP_CONFIG.get().debug.warning {
+        do_log_debug("Warning:", "warning", data, Color::Yellow, true);
     }
 }
 
 pub fn log_error<T: Any + Debug>(data: &T) {
-    if APP_CONFIG.debug.error {
-        do_log_debug(&"Error:", data, Color::Red, true);
+    if APP_CONFIG.get().debug.error {
+        do_log_debug("Error:", "error", data, Color::Red, true);
+    }
+}
+
+/// Builds a JSON log line, known types like requests and IO errors are logged with their own
+/// fields and the rest as their debug representation in `message`
+fn get_json_line<T: Any + Debug>(tag: &str, level: &str, data: &T) -> String {
+    let mut fields = Map::new();
+    fields.insert("timestamp".to_owned(), json!(Local::now().to_rfc3339_opts(SecondsFormat::Micros, false)));
+    fields.insert("level".to_owned(), json!(level));
+    if let Some(id) = REQUEST_ID.with(|current| current.borrow().clone()) {
+        fields.insert("request_id".to_owned(), json!(id));
+    }
+    // The levels already name the tag of the other lines
+    if level == "info" && !tag.is_empty() {
+        fields.insert("tag".to_owned(), json!(tag));
+    }
+
+    let any: &dyn Any = data;
+    if let Some(message) = any.downcast_ref::<&str>() {
+        fields.insert("message".to_owned(), json!(message));
+    } else if let Some(message) = any.downcast_ref::<String>() {
+        fields.insert("message".to_owned(), json!(message));
+    } else if let Some(error) = any.downcast_ref::<io::Error>() {
+        fields.insert("message".to_owned(), json!(error.to_string()));
+        fields.insert("error_kind".to_owned(), json!(format!("{:?}", error.kind())));
+    } else if let Some(request) = any.downcast_ref::<Request>() {
+        fields.insert("message".to_owned(), json!(format!("{} {}", request.method.to_str(), request.path)));
+        fields.insert("method".to_owned(), json!(request.method.to_str()));
+        fields.insert("path".to_owned(), json!(request.path));
+        fields.insert("query".to_owned(), json!(request.query));
+        fields.insert("host".to_owned(), json!(request.request_headers.host));
+        fields.insert("remote_ip".to_owned(), json!(request.other.remote_ip));
+        fields.insert("secure".to_owned(), json!(request.secure));
+        fields.insert("keep_alive".to_owned(), json!(request.keep_alive));
+    } else {
+        fields.insert("message".to_owned(), json!(format!("{:?}", data)));
+    }
+    Value::Object(fields).to_string() + "\n"
+}
+
+/// Writes a request to the access log in Common or Combined Log Format, or as a JSON object
+///
+/// `received` is when the request arrived and `duration` the time taken to answer it, combined
+/// lines end with it in miliseconds
+pub fn log_access(request: &Request, received: DateTime<Local>, duration: Duration) {
+    let config = APP_CONFIG.get();
+    let settings = &config.debug;
+    if settings.access_log.is_empty() {
+        return;
+    }
+    write_log(
+        LogTarget::Access,
+        get_access_line(request, received, duration, &settings.access_log_format),
+    );
+}
+
+/// Builds the access log line of a request in the given `access_log_format`
+fn get_access_line(request: &Request, received: DateTime<Local>, duration: Duration, format: &str) -> String {
+    let response = request.get_sent_response();
+    let remote = &request.other.remote_ip;
+    if format == "json" {
+        let headers = &request.request_headers;
+        let line = json!({
+            "timestamp": received.to_rfc3339_opts(SecondsFormat::Micros, false),
+            "request_id": request.id,
+            "remote_ip": remote,
+            "user": get_user(request),
+            "method": request.method.to_str(),
+            "path": request.path,
+            "query": request.query,
+            "request": request.get_request_line(),
+            "status": response.status,
+            "bytes": response.body_bytes,
+            "referer": headers.referer,
+            "user_agent": headers.user_agent,
+            "duration_ms": duration.as_millis() as u64,
+        });
+        return line.to_string() + "\n";
+    }
+
+    let user = get_user(request).map(|user| escape_log_value(&user));
+    let bytes = match response.body_bytes {
+        0 => "-".to_owned(),
+        value => value.to_string(),
+    };
+    let mut line = format!(
+        "{} - {} [{}] \"{}\" {} {}",
+        if remote.is_empty() { "-" } else { remote },
+        user.as_deref().unwrap_or("-"),
+        received.format("%d/%b/%Y:%H:%M:%S %z"),
+        escape_log_value(request.get_request_line()),
+        response.status,
+        bytes
+    );
+    if format == "combined" {
+        let headers = &request.request_headers;
+        line += &format!(
+            " \"{}\" \"{}\" {}",
+            or_dash(&escape_log_value(&headers.referer)),
+            or_dash(&escape_log_value(&headers.user_agent)),
+            duration.as_millis()
+        );
+    }
+    line.push('\n');
+    line
+}
+
+/// Escapes quotes, backslashes and control characters so values can not break the line
+fn escape_log_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            c if c == '"' || c == '\\' || c.is_control() => escaped.push_str(&format!("\\x{:02X}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Empty values are logged as `-`
+fn or_dash(value: &str) -> &str {
+    if value.is_empty() {
+        "-"
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request_handlers::test_client::parse;
+    use chrono::TimeZone;
+
+    fn received() -> DateTime<Local> {
+        Local.ymd(2024, 3, 9).and_hms(8, 5, 1)
+    }
+
+    #[test]
+    fn writes_common_and_combined_lines() {
+        let request = parse(
+            b"GET /a.txt HTTP/1.1\r\nAuthorization: Basic YWRtaW46c2VjcmV0\r\nUser-Agent: curl\r\n\r\n",
+        );
+        request.record_status(200);
+        request.record_body(1234);
+        let time = received().format("%d/%b/%Y:%H:%M:%S %z").to_string();
+        let duration = Duration::from_millis(15);
+
+        assert_eq!(
+            get_access_line(&request, received(), duration, "common"),
+            format!("127.0.0.1 - admin [{}] \"GET /a.txt HTTP/1.1\" 200 1234\n", time)
+        );
+        assert_eq!(
+            get_access_line(&request, received(), duration, "combined"),
+            format!("127.0.0.1 - admin [{}] \"GET /a.txt HTTP/1.1\" 200 1234 \"-\" \"curl\" 15\n", time)
+        );
+    }
+
+    #[test]
+    fn escapes_values_that_could_break_the_line() {
+        assert_eq!(escape_log_value("plain value"), "plain value");
+        assert_eq!(escape_log_value("a\"b\\c"), "a\\x22b\\x5Cc");
+        assert_eq!(escape_log_value("a\r\nb\t"), "a\\x0D\\x0Ab\\x09");
+
+        let request = parse(b"GET /\"x HTTP/1.1\r\nUser-Agent: a\"b\r\n\r\n");
+        let line = get_access_line(&request, received(), Duration::ZERO, "combined");
+        assert!(line.contains("\"GET /\\x22x HTTP/1.1\" 0 - \"-\" \"a\\x22b\" 0\n"), "{}", line);
+        assert_eq!(line.matches('\n').count(), 1);
+    }
+
+    /// Fields of a JSON line, which must be a single object ending the line
+    fn json_fields<T: Any + Debug>(tag: &str, level: &str, data: &T) -> Map<String, Value> {
+        let line = get_json_line(tag, level, data);
+        assert!(line.ends_with('\n'));
+        assert_eq!(line.matches('\n').count(), 1);
+        match serde_json::from_str(&line).unwrap() {
+            Value::Object(fields) => fields,
+            value => panic!("Not an object: {}", value),
+        }
+    }
+
+    #[test]
+    fn writes_json_lines_with_typed_fields() {
+        let fields = json_fields("Error:", "error", &"multi\nline");
+        assert_eq!(fields["level"], "error");
+        assert_eq!(fields["message"], "multi\nline");
+        assert!(fields["timestamp"].is_string());
+        assert!(!fields.contains_key("tag"));
+        assert!(!fields.contains_key("request_id"));
+
+        let fields = json_fields("Config", "info", &"loaded".to_owned());
+        assert_eq!(fields["tag"], "Config");
+        assert_eq!(fields["message"], "loaded");
+
+        let error = io::Error::new(io::ErrorKind::TimedOut, "too slow");
+        let fields = json_fields("Error:", "error", &error);
+        assert_eq!(fields["message"], "too slow");
+        assert_eq!(fields["error_kind"], "TimedOut");
+
+        let fields = json_fields("Error:", "error", &Some(1));
+        assert_eq!(fields["message"], "Some(1)");
+    }
+
+    #[test]
+    fn writes_requests_and_their_id_on_json_lines() {
+        let request = parse(b"POST /up/?a=1 HTTP/1.1\r\nHost: example.com\r\n\r\n");
+        set_request_id(Some(&request.id));
+        let fields = json_fields("Request", "verbose", &request);
+        set_request_id(None);
+
+        assert_eq!(fields["request_id"], request.id.as_str());
+        assert_eq!(fields["message"], "POST /up/");
+        assert_eq!(fields["method"], "POST");
+        assert_eq!(fields["path"], "/up/");
+        assert_eq!(fields["query"], "a=1");
+        assert_eq!(fields["host"], "example.com");
+        assert_eq!(fields["remote_ip"], "127.0.0.1");
+        assert_eq!(fields["secure"], false);
+
+        assert!(!json_fields("", "verbose", &"after").contains_key("request_id"));
     }
 }
@@ -0,0 +1,345 @@
+//! Log Files
+//!
+//! Lines are queued and written by their own thread so requests never wait for the disk, files
+//! are rotated by size and time keeping a limited number of old copies
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+
+use crate::settings::debug::Debug;
+use crate::APP_CONFIG;
+
+/// Maximum number of lines waiting to be written, new lines are dropped while it is full
+const QUEUE_SIZE: usize = 10000;
+
+/// Minimum time between the console lines that report log file errors
+const ERROR_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Time waited before trying again a rotation that failed, the lines keep going to the same file
+const ROTATE_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Number of lines dropped because the queue was full
+static DROPPED_LINES: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static! {
+    static ref QUEUE: Mutex<SyncSender<Message>> = Mutex::new(start_writer());
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// File a line is written to
+pub enum LogTarget {
+    /// `log_file`
+    Debug,
+    /// `access_log`
+    Access,
+}
+
+enum Message {
+    Line(LogTarget, String),
+    /// Answers once every line queued before is written
+    Flush(SyncSender<()>),
+}
+
+/// Queues a line, it is dropped if the writer can not keep up
+pub fn write_log(target: LogTarget, line: String) {
+    let sender = match QUEUE.lock() {
+        Ok(value) => value.clone(),
+        Err(poisoned) => poisoned.into_inner().clone(),
+    };
+    if let Err(TrySendError::Full(_message)) = sender.try_send(Message::Line(target, line)) {
+        DROPPED_LINES.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Waits until the queued lines are written
+pub fn flush_logs() {
+    let sender = match QUEUE.lock() {
+        Ok(value) => value.clone(),
+        Err(poisoned) => poisoned.into_inner().clone(),
+    };
+    let (done, wait) = sync_channel(1);
+    if sender.send(Message::Flush(done)).is_ok() {
+        wait.recv().ok();
+    }
+}
+
+fn start_writer() -> SyncSender<Message> {
+    let (sender, receiver) = sync_channel(QUEUE_SIZE);
+    thread::spawn(move || write_lines(receiver));
+    sender
+}
+
+/// Writes the queued lines, flushing the files each time the queue is empty
+fn write_lines(receiver: Receiver<Message>) {
+    let mut writer = Writer::default();
+    while let Ok(message) = receiver.recv() {
+        writer.handle(message);
+        while let Ok(message) = receiver.try_recv() {
+            writer.handle(message);
+        }
+        writer.flush();
+    }
+}
+
+#[derive(Default)]
+/// Open log files, they are opened again when their path changes on a reload
+struct Writer {
+    debug_file: Option<RotatingFile>,
+    access_file: Option<RotatingFile>,
+    errors: ErrorReport,
+}
+
+impl Writer {
+    fn handle(&mut self, message: Message) {
+        let (target, line) = match message {
+            Message::Line(target, line) => (target, line),
+            Message::Flush(done) => {
+                self.flush();
+                done.send(()).ok();
+                return;
+            }
+        };
+
+        let config = APP_CONFIG.get();
+        let settings = &config.debug;
+        let (file, path) = match target {
+            LogTarget::Debug => (&mut self.debug_file, &settings.log_file),
+            LogTarget::Access => (&mut self.access_file, &settings.access_log),
+        };
+        if path.is_empty() {
+            return;
+        }
+        if file.as_ref().is_none_or(|file| file.path != Path::new(path)) {
+            *file = match RotatingFile::open(PathBuf::from(path), &settings.rotate_every) {
+                Ok(value) => Some(value),
+                Err(error) => {
+                    self.errors.report(format!("Could not open log file {}: {}", path, error));
+                    None
+                }
+            };
+        }
+
+        if let Some(file) = file {
+            let dropped = DROPPED_LINES.swap(0, Ordering::Relaxed);
+            if dropped > 0 {
+                file.write_line(&format!("{} log lines were dropped\n", dropped), settings, &mut self.errors);
+            }
+            file.write_line(&line, settings, &mut self.errors);
+        }
+    }
+
+    fn flush(&mut self) {
+        for file in self.debug_file.iter_mut().chain(self.access_file.iter_mut()) {
+            file.flush();
+        }
+    }
+}
+
+#[derive(Default)]
+/// Errors of the log files, logging them would queue more lines for the failing file so they
+/// are printed on the console instead, at most once per interval
+struct ErrorReport {
+    /// Errors not printed yet
+    count: usize,
+    last_printed: Option<Instant>,
+}
+
+impl ErrorReport {
+    fn report(&mut self, message: String) {
+        self.count += 1;
+        if self.last_printed.is_some_and(|time| time.elapsed() < ERROR_REPORT_INTERVAL) {
+            return;
+        }
+        if self.count > 1 {
+            eprintln!("{} ({} log file errors since the last report)", message, self.count);
+        } else {
+            eprintln!("{}", message);
+        }
+        self.count = 0;
+        self.last_printed = Some(Instant::now());
+    }
+}
+
+/// Log file that is rotated when it grows too big or its period ends
+struct RotatingFile {
+    path: PathBuf,
+    file: io::BufWriter<File>,
+    size: u64,
+    /// Hour or day of the lines in the file
+    period: String,
+    /// Rotation is not tried again until then after failing
+    retry_after: Option<Instant>,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, rotate_every: &str) -> io::Result<RotatingFile> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let metadata = file.metadata()?;
+        let modified: DateTime<Local> = match metadata.modified() {
+            Ok(value) => value.into(),
+            Err(_err) => Local::now(),
+        };
+        Ok(RotatingFile {
+            path,
+            file: io::BufWriter::new(file),
+            size: metadata.len(),
+            period: get_period(&modified, rotate_every),
+            retry_after: None,
+        })
+    }
+
+    fn write_line(&mut self, line: &str, settings: &Debug, errors: &mut ErrorReport) {
+        let period = get_period(&Local::now(), &settings.rotate_every);
+        let too_big = settings.rotate_bytes > 0
+            && self.size > 0
+            && self.size + line.len() as u64 > settings.rotate_bytes;
+        let waiting = self.retry_after.is_some_and(|time| Instant::now() < time);
+        if (too_big || period != self.period) && !waiting {
+            match self.rotate(settings.keep_files) {
+                Ok(_) => self.retry_after = None,
+                Err(error) => {
+                    errors.report(format!("Could not rotate log file {}: {}", self.path.display(), error));
+                    self.retry_after = Some(Instant::now() + ROTATE_RETRY_INTERVAL);
+                }
+            }
+            self.period = period;
+        }
+
+        match self.file.write_all(line.as_bytes()) {
+            Ok(_) => self.size += line.len() as u64,
+            Err(error) => errors.report(format!("Could not write log file {}: {}", self.path.display(), error)),
+        }
+    }
+
+    fn flush(&mut self) {
+        self.file.flush().ok();
+    }
+
+    /// Renames `file` to `file.1`, `file.1` to `file.2`... removing the copies over `keep_files`
+    fn rotate(&mut self, keep_files: usize) -> io::Result<()> {
+        self.file.flush()?;
+        let rotated = |index: usize| -> PathBuf {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", index));
+            PathBuf::from(name)
+        };
+
+        if keep_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            // Windows does not replace existing files on rename
+            fs::remove_file(rotated(keep_files)).ok();
+            for index in (1..keep_files).rev() {
+                if rotated(index).exists() {
+                    fs::rename(rotated(index), rotated(index + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated(1))?;
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.file = io::BufWriter::new(file);
+        self.size = 0;
+        Ok(())
+    }
+}
+
+/// Obtains the period of a moment, files are rotated when it changes
+fn get_period(time: &DateTime<Local>, rotate_every: &str) -> String {
+    match rotate_every {
+        "hour" => time.format("%Y%m%d%H").to_string(),
+        "day" => time.format("%Y%m%d").to_string(),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::settings::Config;
+
+    fn settings(rotate_bytes: u64, keep_files: usize) -> Debug {
+        let mut settings = Config::load_for_tests().debug;
+        settings.rotate_bytes = rotate_bytes;
+        settings.rotate_every = String::new();
+        settings.keep_files = keep_files;
+        settings
+    }
+
+    fn read(directory: &Path, name: &str) -> Option<String> {
+        fs::read_to_string(directory.join(name)).ok()
+    }
+
+    #[test]
+    fn rotates_by_size_keeping_the_newest_files() {
+        let directory = std::env::temp_dir().join(format!("akira-log-size-{}", std::process::id()));
+        fs::remove_dir_all(&directory).ok();
+        let settings = settings(10, 2);
+        let mut errors = ErrorReport::default();
+        let mut file = RotatingFile::open(directory.join("access.log"), "").unwrap();
+        for line in ["one\n", "two\n", "three\n", "four\n", "five\n", "six\n"] {
+            file.write_line(line, &settings, &mut errors);
+        }
+        file.flush();
+
+        assert_eq!(read(&directory, "access.log").as_deref(), Some("six\n"));
+        assert_eq!(read(&directory, "access.log.1").as_deref(), Some("four\nfive\n"));
+        assert_eq!(read(&directory, "access.log.2").as_deref(), Some("three\n"));
+        assert_eq!(read(&directory, "access.log.3"), None);
+        assert_eq!(errors.count, 0);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn rotates_when_the_period_ends() {
+        let directory = std::env::temp_dir().join(format!("akira-log-time-{}", std::process::id()));
+        fs::remove_dir_all(&directory).ok();
+        let mut settings = settings(0, 1);
+        settings.rotate_every = "day".to_owned();
+        let mut errors = ErrorReport::default();
+        let mut file = RotatingFile::open(directory.join("access.log"), "day").unwrap();
+        file.write_line("today\n", &settings, &mut errors);
+        file.period = "19700101".to_owned();
+        file.write_line("tomorrow\n", &settings, &mut errors);
+        file.write_line("again\n", &settings, &mut errors);
+        file.flush();
+
+        assert_eq!(read(&directory, "access.log").as_deref(), Some("tomorrow\nagain\n"));
+        assert_eq!(read(&directory, "access.log.1").as_deref(), Some("today\n"));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn waits_before_retrying_a_failed_rotation() {
+        let directory = std::env::temp_dir().join(format!("akira-log-retry-{}", std::process::id()));
+        fs::remove_dir_all(&directory).ok();
+        let settings = settings(4, 1);
+        let mut errors = ErrorReport::default();
+        let mut file = RotatingFile::open(directory.join("access.log"), "").unwrap();
+        file.write_line("one\n", &settings, &mut errors);
+        // A directory where the rotated file goes makes the rename fail
+        fs::create_dir_all(directory.join("access.log.1").join("busy")).unwrap();
+        file.write_line("two\n", &settings, &mut errors);
+        assert!(file.retry_after.is_some());
+        file.write_line("three\n", &settings, &mut errors);
+        file.flush();
+
+        assert_eq!(read(&directory, "access.log").as_deref(), Some("one\ntwo\nthree\n"));
+        assert_eq!(errors.count, 0);
+        assert!(errors.last_printed.is_some());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -2,9 +2,20 @@
 use crate::utils::log::log_error;
 use std::io;
 
+pub mod date;
 pub mod log;
+pub mod log_file;
 
-pub fn check_stream_write(result: io::Result<usize>) {
+/// Longest prefix of `value` that fits in `max_bytes` without splitting a character
+pub fn truncate_str(value: &str, max_bytes: usize) -> &str {
+    let mut end = value.len().min(max_bytes);
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    &value[..end]
+}
+
+pub fn check_stream_write(result: io::Result<()>) {
     match result {
         Err(err) => {
             log_error(&err);